@@ -0,0 +1,83 @@
+//! Compares how fast `MooMachine::tick`, with sparse and dense registers, and a
+//! `CompiledMachine` run the same programs.
+//! Run with `cargo bench`.
+
+use std::time::{Duration, Instant};
+
+use moofloom::moo::parse_program_from_string;
+use moofloom::program::Program;
+use moofloom::vm::{CompiledMachine, CompiledProgram, MooMachine, Status};
+
+const PROGRAMS: [(&str, &str); 3] = [
+    (
+        "fibonacci",
+        "load 0u R0; load 1u R1; load 1000000u R2;
+         1: uadd R0 R1 R3; load R1 R0; load R3 R1; usub R2 1u R2; ucmp R2 0u; jgre 1b;",
+    ),
+    (
+        "float sum",
+        "load 0f R0; load 1000000i R1;
+         1: fadd R0 0.5f R0; fmul R0 0.999f R0; isub R1 1i R1; jineg R1 2f; jump 1b; 2: load R0 R2;",
+    ),
+    (
+        "collatz",
+        "load 0u R9;
+         1: uadd R9 1u R9; load R9 R0; ucmp R9 20000u; jgre 4f;
+         2: ucmp R0 1u; jeq 1b; udiv R0 2u R1; umul R1 2u R2; ucmp R0 R2; jeq 3f;
+            umul R0 3u R0; uadd R0 1u R0; jump 2b;
+         3: load R1 R0; jump 2b;
+         4: load R9 R0;",
+    ),
+];
+
+fn time<F: FnMut() -> u64>(mut run: F) -> (Duration, u64) {
+    let start = Instant::now();
+    let ticks = run();
+    (start.elapsed(), ticks)
+}
+
+fn interpret(program: &Program, register_count: Option<u64>) -> u64 {
+    let mut machine = MooMachine::new(program.clone(), Vec::new(), Vec::new());
+    if let Some(count) = register_count {
+        machine = machine.with_register_count(count).unwrap();
+    }
+    let mut ticks = 0;
+    while machine.tick().unwrap() != Status::Halted {
+        ticks += 1;
+    }
+    ticks
+}
+
+fn run_compiled(program: &Program) -> u64 {
+    let compiled = CompiledProgram::new(program).unwrap();
+    let mut machine = CompiledMachine::new(compiled, Vec::new(), Vec::new());
+    let mut ticks = 0;
+    while machine.tick().unwrap() != Status::Halted {
+        ticks += 1;
+    }
+    ticks
+}
+
+fn main() {
+    println!(
+        "{:<12} {:>12} {:>14} {:>14} {:>14} {:>8}",
+        "program", "ticks", "tick", "dense tick", "compiled", "speedup"
+    );
+    for &(name, source) in &PROGRAMS {
+        let program = parse_program_from_string(source).unwrap();
+        let (interpreted, ticks) = time(|| interpret(&program, None));
+        let (dense, dense_ticks) = time(|| interpret(&program, Some(16)));
+        let (compiled, compiled_ticks) = time(|| run_compiled(&program));
+        assert_eq!(ticks, dense_ticks);
+        assert_eq!(ticks, compiled_ticks);
+        println!(
+            "{:<12} {:>12} {:>14.1?} {:>14.1?} {:>14.1?} {:>7.1}x",
+            name,
+            ticks,
+            interpreted,
+            dense,
+            compiled,
+            interpreted.as_secs_f64() / compiled.as_secs_f64(),
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::BTreeSet;
+
+use super::{BasicBlock, Cfg, Loop};
+use crate::moo::parse_program_from_string;
+
+const COUNT: &str = "load 0u R0;
+loop: ucmp R0 5u;
+jeq done;
+uadd R0 1u R0;
+jump loop;
+load 9u R1;
+done: load R0 O0;
+";
+
+#[test]
+fn blocks_test() {
+    let program = parse_program_from_string(COUNT).unwrap();
+    let cfg = Cfg::new(&program);
+    let starts: Vec<_> = cfg.blocks().iter().map(|block| (block.start, block.end)).collect();
+    assert_eq!(starts, vec![(0, 1), (1, 3), (3, 5), (5, 6), (6, 7)]);
+    assert_eq!(cfg.block_at(4), 2);
+    assert_eq!(
+        cfg.blocks()[1],
+        BasicBlock {
+            start: 1,
+            end: 3,
+            successors: vec![4, 2],
+            predecessors: vec![0, 2],
+            halts: false,
+            external: false,
+        }
+    );
+    // The jump only goes to its target.
+    assert_eq!(cfg.blocks()[2].successors, vec![1]);
+    assert!(cfg.blocks()[4].halts);
+    assert!(cfg.blocks()[4].successors.is_empty());
+}
+
+#[test]
+fn unreachable_test() {
+    let program = parse_program_from_string(COUNT).unwrap();
+    let cfg = Cfg::new(&program);
+    assert_eq!(cfg.unreachable(), vec![3]);
+    assert_eq!(cfg.reachable(), vec![true, true, true, false, true]);
+    assert_eq!(cfg.reverse_postorder(), vec![0, 1, 2, 4]);
+}
+
+#[test]
+fn dominators_test() {
+    let program = parse_program_from_string(COUNT).unwrap();
+    let dominators = Cfg::new(&program).dominators();
+    assert_eq!(dominators.immediate(0), None);
+    assert_eq!(dominators.immediate(1), Some(0));
+    assert_eq!(dominators.immediate(2), Some(1));
+    assert_eq!(dominators.immediate(4), Some(1));
+    assert_eq!(dominators.immediate(3), None);
+    assert!(dominators.dominates(1, 2));
+    assert!(dominators.dominates(2, 2));
+    assert!(!dominators.dominates(2, 4));
+    assert!(!dominators.dominates(3, 3));
+}
+
+#[test]
+fn diamond_dominators_test() {
+    let program = parse_program_from_string(
+        "icmp I0 0i; jless 1f; load 1i R0; jump 2f; 1: load -1i R0; 2: load R0 O0;",
+    )
+    .unwrap();
+    let cfg = Cfg::new(&program);
+    assert_eq!(cfg.blocks().len(), 4);
+    let dominators = cfg.dominators();
+    // Neither side of the branch dominates where they join.
+    assert_eq!(dominators.immediate(3), Some(0));
+    assert!(cfg.loops().is_empty());
+}
+
+#[test]
+fn loops_test() {
+    let program = parse_program_from_string(COUNT).unwrap();
+    let loops = Cfg::new(&program).loops();
+    assert_eq!(
+        loops,
+        vec![Loop {
+            header: 1,
+            body: [1, 2].iter().copied().collect::<BTreeSet<_>>(),
+        }]
+    );
+}
+
+#[test]
+fn nested_loops_test() {
+    let program = parse_program_from_string(
+        "1: load I0 R0; 2: usub R0 1u R0; ucmp R0 0u; jgre 2b; load I1 R1; ucmp R1 0u; jgre 1b;",
+    )
+    .unwrap();
+    let cfg = Cfg::new(&program);
+    let loops = cfg.loops();
+    assert_eq!(loops.len(), 2);
+    assert_eq!(loops[0].header, 0);
+    assert_eq!(loops[0].body.len(), cfg.blocks().len());
+    assert_eq!(loops[1].header, 1);
+    assert_eq!(loops[1].body, [1].iter().copied().collect());
+}
+
+#[test]
+fn self_jump_test() {
+    let program = parse_program_from_string("1: jump 1b;").unwrap();
+    let cfg = Cfg::new(&program);
+    assert_eq!(cfg.blocks()[0].successors, vec![0]);
+    assert!(!cfg.blocks()[0].halts);
+    assert_eq!(cfg.loops()[0].header, 0);
+}
+
+#[test]
+fn empty_program_test() {
+    let program = parse_program_from_string("").unwrap();
+    let cfg = Cfg::new(&program);
+    assert!(cfg.blocks().is_empty());
+    assert!(cfg.unreachable().is_empty());
+    assert!(cfg.loops().is_empty());
+}
+
+#[test]
+fn dot_test() {
+    let program = parse_program_from_string("1: ucmp R0 1u; jless 1b; jump 2f; load 1u R0; 2: load R0 O0;").unwrap();
+    let dot = Cfg::new(&program).to_dot(&program);
+    assert_eq!(
+        dot,
+        "digraph cfg {
+    node [shape=box, fontname=\"monospace\"];
+    b0 [label=\"0: ucmp R0 1u\\l1: jless 1@0\\l\"];
+    b1 [label=\"2: jump 2@4\\l\"];
+    b2 [label=\"3: load 1u R0\\l\", style=dashed];
+    b3 [label=\"4: load R0 O0\\l\"];
+    b0 -> b0;
+    b0 -> b1;
+    b1 -> b3;
+    b2 -> b3;
+}
+"
+    );
+}
+
+#[test]
+fn live_after_test() {
+    let program = parse_program_from_string(COUNT).unwrap();
+    let live = Cfg::new(&program).live_after(&program);
+    let set = |registers: &[u64]| registers.iter().copied().collect::<BTreeSet<_>>();
+    assert_eq!(live[0], set(&[0]));
+    assert_eq!(live[3], set(&[0]));
+    // R1 is never read and nothing is live once the output is written.
+    assert_eq!(live[5], set(&[0]));
+    assert_eq!(live[6], set(&[]));
+}
+
+#[test]
+fn external_jump_test() {
+    // `done` is defined by another program this one gets linked with.
+    let program = parse_program_from_string("load 1u R0; load 2u R1; jump done; load R1 O0;").unwrap();
+    let cfg = Cfg::new(&program);
+    assert!(cfg.blocks()[0].external);
+    assert!(cfg.blocks()[0].successors.is_empty());
+    let live = cfg.live_after(&program);
+    assert_eq!(live[1], [0, 1].iter().copied().collect());
+}
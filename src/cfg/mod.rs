@@ -0,0 +1,413 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::program::Program;
+use crate::vm::{Command, Param};
+
+#[cfg(test)]
+mod cfg_test;
+
+/// Index of a block in `Cfg::blocks`.
+pub type BlockId = usize;
+
+/// A run of instructions that is only entered at its first instruction and only
+/// left after its last one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BasicBlock {
+    pub start: u64,
+    /// The address after the last instruction of the block.
+    pub end: u64,
+    pub successors: Vec<BlockId>,
+    pub predecessors: Vec<BlockId>,
+    /// Whether the program can halt after this block by running past its end.
+    pub halts: bool,
+    /// Whether the block can jump to a label the program doesn't define, which
+    /// another program defines once they are linked.
+    pub external: bool,
+}
+
+/// The control flow graph of a `Program`.
+///
+/// Blocks start at address 0, at every labelled address and after every jump, and
+/// are numbered in address order so the block at address 0 is the entry. Blocks at
+/// exported labels are entries too, as other programs can jump to them once linked.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cfg {
+    blocks: Vec<BasicBlock>,
+    block_of: Vec<BlockId>,
+    entries: Vec<BlockId>,
+}
+
+impl Cfg {
+    pub fn new(program: &Program) -> Self {
+        let length = program.len() as u64;
+        let mut leaders = BTreeSet::new();
+        if length > 0 {
+            leaders.insert(0);
+        }
+        leaders.extend(program.labels().values().copied().filter(|&address| address < length));
+        for (address, command) in program.commands().iter().enumerate() {
+            if command.label().is_some() && address as u64 + 1 < length {
+                leaders.insert(address as u64 + 1);
+            }
+        }
+
+        let leaders: Vec<u64> = leaders.into_iter().collect();
+        let mut block_of = vec![0; length as usize];
+        let mut blocks: Vec<BasicBlock> = leaders
+            .iter()
+            .enumerate()
+            .map(|(id, &start)| {
+                let end = leaders.get(id + 1).copied().unwrap_or(length);
+                for address in start..end {
+                    block_of[address as usize] = id;
+                }
+                BasicBlock {
+                    start,
+                    end,
+                    successors: Vec::new(),
+                    predecessors: Vec::new(),
+                    halts: false,
+                    external: false,
+                }
+            })
+            .collect();
+
+        for id in 0..blocks.len() {
+            let last = &program[blocks[id].end as usize - 1];
+            let mut targets = Vec::new();
+            if let Some(label) = last.label() {
+                match program.labels().get(label) {
+                    Some(&address) => targets.push(address),
+                    None => blocks[id].external = true,
+                }
+            }
+            if !matches!(last, Command::Jump(_)) {
+                targets.push(blocks[id].end);
+            }
+            for target in targets {
+                if target >= length {
+                    blocks[id].halts = true;
+                    continue;
+                }
+                let successor = block_of[target as usize];
+                if !blocks[id].successors.contains(&successor) {
+                    blocks[id].successors.push(successor);
+                    blocks[successor].predecessors.push(id);
+                }
+            }
+        }
+        let mut entries: Vec<BlockId> = program
+            .exports()
+            .iter()
+            .filter_map(|label| program.labels().get(label))
+            .filter(|&&address| address < length)
+            .map(|&address| block_of[address as usize])
+            .collect();
+        if length > 0 {
+            entries.push(0);
+        }
+        entries.sort_unstable();
+        entries.dedup();
+        Cfg {
+            blocks,
+            block_of,
+            entries,
+        }
+    }
+
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    /// The block the instruction at `address` is in.
+    pub fn block_at(&self, address: u64) -> BlockId {
+        self.block_of[address as usize]
+    }
+
+    /// The blocks the program can be entered at, in order.
+    pub fn entries(&self) -> &[BlockId] {
+        &self.entries
+    }
+
+    /// Which blocks can be reached from an entry, by block.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack = self.entries.clone();
+        while let Some(id) = stack.pop() {
+            if !reachable[id] {
+                reachable[id] = true;
+                stack.extend(&self.blocks[id].successors);
+            }
+        }
+        reachable
+    }
+
+    /// The blocks that can't be reached from any entry.
+    pub fn unreachable(&self) -> Vec<BlockId> {
+        let reachable = self.reachable();
+        (0..self.blocks.len()).filter(|&id| !reachable[id]).collect()
+    }
+
+    /// The reachable blocks in reverse postorder, which puts every block before its
+    /// successors except along back edges.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut order = self.postorder(&self.entries);
+        order.reverse();
+        order
+    }
+
+    fn postorder(&self, roots: &[BlockId]) -> Vec<BlockId> {
+        let mut order = Vec::new();
+        let mut visited = vec![false; self.blocks.len()];
+        for &root in roots {
+            if visited[root] {
+                continue;
+            }
+            // Each entry is a block and how many of its successors have been visited.
+            let mut stack = vec![(root, 0)];
+            visited[root] = true;
+            while let Some(&mut (id, ref mut next)) = stack.last_mut() {
+                match self.blocks[id].successors.get(*next) {
+                    Some(&successor) => {
+                        *next += 1;
+                        if !visited[successor] {
+                            visited[successor] = true;
+                            stack.push((successor, 0));
+                        }
+                    }
+                    None => {
+                        order.push(id);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+        order
+    }
+
+    /// Finds the dominators of every block reachable from address 0 with the
+    /// algorithm from "A Simple, Fast Dominance Algorithm" by Cooper, Harvey and Kennedy.
+    pub fn dominators(&self) -> Dominators {
+        let roots = if self.blocks.is_empty() { Vec::new() } else { vec![0] };
+        let mut order = self.postorder(&roots);
+        order.reverse();
+        let mut position = vec![usize::MAX; self.blocks.len()];
+        for (index, &id) in order.iter().enumerate() {
+            position[id] = index;
+        }
+        let mut immediate: Vec<Option<BlockId>> = vec![None; self.blocks.len()];
+        if let Some(&entry) = order.first() {
+            immediate[entry] = Some(entry);
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &id in order.iter().skip(1) {
+                let mut new = None;
+                for &predecessor in &self.blocks[id].predecessors {
+                    if immediate[predecessor].is_none() {
+                        continue;
+                    }
+                    new = Some(match new {
+                        None => predecessor,
+                        Some(new) => intersect(&immediate, &position, predecessor, new),
+                    });
+                }
+                if new != immediate[id] {
+                    immediate[id] = new;
+                    changed = true;
+                }
+            }
+        }
+        if let Some(&entry) = order.first() {
+            immediate[entry] = None;
+        }
+        Dominators { immediate }
+    }
+
+    /// The natural loops of the program, ordered by their headers. Back edges to
+    /// the same header are merged into one loop.
+    pub fn loops(&self) -> Vec<Loop> {
+        let dominators = self.dominators();
+        let mut loops: Vec<Loop> = Vec::new();
+        for (tail, block) in self.blocks.iter().enumerate() {
+            for &header in &block.successors {
+                if !dominators.dominates(header, tail) {
+                    continue;
+                }
+                let index = match loops.iter().position(|found| found.header == header) {
+                    Some(index) => index,
+                    None => {
+                        loops.push(Loop {
+                            header,
+                            body: [header].iter().copied().collect(),
+                        });
+                        loops.len() - 1
+                    }
+                };
+                let body = &mut loops[index].body;
+                let mut stack = vec![tail];
+                while let Some(id) = stack.pop() {
+                    if body.insert(id) {
+                        stack.extend(&self.blocks[id].predecessors);
+                    }
+                }
+            }
+        }
+        loops.sort_by_key(|found| found.header);
+        loops
+    }
+
+    /// The registers that may be read before they are written again after each
+    /// instruction of `program`, by address. Registers aren't read once the program
+    /// halts, but every register the program uses may be read by another program it
+    /// jumps to.
+    pub fn live_after(&self, program: &Program) -> Vec<BTreeSet<u64>> {
+        let used: BTreeSet<u64> = program
+            .commands()
+            .iter()
+            .flat_map(Command::params)
+            .filter_map(|param| match param {
+                Param::Register(register) => Some(register),
+                _ => None,
+            })
+            .collect();
+        let live_out = |live_in: &[BTreeSet<u64>], id: BlockId| {
+            let block = &self.blocks[id];
+            let mut live: BTreeSet<u64> = block
+                .successors
+                .iter()
+                .flat_map(|&successor| live_in[successor].iter().copied())
+                .collect();
+            if block.external {
+                live.extend(&used);
+            }
+            live
+        };
+
+        let mut live_in = vec![BTreeSet::new(); self.blocks.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for id in (0..self.blocks.len()).rev() {
+                let block = &self.blocks[id];
+                let mut live = live_out(&live_in, id);
+                for address in (block.start..block.end).rev() {
+                    update_liveness(&program[address as usize], &mut live);
+                }
+                if live != live_in[id] {
+                    live_in[id] = live;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut live_after = vec![BTreeSet::new(); program.len()];
+        for (id, block) in self.blocks.iter().enumerate() {
+            let mut live = live_out(&live_in, id);
+            for address in (block.start..block.end).rev() {
+                live_after[address as usize] = live.clone();
+                update_liveness(&program[address as usize], &mut live);
+            }
+        }
+        live_after
+    }
+
+    /// Writes the graph in graphviz's DOT language. Unreachable blocks are dashed.
+    pub fn to_dot(&self, program: &Program) -> String {
+        let reachable = self.reachable();
+        let mut dot = String::new();
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for (id, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for address in block.start..block.end {
+                write!(label, "{}: {}\\l", address, escape(&program[address as usize].to_string())).unwrap();
+            }
+            let style = if reachable[id] { "" } else { ", style=dashed" };
+            writeln!(dot, "    b{} [label=\"{}\"{}];", id, label, style).unwrap();
+        }
+        for (id, block) in self.blocks.iter().enumerate() {
+            for successor in &block.successors {
+                writeln!(dot, "    b{} -> b{};", id, successor).unwrap();
+            }
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+fn intersect(immediate: &[Option<BlockId>], position: &[usize], mut a: BlockId, mut b: BlockId) -> BlockId {
+    while a != b {
+        while position[a] > position[b] {
+            a = immediate[a].unwrap();
+        }
+        while position[b] > position[a] {
+            b = immediate[b].unwrap();
+        }
+    }
+    a
+}
+
+/// Turns the registers live after `command` into the registers live before it.
+fn update_liveness(command: &Command, live: &mut BTreeSet<u64>) {
+    if let Some(Param::Register(register)) = command.destination() {
+        live.remove(&register);
+    }
+    for param in command.operands() {
+        if let Param::Register(register) = param {
+            live.insert(register);
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// The dominator tree of a `Cfg`. A block dominates another if every path from
+/// the entry to the other block goes through it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dominators {
+    immediate: Vec<Option<BlockId>>,
+}
+
+impl Dominators {
+    /// The closest block that dominates `block` other than itself. `None` for the
+    /// entry and for unreachable blocks.
+    pub fn immediate(&self, block: BlockId) -> Option<BlockId> {
+        self.immediate[block]
+    }
+
+    /// Whether `a` dominates `b`. Every reachable block dominates itself.
+    /// Unreachable blocks neither dominate nor are dominated.
+    pub fn dominates(&self, a: BlockId, mut b: BlockId) -> bool {
+        if !self.is_reachable(a) || !self.is_reachable(b) {
+            return false;
+        }
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.immediate[b] {
+                Some(up) => b = up,
+                None => return false,
+            }
+        }
+    }
+
+    fn is_reachable(&self, block: BlockId) -> bool {
+        // Only the entry is reachable without an immediate dominator.
+        block == 0 || self.immediate[block].is_some()
+    }
+}
+
+/// A natural loop: the blocks that can reach a back edge to `header` without
+/// going through `header`, plus the header itself.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Loop {
+    pub header: BlockId,
+    pub body: BTreeSet<BlockId>,
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::channel;
+
+use super::{generate_rust, generated_test, CodegenError, RustGenerator};
+use crate::moo::parse_program_from_string;
+use crate::program::Program;
+use crate::vm::{Command, MooMachine, Param, Status, VmError};
+
+type Generated = fn(&mut Vec<u64>, &mut Vec<u64>) -> Result<(), VmError>;
+
+/// The programs `generated_test.rs` is generated from. They all use `I0` and `O0` so
+/// the generated functions have the same signature.
+const PROGRAMS: [(&str, &str, Generated); 7] = [
+    (
+        "fibonacci",
+        "load I0 R2; ucmp R2 200u; jgre 2f; load 0u R0; load 1u R1;
+         1: ucmp R2 0u; jeq 2f; uadd R0 R1 R3; load R1 R0; load R3 R1; usub R2 1u R2; jump 1b;
+         2: load R0 O0; load R2 O0;",
+        generated_test::fibonacci,
+    ),
+    (
+        "signed",
+        "load I0 R0; iadd R0 -5i R1; imul R1 3i R1; idiv R1 2i O0; isub -9223372036854775808i R1 O0;
+         imul R0 R0 O0; idiv -9223372036854775808i -1i O0; jineg R1 1f; load 1u O0; jump 2f;
+         1: load 2u O0; 2: load R1 O0;",
+        generated_test::signed,
+    ),
+    (
+        "floats",
+        "load I0 R0; fmul R0 0.5f R1; fadd R1 2i R1; fdiv R1 4u R2; fsub R2 10.5f O0; jfneg R2 1f; load 7u O0;
+         1: fcmp R2 R1; jless 1f; load 8u O0; 1: load -0f O0; load 1e300f R3; fmul R3 R3 O0; fdiv 1f 3f O0;",
+        generated_test::floats,
+    ),
+    (
+        "sum_until_closed",
+        "1: load I0 R0; uadd R0 R5 R5; load R5 O0; jump 1b;",
+        generated_test::sum_until_closed,
+    ),
+    (
+        "counting",
+        "load I0 R0; ucmp R0 20u; jgre 2f;
+         1: icmp R0 10i; jgre 2f; iadd R0 1i R0; load R0 O0; jneq 1b;
+         2: load -1i O0; ucmp R0 R0; jeq 3f; load 5u O0; 3: load R0 O0;",
+        generated_test::counting,
+    ),
+    (
+        "conversions",
+        "load 2.75f R0; load R0 O0; uadd 2.75f 1u O0; iadd -2.75f 1i O0; fadd 3u -4i O0; usub 0u 1i O0;
+         load I0 R1; load R1 O0; load R9 O0; udiv 7u I0 O0;",
+        generated_test::conversions,
+    ),
+    (
+        "jump_without_compare",
+        "load I0 R0; .loop: jeq .loop; load R0 O0;",
+        generated_test::jump_without_compare,
+    ),
+];
+
+const HEADER: &str = "// Generated from the programs in `codegen_test.rs` by `generated_is_up_to_date_test`.\n\
+                      // Run the tests with UPDATE_GENERATED=1 to regenerate it.\n";
+
+fn generate_all() -> String {
+    let generator = RustGenerator::new().with_crate_path("crate");
+    let mut code = HEADER.to_string();
+    for (name, source, _) in &PROGRAMS {
+        code.push('\n');
+        code.push_str(&generator.generate(name, &parse_program_from_string(source).unwrap()).unwrap());
+    }
+    code
+}
+
+#[test]
+fn generated_is_up_to_date_test() {
+    let code = generate_all();
+    if env::var_os("UPDATE_GENERATED").is_some() {
+        fs::write(concat!(env!("CARGO_MANIFEST_DIR"), "/src/codegen/generated_test.rs"), &code).unwrap();
+    } else {
+        assert!(code == include_str!("generated_test.rs"), "generated_test.rs is out of date");
+    }
+}
+
+/// What running a program did: how it stopped and what it wrote, or what it panicked with.
+type Outcome = Result<(Result<(), VmError>, Vec<u64>), String>;
+
+fn catch<F: FnOnce() -> (Result<(), VmError>, Vec<u64>)>(run: F) -> Outcome {
+    panic::catch_unwind(AssertUnwindSafe(run)).map_err(|payload| {
+        payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default()
+    })
+}
+
+fn interpret(program: &Program, input: &[u64]) -> Outcome {
+    catch(|| {
+        let (sender, receiver) = channel();
+        let mut machine = MooMachine::new(program.clone(), vec![Box::new(input.to_vec())], vec![Box::new(sender)]);
+        let result = loop {
+            match machine.tick() {
+                Ok(Status::Halted) => break Ok(()),
+                Ok(_) => {}
+                Err(err) => break Err(err),
+            }
+        };
+        (result, receiver.try_iter().collect())
+    })
+}
+
+fn run_generated(generated: Generated, input: &[u64]) -> Outcome {
+    catch(|| {
+        let mut output = Vec::new();
+        let result = generated(&mut input.to_vec(), &mut output);
+        (result, output)
+    })
+}
+
+#[test]
+fn generated_matches_interpreter_test() {
+    let inputs = [
+        vec![],
+        vec![0, 0, 0],
+        vec![5, 3, 2, 1],
+        vec![93, 7, 1, 9],
+        vec![201, 10, 11],
+        vec![2.5f64.to_bits(), (-3.25f64).to_bits(), 1],
+        vec![(-7i64) as u64, 1e10f64.to_bits()],
+        vec![f64::NAN.to_bits()],
+    ];
+    for (name, source, generated) in &PROGRAMS {
+        let program = parse_program_from_string(source).unwrap();
+        for input in &inputs {
+            assert_eq!(
+                run_generated(*generated, input),
+                interpret(&program, input),
+                "{} with {:?}",
+                name,
+                input
+            );
+        }
+    }
+}
+
+#[test]
+fn generated_stops_where_interpreter_does_test() {
+    let program = parse_program_from_string(PROGRAMS[3].1).unwrap();
+    assert_eq!(
+        interpret(&program, &[1, 2, 3]),
+        Ok((Err(VmError::InputClosed(0)), vec![1, 3, 6])),
+    );
+    assert_eq!(
+        run_generated(generated_test::jump_without_compare, &[1]),
+        Ok((Err(VmError::JumpWithoutCompare), vec![])),
+    );
+    assert_eq!(
+        run_generated(generated_test::conversions, &[0, 0]).map(|(result, _)| result),
+        Ok(Err(VmError::DivisionByZero)),
+    );
+}
+
+#[test]
+fn channels_become_parameters_test() {
+    let program = parse_program_from_string("uadd I2 I0 O1;").unwrap();
+    let code = generate_rust("add", &program).unwrap();
+    assert!(code.contains(
+        "pub fn add<I0, I2, O1>(i0: &mut I0, i2: &mut I2, o1: &mut O1) -> Result<(), moofloom::vm::VmError>\n\
+         where\n    I0: moofloom::common::Source<u64>,\n    I2: moofloom::common::Source<u64>,\n    \
+         O1: moofloom::common::Sink<u64>,\n{"
+    ));
+    assert!(code.contains("let a = take(i2, 2)?;\n                let b = take(i0, 0)?;\n"));
+
+    let program = parse_program_from_string("load 1u R0;").unwrap();
+    let code = generate_rust("quiet", &program).unwrap();
+    assert!(code.contains("pub fn quiet() -> Result<(), moofloom::vm::VmError> {"));
+}
+
+#[test]
+fn codegen_errors_test() {
+    let program = Program::new(vec![Command::Jump("nowhere".to_string())], HashMap::new());
+    assert_eq!(generate_rust("f", &program), Err(CodegenError::UnknownLabel("nowhere".to_string())));
+
+    let program = parse_program_from_string("load 1u R0;").unwrap();
+    for name in &["", "_", "1f", "add-one", "f()", "fn", "loop", "match", "self", "Self", "crate", "async", "try"] {
+        assert_eq!(generate_rust(name, &program), Err(CodegenError::InvalidName(name.to_string())));
+    }
+    assert!(generate_rust("_add_1", &program).is_ok());
+
+    let errors = [
+        (Command::Load(Param::Output(0), Param::Register(0)), CodegenError::InvalidOperand(Param::Output(0))),
+        (
+            Command::FSub(Param::FConstant(1.), Param::Output(2), Param::Register(0)),
+            CodegenError::InvalidOperand(Param::Output(2)),
+        ),
+        (Command::JFNeg(Param::Output(1), "1".to_string()), CodegenError::InvalidOperand(Param::Output(1))),
+        (
+            Command::IAdd(Param::IConstant(1), Param::IConstant(1), Param::Input(0)),
+            CodegenError::InvalidDestination(Param::Input(0)),
+        ),
+        (
+            Command::Load(Param::FConstant(1.), Param::UConstant(1)),
+            CodegenError::InvalidDestination(Param::UConstant(1)),
+        ),
+        (Command::FCmp(Param::FConstant(f64::NAN), Param::Register(0)), CodegenError::NanCompare),
+    ];
+    for (command, error) in errors.iter().cloned() {
+        let labels = vec![("1".to_string(), 0)].into_iter().collect();
+        assert_eq!(generate_rust("f", &Program::new(vec![command], labels)), Err(error));
+    }
+}
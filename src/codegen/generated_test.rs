@@ -0,0 +1,657 @@
+// Generated from the programs in `codegen_test.rs` by `generated_is_up_to_date_test`.
+// Run the tests with UPDATE_GENERATED=1 to regenerate it.
+
+#[allow(dead_code, unused_imports, unused_mut, unused_variables, unused_assignments, unreachable_code, clippy::all)]
+pub fn fibonacci<I0, O0>(i0: &mut I0, o0: &mut O0) -> Result<(), crate::vm::VmError>
+where
+    I0: crate::common::Source<u64>,
+    O0: crate::common::Sink<u64>,
+{
+    use std::cmp::Ordering;
+    use std::task::Poll;
+    use crate::common::{Sink, SinkError, Source};
+    use crate::vm::VmError;
+
+    fn float(bits: u64) -> Result<f64, VmError> {
+        let float = f64::from_bits(bits);
+        if float.is_nan() {
+            Err(VmError::LoadedNan)
+        } else if float.is_infinite() {
+            Err(VmError::LoadedInfinity)
+        } else {
+            Ok(float)
+        }
+    }
+
+    fn take<I: Source<u64>>(input: &mut I, channel: u64) -> Result<u64, VmError> {
+        loop {
+            match input.poll() {
+                Poll::Ready(Some(value)) => return Ok(value),
+                Poll::Ready(None) => return Err(VmError::InputClosed(channel)),
+                Poll::Pending => std::thread::yield_now(),
+            }
+        }
+    }
+
+    fn put<O: Sink<u64>>(output: &mut O, channel: u64, value: u64) -> Result<(), VmError> {
+        loop {
+            match output.put(value) {
+                Ok(()) => return Ok(()),
+                Err(SinkError::Full) => std::thread::yield_now(),
+                Err(SinkError::Closed) => return Err(VmError::OutputClosed(channel)),
+            }
+        }
+    }
+
+    let mut r0: u64 = 0;
+    let mut r1: u64 = 0;
+    let mut r2: u64 = 0;
+    let mut r3: u64 = 0;
+    let mut compare: Option<Ordering> = None;
+    let mut state: u64 = 0;
+    loop {
+        state = match state {
+            0 => {
+                // load I0 R2
+                r2 = take(i0, 0)?;
+                // ucmp R2 200u
+                let a = r2;
+                let b = 200u64;
+                compare = Some(a.cmp(&b));
+                // jgre 2@12
+                let compared = compare.ok_or(VmError::JumpWithoutCompare)?;
+                if compared == Ordering::Greater { 12 } else { 3 }
+            }
+            3 => {
+                // load 0u R0
+                r0 = 0u64;
+                // load 1u R1
+                r1 = 1u64;
+                5
+            }
+            5 => {
+                // ucmp R2 0u
+                let a = r2;
+                let b = 0u64;
+                compare = Some(a.cmp(&b));
+                // jeq 2@12
+                let compared = compare.ok_or(VmError::JumpWithoutCompare)?;
+                if compared == Ordering::Equal { 12 } else { 7 }
+            }
+            7 => {
+                // uadd R0 R1 R3
+                let a = r0;
+                let b = r1;
+                r3 = a.wrapping_add(b);
+                // load R1 R0
+                r0 = r1;
+                // load R3 R1
+                r1 = r3;
+                // usub R2 1u R2
+                let a = r2;
+                let b = 1u64;
+                r2 = a.wrapping_sub(b);
+                // jump 1@5
+                5
+            }
+            12 => {
+                // load R0 O0
+                put(o0, 0, r0)?;
+                // load R2 O0
+                put(o0, 0, r2)?;
+                14
+            }
+            _ => return Ok(()),
+        };
+    }
+}
+
+#[allow(dead_code, unused_imports, unused_mut, unused_variables, unused_assignments, unreachable_code, clippy::all)]
+pub fn signed<I0, O0>(i0: &mut I0, o0: &mut O0) -> Result<(), crate::vm::VmError>
+where
+    I0: crate::common::Source<u64>,
+    O0: crate::common::Sink<u64>,
+{
+    use std::cmp::Ordering;
+    use std::task::Poll;
+    use crate::common::{Sink, SinkError, Source};
+    use crate::vm::VmError;
+
+    fn float(bits: u64) -> Result<f64, VmError> {
+        let float = f64::from_bits(bits);
+        if float.is_nan() {
+            Err(VmError::LoadedNan)
+        } else if float.is_infinite() {
+            Err(VmError::LoadedInfinity)
+        } else {
+            Ok(float)
+        }
+    }
+
+    fn take<I: Source<u64>>(input: &mut I, channel: u64) -> Result<u64, VmError> {
+        loop {
+            match input.poll() {
+                Poll::Ready(Some(value)) => return Ok(value),
+                Poll::Ready(None) => return Err(VmError::InputClosed(channel)),
+                Poll::Pending => std::thread::yield_now(),
+            }
+        }
+    }
+
+    fn put<O: Sink<u64>>(output: &mut O, channel: u64, value: u64) -> Result<(), VmError> {
+        loop {
+            match output.put(value) {
+                Ok(()) => return Ok(()),
+                Err(SinkError::Full) => std::thread::yield_now(),
+                Err(SinkError::Closed) => return Err(VmError::OutputClosed(channel)),
+            }
+        }
+    }
+
+    let mut r0: u64 = 0;
+    let mut r1: u64 = 0;
+    let mut compare: Option<Ordering> = None;
+    let mut state: u64 = 0;
+    loop {
+        state = match state {
+            0 => {
+                // load I0 R0
+                r0 = take(i0, 0)?;
+                // iadd R0 -5i R1
+                let a = r0 as i64;
+                let b = -5i64;
+                r1 = a.wrapping_add(b) as u64;
+                // imul R1 3i R1
+                let a = r1 as i64;
+                let b = 3i64;
+                r1 = a.wrapping_mul(b) as u64;
+                // idiv R1 2i O0
+                let a = r1 as i64;
+                let b = 2i64;
+                if b == 0 {
+                    return Err(VmError::DivisionByZero);
+                }
+                put(o0, 0, a.wrapping_div(b) as u64)?;
+                // isub -9223372036854775808i R1 O0
+                let a = -9223372036854775808i64;
+                let b = r1 as i64;
+                put(o0, 0, a.wrapping_sub(b) as u64)?;
+                // imul R0 R0 O0
+                let a = r0 as i64;
+                let b = r0 as i64;
+                put(o0, 0, a.wrapping_mul(b) as u64)?;
+                // idiv -9223372036854775808i -1i O0
+                let a = -9223372036854775808i64;
+                let b = -1i64;
+                if b == 0 {
+                    return Err(VmError::DivisionByZero);
+                }
+                put(o0, 0, a.wrapping_div(b) as u64)?;
+                // jineg R1 1@10
+                let a = r1 as i64;
+                if a < 0 { 10 } else { 8 }
+            }
+            8 => {
+                // load 1u O0
+                put(o0, 0, 1u64)?;
+                // jump 2@11
+                11
+            }
+            10 => {
+                // load 2u O0
+                put(o0, 0, 2u64)?;
+                11
+            }
+            11 => {
+                // load R1 O0
+                put(o0, 0, r1)?;
+                12
+            }
+            _ => return Ok(()),
+        };
+    }
+}
+
+#[allow(dead_code, unused_imports, unused_mut, unused_variables, unused_assignments, unreachable_code, clippy::all)]
+pub fn floats<I0, O0>(i0: &mut I0, o0: &mut O0) -> Result<(), crate::vm::VmError>
+where
+    I0: crate::common::Source<u64>,
+    O0: crate::common::Sink<u64>,
+{
+    use std::cmp::Ordering;
+    use std::task::Poll;
+    use crate::common::{Sink, SinkError, Source};
+    use crate::vm::VmError;
+
+    fn float(bits: u64) -> Result<f64, VmError> {
+        let float = f64::from_bits(bits);
+        if float.is_nan() {
+            Err(VmError::LoadedNan)
+        } else if float.is_infinite() {
+            Err(VmError::LoadedInfinity)
+        } else {
+            Ok(float)
+        }
+    }
+
+    fn take<I: Source<u64>>(input: &mut I, channel: u64) -> Result<u64, VmError> {
+        loop {
+            match input.poll() {
+                Poll::Ready(Some(value)) => return Ok(value),
+                Poll::Ready(None) => return Err(VmError::InputClosed(channel)),
+                Poll::Pending => std::thread::yield_now(),
+            }
+        }
+    }
+
+    fn put<O: Sink<u64>>(output: &mut O, channel: u64, value: u64) -> Result<(), VmError> {
+        loop {
+            match output.put(value) {
+                Ok(()) => return Ok(()),
+                Err(SinkError::Full) => std::thread::yield_now(),
+                Err(SinkError::Closed) => return Err(VmError::OutputClosed(channel)),
+            }
+        }
+    }
+
+    let mut r0: u64 = 0;
+    let mut r1: u64 = 0;
+    let mut r2: u64 = 0;
+    let mut r3: u64 = 0;
+    let mut compare: Option<Ordering> = None;
+    let mut state: u64 = 0;
+    loop {
+        state = match state {
+            0 => {
+                // load I0 R0
+                r0 = take(i0, 0)?;
+                // fmul R0 0.5f R1
+                let a = float(r0)?;
+                let b = 0.5f64;
+                r1 = (a * b).to_bits();
+                // fadd R1 2i R1
+                let a = float(r1)?;
+                let b = 2.0f64;
+                r1 = (a + b).to_bits();
+                // fdiv R1 4u R2
+                let a = float(r1)?;
+                let b = 4.0f64;
+                if b == 0.0 {
+                    return Err(VmError::DivisionByZero);
+                }
+                r2 = (a / b).to_bits();
+                // fsub R2 10.5f O0
+                let a = float(r2)?;
+                let b = 10.5f64;
+                put(o0, 0, (a - b).to_bits())?;
+                // jfneg R2 1@7
+                let a = float(r2)?;
+                if a < 0.0 { 7 } else { 6 }
+            }
+            6 => {
+                // load 7u O0
+                put(o0, 0, 7u64)?;
+                7
+            }
+            7 => {
+                // fcmp R2 R1
+                let a = float(r2)?;
+                let b = float(r1)?;
+                compare = Some(a.partial_cmp(&b).ok_or(VmError::LoadedNan)?);
+                // jless 1@10
+                let compared = compare.ok_or(VmError::JumpWithoutCompare)?;
+                if compared == Ordering::Less { 10 } else { 9 }
+            }
+            9 => {
+                // load 8u O0
+                put(o0, 0, 8u64)?;
+                10
+            }
+            10 => {
+                // load -0f O0
+                put(o0, 0, f64::to_bits(-0.0f64))?;
+                // load 1000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000f R3
+                r3 = f64::to_bits(1e300f64);
+                // fmul R3 R3 O0
+                let a = float(r3)?;
+                let b = float(r3)?;
+                put(o0, 0, (a * b).to_bits())?;
+                // fdiv 1f 3f O0
+                let a = 1.0f64;
+                let b = 3.0f64;
+                if b == 0.0 {
+                    return Err(VmError::DivisionByZero);
+                }
+                put(o0, 0, (a / b).to_bits())?;
+                14
+            }
+            _ => return Ok(()),
+        };
+    }
+}
+
+#[allow(dead_code, unused_imports, unused_mut, unused_variables, unused_assignments, unreachable_code, clippy::all)]
+pub fn sum_until_closed<I0, O0>(i0: &mut I0, o0: &mut O0) -> Result<(), crate::vm::VmError>
+where
+    I0: crate::common::Source<u64>,
+    O0: crate::common::Sink<u64>,
+{
+    use std::cmp::Ordering;
+    use std::task::Poll;
+    use crate::common::{Sink, SinkError, Source};
+    use crate::vm::VmError;
+
+    fn float(bits: u64) -> Result<f64, VmError> {
+        let float = f64::from_bits(bits);
+        if float.is_nan() {
+            Err(VmError::LoadedNan)
+        } else if float.is_infinite() {
+            Err(VmError::LoadedInfinity)
+        } else {
+            Ok(float)
+        }
+    }
+
+    fn take<I: Source<u64>>(input: &mut I, channel: u64) -> Result<u64, VmError> {
+        loop {
+            match input.poll() {
+                Poll::Ready(Some(value)) => return Ok(value),
+                Poll::Ready(None) => return Err(VmError::InputClosed(channel)),
+                Poll::Pending => std::thread::yield_now(),
+            }
+        }
+    }
+
+    fn put<O: Sink<u64>>(output: &mut O, channel: u64, value: u64) -> Result<(), VmError> {
+        loop {
+            match output.put(value) {
+                Ok(()) => return Ok(()),
+                Err(SinkError::Full) => std::thread::yield_now(),
+                Err(SinkError::Closed) => return Err(VmError::OutputClosed(channel)),
+            }
+        }
+    }
+
+    let mut r0: u64 = 0;
+    let mut r5: u64 = 0;
+    let mut compare: Option<Ordering> = None;
+    let mut state: u64 = 0;
+    loop {
+        state = match state {
+            0 => {
+                // load I0 R0
+                r0 = take(i0, 0)?;
+                // uadd R0 R5 R5
+                let a = r0;
+                let b = r5;
+                r5 = a.wrapping_add(b);
+                // load R5 O0
+                put(o0, 0, r5)?;
+                // jump 1@0
+                0
+            }
+            _ => return Ok(()),
+        };
+    }
+}
+
+#[allow(dead_code, unused_imports, unused_mut, unused_variables, unused_assignments, unreachable_code, clippy::all)]
+pub fn counting<I0, O0>(i0: &mut I0, o0: &mut O0) -> Result<(), crate::vm::VmError>
+where
+    I0: crate::common::Source<u64>,
+    O0: crate::common::Sink<u64>,
+{
+    use std::cmp::Ordering;
+    use std::task::Poll;
+    use crate::common::{Sink, SinkError, Source};
+    use crate::vm::VmError;
+
+    fn float(bits: u64) -> Result<f64, VmError> {
+        let float = f64::from_bits(bits);
+        if float.is_nan() {
+            Err(VmError::LoadedNan)
+        } else if float.is_infinite() {
+            Err(VmError::LoadedInfinity)
+        } else {
+            Ok(float)
+        }
+    }
+
+    fn take<I: Source<u64>>(input: &mut I, channel: u64) -> Result<u64, VmError> {
+        loop {
+            match input.poll() {
+                Poll::Ready(Some(value)) => return Ok(value),
+                Poll::Ready(None) => return Err(VmError::InputClosed(channel)),
+                Poll::Pending => std::thread::yield_now(),
+            }
+        }
+    }
+
+    fn put<O: Sink<u64>>(output: &mut O, channel: u64, value: u64) -> Result<(), VmError> {
+        loop {
+            match output.put(value) {
+                Ok(()) => return Ok(()),
+                Err(SinkError::Full) => std::thread::yield_now(),
+                Err(SinkError::Closed) => return Err(VmError::OutputClosed(channel)),
+            }
+        }
+    }
+
+    let mut r0: u64 = 0;
+    let mut compare: Option<Ordering> = None;
+    let mut state: u64 = 0;
+    loop {
+        state = match state {
+            0 => {
+                // load I0 R0
+                r0 = take(i0, 0)?;
+                // ucmp R0 20u
+                let a = r0;
+                let b = 20u64;
+                compare = Some(a.cmp(&b));
+                // jgre 2@8
+                let compared = compare.ok_or(VmError::JumpWithoutCompare)?;
+                if compared == Ordering::Greater { 8 } else { 3 }
+            }
+            3 => {
+                // icmp R0 10i
+                let a = r0 as i64;
+                let b = 10i64;
+                compare = Some(a.cmp(&b));
+                // jgre 2@8
+                let compared = compare.ok_or(VmError::JumpWithoutCompare)?;
+                if compared == Ordering::Greater { 8 } else { 5 }
+            }
+            5 => {
+                // iadd R0 1i R0
+                let a = r0 as i64;
+                let b = 1i64;
+                r0 = a.wrapping_add(b) as u64;
+                // load R0 O0
+                put(o0, 0, r0)?;
+                // jneq 1@3
+                let compared = compare.ok_or(VmError::JumpWithoutCompare)?;
+                if compared != Ordering::Equal { 3 } else { 8 }
+            }
+            8 => {
+                // load -1i O0
+                put(o0, 0, -1i64 as u64)?;
+                // ucmp R0 R0
+                let a = r0;
+                let b = r0;
+                compare = Some(a.cmp(&b));
+                // jeq 3@12
+                let compared = compare.ok_or(VmError::JumpWithoutCompare)?;
+                if compared == Ordering::Equal { 12 } else { 11 }
+            }
+            11 => {
+                // load 5u O0
+                put(o0, 0, 5u64)?;
+                12
+            }
+            12 => {
+                // load R0 O0
+                put(o0, 0, r0)?;
+                13
+            }
+            _ => return Ok(()),
+        };
+    }
+}
+
+#[allow(dead_code, unused_imports, unused_mut, unused_variables, unused_assignments, unreachable_code, clippy::all)]
+pub fn conversions<I0, O0>(i0: &mut I0, o0: &mut O0) -> Result<(), crate::vm::VmError>
+where
+    I0: crate::common::Source<u64>,
+    O0: crate::common::Sink<u64>,
+{
+    use std::cmp::Ordering;
+    use std::task::Poll;
+    use crate::common::{Sink, SinkError, Source};
+    use crate::vm::VmError;
+
+    fn float(bits: u64) -> Result<f64, VmError> {
+        let float = f64::from_bits(bits);
+        if float.is_nan() {
+            Err(VmError::LoadedNan)
+        } else if float.is_infinite() {
+            Err(VmError::LoadedInfinity)
+        } else {
+            Ok(float)
+        }
+    }
+
+    fn take<I: Source<u64>>(input: &mut I, channel: u64) -> Result<u64, VmError> {
+        loop {
+            match input.poll() {
+                Poll::Ready(Some(value)) => return Ok(value),
+                Poll::Ready(None) => return Err(VmError::InputClosed(channel)),
+                Poll::Pending => std::thread::yield_now(),
+            }
+        }
+    }
+
+    fn put<O: Sink<u64>>(output: &mut O, channel: u64, value: u64) -> Result<(), VmError> {
+        loop {
+            match output.put(value) {
+                Ok(()) => return Ok(()),
+                Err(SinkError::Full) => std::thread::yield_now(),
+                Err(SinkError::Closed) => return Err(VmError::OutputClosed(channel)),
+            }
+        }
+    }
+
+    let mut r0: u64 = 0;
+    let mut r1: u64 = 0;
+    let mut r9: u64 = 0;
+    let mut compare: Option<Ordering> = None;
+    let mut state: u64 = 0;
+    loop {
+        state = match state {
+            0 => {
+                // load 2.75f R0
+                r0 = f64::to_bits(2.75f64);
+                // load R0 O0
+                put(o0, 0, r0)?;
+                // uadd 2.75f 1u O0
+                let a = 2u64;
+                let b = 1u64;
+                put(o0, 0, a.wrapping_add(b))?;
+                // iadd -2.75f 1i O0
+                let a = -2i64;
+                let b = 1i64;
+                put(o0, 0, a.wrapping_add(b) as u64)?;
+                // fadd 3u -4i O0
+                let a = 3.0f64;
+                let b = -4.0f64;
+                put(o0, 0, (a + b).to_bits())?;
+                // usub 0u 1i O0
+                let a = 0u64;
+                let b = 1u64;
+                put(o0, 0, a.wrapping_sub(b))?;
+                // load I0 R1
+                r1 = take(i0, 0)?;
+                // load R1 O0
+                put(o0, 0, r1)?;
+                // load R9 O0
+                put(o0, 0, r9)?;
+                // udiv 7u I0 O0
+                let a = 7u64;
+                let b = take(i0, 0)?;
+                if b == 0 {
+                    return Err(VmError::DivisionByZero);
+                }
+                put(o0, 0, a.wrapping_div(b))?;
+                10
+            }
+            _ => return Ok(()),
+        };
+    }
+}
+
+#[allow(dead_code, unused_imports, unused_mut, unused_variables, unused_assignments, unreachable_code, clippy::all)]
+pub fn jump_without_compare<I0, O0>(i0: &mut I0, o0: &mut O0) -> Result<(), crate::vm::VmError>
+where
+    I0: crate::common::Source<u64>,
+    O0: crate::common::Sink<u64>,
+{
+    use std::cmp::Ordering;
+    use std::task::Poll;
+    use crate::common::{Sink, SinkError, Source};
+    use crate::vm::VmError;
+
+    fn float(bits: u64) -> Result<f64, VmError> {
+        let float = f64::from_bits(bits);
+        if float.is_nan() {
+            Err(VmError::LoadedNan)
+        } else if float.is_infinite() {
+            Err(VmError::LoadedInfinity)
+        } else {
+            Ok(float)
+        }
+    }
+
+    fn take<I: Source<u64>>(input: &mut I, channel: u64) -> Result<u64, VmError> {
+        loop {
+            match input.poll() {
+                Poll::Ready(Some(value)) => return Ok(value),
+                Poll::Ready(None) => return Err(VmError::InputClosed(channel)),
+                Poll::Pending => std::thread::yield_now(),
+            }
+        }
+    }
+
+    fn put<O: Sink<u64>>(output: &mut O, channel: u64, value: u64) -> Result<(), VmError> {
+        loop {
+            match output.put(value) {
+                Ok(()) => return Ok(()),
+                Err(SinkError::Full) => std::thread::yield_now(),
+                Err(SinkError::Closed) => return Err(VmError::OutputClosed(channel)),
+            }
+        }
+    }
+
+    let mut r0: u64 = 0;
+    let mut compare: Option<Ordering> = None;
+    let mut state: u64 = 0;
+    loop {
+        state = match state {
+            0 => {
+                // load I0 R0
+                r0 = take(i0, 0)?;
+                1
+            }
+            1 => {
+                // jeq .loop
+                let compared = compare.ok_or(VmError::JumpWithoutCompare)?;
+                if compared == Ordering::Equal { 1 } else { 2 }
+            }
+            2 => {
+                // load R0 O0
+                put(o0, 0, r0)?;
+                3
+            }
+            _ => return Ok(()),
+        };
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::BTreeSet;
+use std::fmt::{self, Write};
+
+use crate::cfg::Cfg;
+use crate::program::Program;
+use crate::vm::{Command, Param};
+
+#[cfg(test)]
+mod codegen_test;
+#[cfg(test)]
+mod generated_test;
+
+/// Turns `Program`s into standalone Rust functions that do what a `MooMachine`
+/// running them does, with the same wrapping integer and float semantics.
+///
+/// Registers become `u64` locals and every basic block becomes a state of a `match`
+/// in a loop. Every input and output channel the program uses becomes a parameter
+/// that is generic over `Source<u64>` or `Sink<u64>`, inputs first and in channel
+/// order. The function returns once the program halts, or with the `VmError` a
+/// machine would fail with. Where a machine would block the function yields the
+/// thread and tries again. Programs that read from an output, store into an input
+/// or a constant or compare a NaN constant are refused.
+#[derive(Clone, Debug)]
+pub struct RustGenerator {
+    crate_path: String,
+}
+
+impl Default for RustGenerator {
+    fn default() -> Self {
+        RustGenerator::new()
+    }
+}
+
+impl RustGenerator {
+    pub fn new() -> Self {
+        RustGenerator {
+            crate_path: "moofloom".to_string(),
+        }
+    }
+
+    /// Sets the path the generated code uses for this crate, `moofloom` by default.
+    pub fn with_crate_path<S: Into<String>>(mut self, crate_path: S) -> Self {
+        self.crate_path = crate_path.into();
+        self
+    }
+
+    /// Generates a public function called `name` that runs `program`.
+    pub fn generate(&self, name: &str, program: &Program) -> Result<String, CodegenError> {
+        if !is_identifier(name) {
+            return Err(CodegenError::InvalidName(name.to_string()));
+        }
+        let mut registers = BTreeSet::new();
+        let mut inputs = BTreeSet::new();
+        let mut outputs = BTreeSet::new();
+        for command in program.commands() {
+            if let Some(label) = command.label() {
+                if !program.labels().contains_key(label) {
+                    return Err(CodegenError::UnknownLabel(label.to_string()));
+                }
+            }
+            for param in command.params() {
+                match param {
+                    Param::Register(register) => registers.insert(register),
+                    Param::Input(channel) => inputs.insert(channel),
+                    Param::Output(channel) => outputs.insert(channel),
+                    _ => false,
+                };
+            }
+        }
+
+        let krate = &self.crate_path;
+        let mut code = String::new();
+        writeln!(code, "#[allow({})]", ALLOWED_LINTS).unwrap();
+        let channels: Vec<_> = inputs
+            .iter()
+            .map(|channel| (format!("I{}", channel), format!("i{}", channel), "Source"))
+            .chain(outputs.iter().map(|channel| (format!("O{}", channel), format!("o{}", channel), "Sink")))
+            .collect();
+        if channels.is_empty() {
+            writeln!(code, "pub fn {}() -> Result<(), {}::vm::VmError> {{", name, krate).unwrap();
+        } else {
+            let types: Vec<_> = channels.iter().map(|(ty, _, _)| ty.as_str()).collect();
+            let params: Vec<_> = channels.iter().map(|(ty, param, _)| format!("{}: &mut {}", param, ty)).collect();
+            writeln!(
+                code,
+                "pub fn {}<{}>({}) -> Result<(), {}::vm::VmError>",
+                name,
+                types.join(", "),
+                params.join(", "),
+                krate
+            )
+            .unwrap();
+            writeln!(code, "where").unwrap();
+            for (ty, _, bound) in &channels {
+                writeln!(code, "    {}: {}::common::{}<u64>,", ty, krate, bound).unwrap();
+            }
+            writeln!(code, "{{").unwrap();
+        }
+        writeln!(code, "    use std::cmp::Ordering;").unwrap();
+        writeln!(code, "    use std::task::Poll;").unwrap();
+        writeln!(code, "    use {}::common::{{Sink, SinkError, Source}};", krate).unwrap();
+        writeln!(code, "    use {}::vm::VmError;", krate).unwrap();
+        code.push_str(HELPERS);
+
+        for register in &registers {
+            writeln!(code, "    let mut r{}: u64 = 0;", register).unwrap();
+        }
+        writeln!(code, "    let mut compare: Option<Ordering> = None;").unwrap();
+        writeln!(code, "    let mut state: u64 = 0;").unwrap();
+        writeln!(code, "    loop {{").unwrap();
+        writeln!(code, "        state = match state {{").unwrap();
+        for block in Cfg::new(program).blocks() {
+            writeln!(code, "            {} => {{", block.start).unwrap();
+            for address in block.start..block.end {
+                let command = &program[address as usize];
+                writeln!(code, "                // {}", command).unwrap();
+                let mut lines = instruction(command)?;
+                if address + 1 == block.end {
+                    lines.extend(next(command, program, block.end)?);
+                }
+                for line in lines.iter().flat_map(|line| line.lines()) {
+                    writeln!(code, "                {}", line).unwrap();
+                }
+            }
+            writeln!(code, "            }}").unwrap();
+        }
+        writeln!(code, "            _ => return Ok(()),").unwrap();
+        writeln!(code, "        }};").unwrap();
+        writeln!(code, "    }}").unwrap();
+        writeln!(code, "}}").unwrap();
+        Ok(code)
+    }
+}
+
+/// Generates a function called `name` that runs `program` with the default
+/// `RustGenerator`.
+pub fn generate_rust(name: &str, program: &Program) -> Result<String, CodegenError> {
+    RustGenerator::new().generate(name, program)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CodegenError {
+    /// The name of the function isn't a Rust identifier.
+    InvalidName(String),
+    /// A jump goes to a label the program doesn't define.
+    UnknownLabel(String),
+    /// An instruction reads from an output.
+    InvalidOperand(Param),
+    /// An instruction stores into an input or a constant.
+    InvalidDestination(Param),
+    /// An fcmp compares a NaN constant, which has no order.
+    NanCompare,
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodegenError::InvalidName(name) => write!(f, "\"{}\" is not a valid function name", name),
+            CodegenError::UnknownLabel(label) => write!(f, "the program jumps to \"{}\" which it does not define", label),
+            CodegenError::InvalidOperand(param) => write!(f, "the program reads from {}", param),
+            CodegenError::InvalidDestination(param) => write!(f, "the program stores into {}", param),
+            CodegenError::NanCompare => write!(f, "the program compares a NaN constant"),
+        }
+    }
+}
+
+// Generated code is straightforward rather than idiomatic, and not everything it
+// declares is used by every program.
+const ALLOWED_LINTS: &str = "dead_code, unused_imports, unused_mut, unused_variables, unused_assignments, \
+                             unreachable_code, clippy::all";
+
+// The conversions and channel handling of `MooMachine`.
+const HELPERS: &str = r#"
+    fn float(bits: u64) -> Result<f64, VmError> {
+        let float = f64::from_bits(bits);
+        if float.is_nan() {
+            Err(VmError::LoadedNan)
+        } else if float.is_infinite() {
+            Err(VmError::LoadedInfinity)
+        } else {
+            Ok(float)
+        }
+    }
+
+    fn take<I: Source<u64>>(input: &mut I, channel: u64) -> Result<u64, VmError> {
+        loop {
+            match input.poll() {
+                Poll::Ready(Some(value)) => return Ok(value),
+                Poll::Ready(None) => return Err(VmError::InputClosed(channel)),
+                Poll::Pending => std::thread::yield_now(),
+            }
+        }
+    }
+
+    fn put<O: Sink<u64>>(output: &mut O, channel: u64, value: u64) -> Result<(), VmError> {
+        loop {
+            match output.put(value) {
+                Ok(()) => return Ok(()),
+                Err(SinkError::Full) => std::thread::yield_now(),
+                Err(SinkError::Closed) => return Err(VmError::OutputClosed(channel)),
+            }
+        }
+    }
+
+"#;
+
+// Strict and reserved keywords of every edition. Some of them, like `self` and
+// `crate`, can't be raw identifiers either, so names that are keywords are refused.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate", "do", "dyn", "else",
+    "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "macro", "match", "mod",
+    "move", "mut", "override", "priv", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+    "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            name != "_" && !KEYWORDS.contains(&name) && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// The domain an instruction computes in.
+#[derive(Clone, Copy, PartialEq)]
+enum Domain {
+    Float,
+    Signed,
+    Unsigned,
+}
+
+/// An expression reading `param` the way `MooMachine` reads it in `domain`.
+fn operand(param: Param, domain: Domain) -> Result<String, CodegenError> {
+    let raw = match param {
+        Param::Register(register) => format!("r{}", register),
+        Param::Input(channel) => format!("take(i{0}, {0})?", channel),
+        Param::Output(_) => return Err(CodegenError::InvalidOperand(param)),
+        Param::FConstant(float) => return Ok(constant(domain, float, float as i64, float as u64)),
+        Param::IConstant(integer) => return Ok(constant(domain, integer as f64, integer, integer as u64)),
+        Param::UConstant(integer) => return Ok(constant(domain, integer as f64, integer as i64, integer)),
+    };
+    Ok(match domain {
+        Domain::Float => format!("float({})?", raw),
+        Domain::Signed => format!("{} as i64", raw),
+        Domain::Unsigned => raw,
+    })
+}
+
+/// A literal for a constant converted into `domain` the way `MooMachine` converts it.
+fn constant(domain: Domain, float: f64, signed: i64, unsigned: u64) -> String {
+    match domain {
+        Domain::Float => float_literal(float),
+        Domain::Signed => format!("{}i64", signed),
+        Domain::Unsigned => format!("{}u64", unsigned),
+    }
+}
+
+fn float_literal(float: f64) -> String {
+    if float.is_nan() {
+        format!("f64::from_bits({:#x})", float.to_bits())
+    } else if float == f64::INFINITY {
+        "f64::INFINITY".to_string()
+    } else if float == f64::NEG_INFINITY {
+        "f64::NEG_INFINITY".to_string()
+    } else {
+        format!("{:?}f64", float)
+    }
+}
+
+/// A statement storing `bits` into `into`.
+fn store(into: Param, bits: &str) -> Result<String, CodegenError> {
+    match into {
+        Param::Register(register) => Ok(format!("r{} = {};", register, bits)),
+        Param::Output(channel) => Ok(format!("put(o{0}, {0}, {1})?;", channel, bits)),
+        _ => Err(CodegenError::InvalidDestination(into)),
+    }
+}
+
+/// The statements executing `command`, apart from where it jumps.
+fn instruction(command: &Command) -> Result<Vec<String>, CodegenError> {
+    use self::Command::*;
+
+    let (domain, a, b, into, op) = match *command {
+        FAdd(a, b, into) => (Domain::Float, a, b, into, "(a + b).to_bits()"),
+        FSub(a, b, into) => (Domain::Float, a, b, into, "(a - b).to_bits()"),
+        FMul(a, b, into) => (Domain::Float, a, b, into, "(a * b).to_bits()"),
+        FDiv(a, b, into) => (Domain::Float, a, b, into, "(a / b).to_bits()"),
+        UAdd(a, b, into) => (Domain::Unsigned, a, b, into, "a.wrapping_add(b)"),
+        USub(a, b, into) => (Domain::Unsigned, a, b, into, "a.wrapping_sub(b)"),
+        UMul(a, b, into) => (Domain::Unsigned, a, b, into, "a.wrapping_mul(b)"),
+        UDiv(a, b, into) => (Domain::Unsigned, a, b, into, "a.wrapping_div(b)"),
+        IAdd(a, b, into) => (Domain::Signed, a, b, into, "a.wrapping_add(b) as u64"),
+        ISub(a, b, into) => (Domain::Signed, a, b, into, "a.wrapping_sub(b) as u64"),
+        IMul(a, b, into) => (Domain::Signed, a, b, into, "a.wrapping_mul(b) as u64"),
+        IDiv(a, b, into) => (Domain::Signed, a, b, into, "a.wrapping_div(b) as u64"),
+        ICmp(a, b) | UCmp(a, b) | FCmp(a, b) => {
+            let (domain, compare) = match command {
+                ICmp(..) => (Domain::Signed, "a.cmp(&b)"),
+                UCmp(..) => (Domain::Unsigned, "a.cmp(&b)"),
+                _ => {
+                    let is_nan = |param| matches!(param, Param::FConstant(float) if float.is_nan());
+                    if is_nan(a) || is_nan(b) {
+                        return Err(CodegenError::NanCompare);
+                    }
+                    // Registers and inputs are never read as NaN, so this can't fail either.
+                    (Domain::Float, "a.partial_cmp(&b).ok_or(VmError::LoadedNan)?")
+                }
+            };
+            return Ok(vec![
+                format!("let a = {};", operand(a, domain)?),
+                format!("let b = {};", operand(b, domain)?),
+                format!("compare = Some({});", compare),
+            ]);
+        }
+        Load(what, into) => {
+            let bits = match what {
+                Param::FConstant(float) => format!("f64::to_bits({})", float_literal(float)),
+                Param::IConstant(integer) => format!("{}i64 as u64", integer),
+                Param::UConstant(integer) => format!("{}u64", integer),
+                _ => operand(what, Domain::Unsigned)?,
+            };
+            return Ok(vec![store(into, &bits)?]);
+        }
+        Jump(_) | JGre(_) | JLess(_) | JEq(_) | JNeq(_) | JINeg(..) | JFNeg(..) => return Ok(Vec::new()),
+    };
+    let mut lines = vec![
+        format!("let a = {};", operand(a, domain)?),
+        format!("let b = {};", operand(b, domain)?),
+    ];
+    match command {
+        FDiv(..) => lines.push("if b == 0.0 {\n    return Err(VmError::DivisionByZero);\n}".to_string()),
+        UDiv(..) | IDiv(..) => lines.push("if b == 0 {\n    return Err(VmError::DivisionByZero);\n}".to_string()),
+        _ => {}
+    }
+    lines.push(store(into, op)?);
+    Ok(lines)
+}
+
+/// The statements ending the block that ends with `command`, where `end` is the
+/// address after it. The last one is the expression for the next state.
+fn next(command: &Command, program: &Program, end: u64) -> Result<Vec<String>, CodegenError> {
+    use self::Command::*;
+
+    let target = match command.label() {
+        Some(label) => program.labels()[label],
+        None => return Ok(vec![end.to_string()]),
+    };
+    let ordering = match *command {
+        Jump(_) => return Ok(vec![target.to_string()]),
+        JGre(_) => "compared == Ordering::Greater",
+        JLess(_) => "compared == Ordering::Less",
+        JEq(_) => "compared == Ordering::Equal",
+        JNeq(_) => "compared != Ordering::Equal",
+        JINeg(number, _) => {
+            return Ok(vec![
+                format!("let a = {};", operand(number, Domain::Signed)?),
+                format!("if a < 0 {{ {} }} else {{ {} }}", target, end),
+            ]);
+        }
+        JFNeg(number, _) => {
+            return Ok(vec![
+                format!("let a = {};", operand(number, Domain::Float)?),
+                format!("if a < 0.0 {{ {} }} else {{ {} }}", target, end),
+            ]);
+        }
+        _ => unreachable!(),
+    };
+    Ok(vec![
+        "let compared = compare.ok_or(VmError::JumpWithoutCompare)?;".to_string(),
+        format!("if {} {{ {} }} else {{ {} }}", ordering, target, end),
+    ])
+}
@@ -0,0 +1,304 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::mpsc::channel;
+use std::task::Poll;
+
+use super::{
+    bounded_channel, unbounded_channel, BinarySink, BinarySource, BroadcastSink, CollectSink, IterSource, NumberFormat, Sink,
+    SinkError, Source, TextSink, TextSource,
+};
+
+fn take_all<T, S: Source<T>>(source: &mut S) -> Vec<T> {
+    let mut taken = Vec::new();
+    while let Some(number) = source.take() {
+        taken.push(number);
+    }
+    taken
+}
+
+#[test]
+fn text_source_unsigned_test() {
+    let mut source = TextSource::new("1 2\n\n  3\t4\n5".as_bytes(), NumberFormat::Unsigned);
+    assert_eq!(take_all(&mut source), vec![1, 2, 3, 4, 5]);
+    assert!(source.error().is_none());
+}
+
+#[test]
+fn text_source_signed_and_float_test() {
+    let mut source = TextSource::new("-1 7".as_bytes(), NumberFormat::Signed);
+    assert_eq!(take_all(&mut source), vec![-1i64 as u64, 7]);
+
+    let mut source = TextSource::new("0.5 -2".as_bytes(), NumberFormat::Float);
+    assert_eq!(take_all(&mut source), vec![0.5f64.to_bits(), (-2f64).to_bits()]);
+}
+
+#[test]
+fn text_source_invalid_number_test() {
+    let mut source = TextSource::new("1 2\nthree 4".as_bytes(), NumberFormat::Unsigned);
+    assert_eq!(take_all(&mut source), vec![1, 2]);
+    assert_eq!(source.error().unwrap().kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn text_sink_test() {
+    let mut written = Vec::new();
+    {
+        let mut sink = TextSink::new(&mut written, NumberFormat::Signed);
+        sink.put(3).unwrap();
+        sink.put(-4i64 as u64).unwrap();
+    }
+    {
+        let mut sink = TextSink::new(&mut written, NumberFormat::Float);
+        sink.put(1.5f64.to_bits()).unwrap();
+        sink.put(2f64.to_bits()).unwrap();
+    }
+    assert_eq!(String::from_utf8(written).unwrap(), "3\n-4\n1.5\n2\n");
+}
+
+#[test]
+fn text_round_trip_test() {
+    let numbers = vec![0, 1, u64::MAX, 1 << 40];
+    let mut written = Vec::new();
+    {
+        let mut sink = TextSink::new(&mut written, NumberFormat::Unsigned);
+        for &number in &numbers {
+            sink.put(number).unwrap();
+        }
+    }
+    let mut source = TextSource::new(written.as_slice(), NumberFormat::Unsigned);
+    assert_eq!(take_all(&mut source), numbers);
+}
+
+#[test]
+fn binary_round_trip_test() {
+    let numbers = vec![0, 1, u64::MAX, 0x0102_0304_0506_0708];
+    let mut written = Vec::new();
+    {
+        let mut sink = BinarySink::new(&mut written);
+        for &number in &numbers {
+            sink.put(number).unwrap();
+        }
+    }
+    assert_eq!(&written[24..32], &[8, 7, 6, 5, 4, 3, 2, 1]);
+    let mut source = BinarySource::new(written.as_slice());
+    assert_eq!(take_all(&mut source), numbers);
+    assert!(source.error().is_none());
+}
+
+#[test]
+fn binary_source_partial_number_test() {
+    let bytes = [1, 0, 0, 0, 0, 0, 0, 0, 2, 0];
+    let mut source = BinarySource::new(&bytes[..]);
+    assert_eq!(take_all(&mut source), vec![1]);
+    assert_eq!(source.error().unwrap().kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn source_map_filter_test() {
+    let mut source = vec![1u64, 2, 3, 4, 5].filter(|n| n % 2 == 1).map(|n| n * 10);
+    assert_eq!(take_all(&mut source), vec![10, 30, 50]);
+}
+
+#[test]
+fn source_chain_limit_test() {
+    let mut source = vec![1, 2].chain(VecDeque::from(vec![3, 4, 5])).limit(4);
+    assert_eq!(take_all(&mut source), vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn source_cycle_test() {
+    let mut source = vec![1, 2, 3].cycle().limit(7);
+    assert_eq!(take_all(&mut source), vec![1, 2, 3, 1, 2, 3, 1]);
+
+    let mut empty = Vec::<u64>::new().cycle();
+    assert_eq!(empty.take(), None);
+}
+
+#[test]
+fn source_zip_test() {
+    let mut source = vec![1, 2, 3].zip("ab".to_string());
+    assert_eq!(take_all(&mut source), vec![(1, 'a'), (2, 'b')]);
+}
+
+#[test]
+fn iter_source_test() {
+    let mut source = IterSource((0..3).map(|n| n * 2));
+    assert_eq!(take_all(&mut source), vec![0, 2, 4]);
+}
+
+#[test]
+fn source_by_reference_test() {
+    let mut numbers = vec![1, 2, 3];
+    assert_eq!(take_all(&mut (&mut numbers).limit(2)), vec![1, 2]);
+    assert_eq!(numbers, vec![3]);
+}
+
+#[test]
+fn sink_map_filter_test() {
+    let mut collected = Vec::new();
+    {
+        let mut sink = (&mut collected).with(|n: u64| n + 1).with_filter(|n: &u64| *n > 2);
+        sink.put(1).unwrap();
+        sink.put(2).unwrap();
+        sink.put(3).unwrap();
+    }
+    // The outermost adapter sees things first, so the filter runs before the map.
+    assert_eq!(collected, vec![4]);
+}
+
+#[test]
+fn sink_tee_and_broadcast_test() {
+    let mut first = Vec::new();
+    let mut second = VecDeque::new();
+    {
+        let mut sink = (&mut first).tee(&mut second);
+        sink.put(1).unwrap();
+        sink.put(2).unwrap();
+    }
+    assert_eq!(first, vec![1, 2]);
+    assert_eq!(second, VecDeque::from(vec![1, 2]));
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut sink = BroadcastSink::new(vec![Box::new(sender.clone()), Box::new(sender)]);
+    sink.put(7).unwrap();
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![7, 7]);
+}
+
+#[test]
+fn tee_and_broadcast_retry_test() {
+    let mut all = Vec::new();
+    let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+    {
+        let mut sink = (&mut all).tee(sender.clone());
+        sink.put(1).unwrap();
+        sink.put(2).unwrap();
+        assert_eq!(sink.put(3), Err(SinkError::Full));
+        assert_eq!(sink.put(3), Err(SinkError::Full));
+        assert_eq!(receiver.recv(), Ok(1));
+        sink.put(3).unwrap();
+        assert_eq!(receiver.recv(), Ok(2));
+        sink.close();
+    }
+    assert_eq!(all, vec![1, 2, 3]);
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![3]);
+
+    let (collected, collector) = std::sync::mpsc::channel();
+    let mut sink = BroadcastSink::new(vec![Box::new(collected), Box::new(sender)]);
+    sink.put(4).unwrap();
+    sink.put(5).unwrap();
+    assert_eq!(sink.put(6), Err(SinkError::Full));
+    assert_eq!(receiver.recv(), Ok(4));
+    sink.put(6).unwrap();
+    assert_eq!(collector.try_iter().collect::<Vec<_>>(), vec![4, 5, 6]);
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![5]);
+}
+
+#[test]
+fn collect_sink_test() {
+    let mut sink = CollectSink::with_capacity(2);
+    sink.put(1).unwrap();
+    sink.put(2).unwrap();
+    assert_eq!(sink.put(3), Err(SinkError::Full));
+    assert_eq!(sink.collected(), &[1, 2]);
+    sink.close();
+    assert_eq!(sink.put(3), Err(SinkError::Closed));
+    assert_eq!(sink.into_inner(), vec![1, 2]);
+}
+
+#[test]
+fn closed_sink_test() {
+    let (mut sender, receiver) = std::sync::mpsc::channel();
+    sender.put(1).unwrap();
+    drop(receiver);
+    assert_eq!(sender.put(2), Err(SinkError::Closed));
+
+    let mut written = Vec::new();
+    let mut sink = TextSink::new(&mut written, NumberFormat::Unsigned);
+    sink.put(1).unwrap();
+    sink.close();
+    assert_eq!(sink.put(2), Err(SinkError::Closed));
+}
+
+#[test]
+fn sync_sender_full_test() {
+    let (mut sender, receiver) = std::sync::mpsc::sync_channel(1);
+    sender.put(1).unwrap();
+    assert_eq!(sender.put(2), Err(SinkError::Full));
+    assert_eq!(receiver.recv().unwrap(), 1);
+    sender.put(3).unwrap();
+}
+
+#[test]
+fn receiver_poll_test() {
+    let (sender, mut receiver) = channel();
+    assert_eq!(receiver.poll(), Poll::Pending);
+    sender.send(1).unwrap();
+    assert_eq!(receiver.poll(), Poll::Ready(Some(1)));
+    drop(sender);
+    assert_eq!(receiver.poll(), Poll::Ready(None));
+}
+
+#[test]
+fn combinator_poll_test() {
+    let (sender, receiver) = channel();
+    let mut source = receiver.filter(|n| n % 2 == 0).map(|n| n + 1).limit(2);
+    assert_eq!(source.poll(), Poll::Pending);
+    sender.send(1).unwrap();
+    sender.send(2).unwrap();
+    assert_eq!(source.poll(), Poll::Ready(Some(3)));
+    assert_eq!(source.poll(), Poll::Pending);
+    sender.send(4).unwrap();
+    assert_eq!(source.poll(), Poll::Ready(Some(5)));
+    assert_eq!(source.poll(), Poll::Ready(None));
+}
+
+#[test]
+fn zip_poll_keeps_first_test() {
+    let (sender, receiver) = channel();
+    let mut source = vec![1, 2].zip(receiver);
+    assert_eq!(source.poll(), Poll::Pending);
+    assert_eq!(source.poll(), Poll::Pending);
+    sender.send('a').unwrap();
+    assert_eq!(source.poll(), Poll::Ready(Some((1, 'a'))));
+    drop(sender);
+    assert_eq!(source.poll(), Poll::Ready(None));
+}
+
+#[test]
+fn bounded_channel_test() {
+    let (mut sink, mut source) = bounded_channel(2);
+    sink.put(1).unwrap();
+    sink.put(2).unwrap();
+    assert_eq!(sink.put(3), Err(SinkError::Full));
+    assert_eq!(source.poll(), Poll::Ready(Some(1)));
+    sink.put(3).unwrap();
+    assert_eq!(source.poll(), Poll::Ready(Some(2)));
+    assert_eq!(source.poll(), Poll::Ready(Some(3)));
+    assert_eq!(source.poll(), Poll::Pending);
+    sink.put(4).unwrap();
+    drop(sink);
+    assert_eq!(source.poll(), Poll::Ready(Some(4)));
+    assert_eq!(source.poll(), Poll::Ready(None));
+}
+
+#[test]
+fn rendezvous_channel_test() {
+    let (mut sink, mut source) = bounded_channel(0);
+    assert_eq!(sink.put(1), Err(SinkError::Full));
+    assert_eq!(source.poll(), Poll::Pending);
+    sink.put(1).unwrap();
+    assert_eq!(sink.put(2), Err(SinkError::Full));
+    assert_eq!(source.poll(), Poll::Ready(Some(1)));
+    assert_eq!(sink.put(2), Err(SinkError::Full));
+}
+
+#[test]
+fn unbounded_channel_test() {
+    let (mut sink, mut source) = unbounded_channel();
+    for n in 0..1000 {
+        sink.put(n).unwrap();
+    }
+    assert_eq!(take_all(&mut source).len(), 1000);
+    drop(source);
+    assert_eq!(sink.put(0), Err(SinkError::Closed));
+}
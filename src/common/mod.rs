@@ -0,0 +1,1016 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read, Stdin, Stdout, Write};
+use std::marker::PhantomData;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::{SyncSender, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+
+#[cfg(test)]
+mod common_test;
+
+///´Source´'s are sources for some type T. Taking from a source returns an optional.
+/// While a ´Source´ has things it should return Some(T).
+/// If the ´Source´ permanently runs out of things it should return None signaling to
+/// the user of the source that they should move on to do other things.
+pub trait Source<T> {
+    fn take(&mut self) -> Option<T>;
+
+    /// Like `take` but never waits. Returns `Poll::Pending` if the source has nothing
+    /// right now but might later, `Poll::Ready(None)` if it has permanently run out.
+    /// Sources that never have to wait can rely on the default which just calls `take`.
+    fn poll(&mut self) -> Poll<Option<T>> {
+        Poll::Ready(self.take())
+    }
+
+    fn map<U, F>(self, f: F) -> MapSource<Self, F, T>
+    where
+        Self: Sized,
+        F: FnMut(T) -> U,
+    {
+        MapSource {
+            source: self,
+            f,
+            phantom: PhantomData,
+        }
+    }
+
+    fn filter<P>(self, predicate: P) -> FilterSource<Self, P>
+    where
+        Self: Sized,
+        P: FnMut(&T) -> bool,
+    {
+        FilterSource {
+            source: self,
+            predicate,
+        }
+    }
+
+    /// Takes from this source until it runs out and then from `other`.
+    fn chain<S>(self, other: S) -> ChainSource<Self, S>
+    where
+        Self: Sized,
+        S: Source<T>,
+    {
+        ChainSource {
+            first: self,
+            second: other,
+            first_done: false,
+        }
+    }
+
+    /// Runs out after `n` things have been taken.
+    /// This is `take(n)` on an `Iterator` but `take` is already taken.
+    fn limit(self, n: usize) -> LimitSource<Self>
+    where
+        Self: Sized,
+    {
+        LimitSource {
+            source: self,
+            remaining: n,
+        }
+    }
+
+    /// Repeats everything this source returned once it runs out.
+    fn cycle(self) -> CycleSource<Self, T>
+    where
+        Self: Sized,
+        T: Clone,
+    {
+        CycleSource {
+            source: Some(self),
+            taken: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Pairs things from this source with things from `other`.
+    /// Runs out as soon as either of them does.
+    fn zip<U, S>(self, other: S) -> ZipSource<Self, S, T>
+    where
+        Self: Sized,
+        S: Source<U>,
+    {
+        ZipSource {
+            first: self,
+            second: other,
+            waiting: None,
+        }
+    }
+}
+
+impl<T, S: Source<T> + ?Sized> Source<T> for &mut S {
+    fn take(&mut self) -> Option<T> {
+        (**self).take()
+    }
+
+    fn poll(&mut self) -> Poll<Option<T>> {
+        (**self).poll()
+    }
+}
+
+impl<T, S: Source<T> + ?Sized> Source<T> for Box<S> {
+    fn take(&mut self) -> Option<T> {
+        (**self).take()
+    }
+
+    fn poll(&mut self) -> Poll<Option<T>> {
+        (**self).poll()
+    }
+}
+
+impl<T> Source<T> for Vec<T> {
+    fn take(&mut self) -> Option<T> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.remove(0))
+        }
+    }
+}
+
+impl Source<char> for String {
+    fn take(&mut self) -> Option<char> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.remove(0))
+        }
+    }
+}
+
+impl<T> Source<T> for VecDeque<T> {
+    fn take(&mut self) -> Option<T> {
+        self.pop_front()
+    }
+}
+
+/// `take` waits for the sender, `poll` doesn't.
+impl<T> Source<T> for Receiver<T> {
+    fn take(&mut self) -> Option<T> {
+        self.recv().ok()
+    }
+
+    fn poll(&mut self) -> Poll<Option<T>> {
+        match self.try_recv() {
+            Ok(thing) => Poll::Ready(Some(thing)),
+            Err(TryRecvError::Empty) => Poll::Pending,
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+        }
+    }
+}
+
+/// ´Sink´s are things take take in some type T. Generaly Sinks are used in tandem
+/// with sources so that if something is put into a sink it should appear in a source somewhere.
+/// Putting into a sink fails if the sink is closed or, for bounded sinks, full. The thing
+/// is dropped in that case and has to be put again once the sink has room for it.
+pub trait Sink<T> {
+    fn put(&mut self, thing: T) -> Result<(), SinkError>;
+
+    /// Signals that nothing more will be put into this sink.
+    /// Sinks that have nothing to release can ignore this.
+    fn close(&mut self) {}
+
+    /// Puts `f(thing)` into this sink for every `thing` put into the returned sink.
+    /// This is the sink side `map`. It has a different name because many types, like
+    /// `Vec`, are both sources and sinks and `map` would be ambiguous for them.
+    fn with<U, F>(self, f: F) -> MapSink<Self, F, T>
+    where
+        Self: Sized,
+        F: FnMut(U) -> T,
+    {
+        MapSink {
+            sink: self,
+            f,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Only puts the things matching `predicate` into this sink.
+    fn with_filter<P>(self, predicate: P) -> FilterSink<Self, P>
+    where
+        Self: Sized,
+        P: FnMut(&T) -> bool,
+    {
+        FilterSink {
+            sink: self,
+            predicate,
+        }
+    }
+
+    /// Puts everything into both this sink and `other`.
+    fn tee<S>(self, other: S) -> TeeSink<Self, S, T>
+    where
+        Self: Sized,
+        S: Sink<T>,
+        T: Clone,
+    {
+        TeeSink {
+            first: self,
+            second: other,
+            first_pending: None,
+            second_pending: None,
+        }
+    }
+}
+
+impl<T, S: Sink<T> + ?Sized> Sink<T> for &mut S {
+    fn put(&mut self, thing: T) -> Result<(), SinkError> {
+        (**self).put(thing)
+    }
+
+    fn close(&mut self) {
+        (**self).close();
+    }
+}
+
+impl<T, S: Sink<T> + ?Sized> Sink<T> for Box<S> {
+    fn put(&mut self, thing: T) -> Result<(), SinkError> {
+        (**self).put(thing)
+    }
+
+    fn close(&mut self) {
+        (**self).close();
+    }
+}
+
+impl<T> Sink<T> for Vec<T> {
+    fn put(&mut self, thing: T) -> Result<(), SinkError> {
+        self.push(thing);
+        Ok(())
+    }
+}
+
+impl<T> Sink<T> for VecDeque<T> {
+    fn put(&mut self, thing: T) -> Result<(), SinkError> {
+        self.push_back(thing);
+        Ok(())
+    }
+}
+
+/// The sink is closed once the receiving end has been dropped.
+/// Closing the sink itself is done by dropping it.
+impl<T> Sink<T> for Sender<T> {
+    fn put(&mut self, thing: T) -> Result<(), SinkError> {
+        self.send(thing).map_err(|_| SinkError::Closed)
+    }
+}
+
+/// A bounded channel is full when its buffer is. Closing works like with `Sender`.
+impl<T> Sink<T> for SyncSender<T> {
+    fn put(&mut self, thing: T) -> Result<(), SinkError> {
+        self.try_send(thing).map_err(|err| match err {
+            TrySendError::Full(_) => SinkError::Full,
+            TrySendError::Disconnected(_) => SinkError::Closed,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SinkError {
+    /// Nothing more can be put into the sink.
+    Closed,
+    /// The sink can't take anything right now but might later.
+    Full,
+}
+
+/// Creates a channel that holds at most `capacity` things. Unlike with `mpsc`
+/// channels, taking from an empty channel never waits, so when machines connected
+/// by these channels are stepped on a single thread nothing depends on timing.
+///
+/// With a capacity of 0 the channel is a rendezvous: a put only succeeds while the
+/// receiving end is waiting, that is after it has polled the empty channel and
+/// before it has taken anything.
+pub fn bounded_channel<T>(capacity: usize) -> (ChannelSink<T>, ChannelSource<T>) {
+    channel_with_capacity(Some(capacity))
+}
+
+/// Like `bounded_channel` but puts never fail because the channel is full.
+pub fn unbounded_channel<T>() -> (ChannelSink<T>, ChannelSource<T>) {
+    channel_with_capacity(None)
+}
+
+fn channel_with_capacity<T>(capacity: Option<usize>) -> (ChannelSink<T>, ChannelSource<T>) {
+    let state = Arc::new(Mutex::new(ChannelState {
+        queue: VecDeque::new(),
+        capacity,
+        receiver_waiting: false,
+        sink_closed: false,
+        source_dropped: false,
+    }));
+    (
+        ChannelSink {
+            state: state.clone(),
+        },
+        ChannelSource { state },
+    )
+}
+
+struct ChannelState<T> {
+    queue: VecDeque<T>,
+    capacity: Option<usize>,
+    receiver_waiting: bool,
+    sink_closed: bool,
+    source_dropped: bool,
+}
+
+/// The sending end of a `bounded_channel`. Dropping it closes the channel.
+pub struct ChannelSink<T> {
+    state: Arc<Mutex<ChannelState<T>>>,
+}
+
+impl<T> Sink<T> for ChannelSink<T> {
+    fn put(&mut self, thing: T) -> Result<(), SinkError> {
+        let mut state = self.state.lock().unwrap();
+        if state.sink_closed || state.source_dropped {
+            return Err(SinkError::Closed);
+        }
+        let has_room = match state.capacity {
+            Some(0) => state.queue.is_empty() && state.receiver_waiting,
+            Some(capacity) => state.queue.len() < capacity,
+            None => true,
+        };
+        if !has_room {
+            return Err(SinkError::Full);
+        }
+        state.queue.push_back(thing);
+        state.receiver_waiting = false;
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.state.lock().unwrap().sink_closed = true;
+    }
+}
+
+impl<T> Drop for ChannelSink<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// The receiving end of a `bounded_channel`. Dropping it closes the channel.
+/// `take` doesn't wait for the sink so it returns `None` both when the channel is
+/// empty and when it's closed.
+pub struct ChannelSource<T> {
+    state: Arc<Mutex<ChannelState<T>>>,
+}
+
+impl<T> Source<T> for ChannelSource<T> {
+    fn take(&mut self) -> Option<T> {
+        self.state.lock().unwrap().queue.pop_front()
+    }
+
+    fn poll(&mut self) -> Poll<Option<T>> {
+        let mut state = self.state.lock().unwrap();
+        match state.queue.pop_front() {
+            Some(thing) => {
+                state.receiver_waiting = false;
+                Poll::Ready(Some(thing))
+            }
+            None if state.sink_closed => Poll::Ready(None),
+            None => {
+                state.receiver_waiting = true;
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for ChannelSource<T> {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().source_dropped = true;
+    }
+}
+
+pub struct MapSource<S, F, T> {
+    source: S,
+    f: F,
+    phantom: PhantomData<fn() -> T>,
+}
+
+impl<T, U, S: Source<T>, F: FnMut(T) -> U> Source<U> for MapSource<S, F, T> {
+    fn take(&mut self) -> Option<U> {
+        self.source.take().map(&mut self.f)
+    }
+
+    fn poll(&mut self) -> Poll<Option<U>> {
+        let f = &mut self.f;
+        self.source.poll().map(|thing| thing.map(f))
+    }
+}
+
+pub struct FilterSource<S, P> {
+    source: S,
+    predicate: P,
+}
+
+impl<T, S: Source<T>, P: FnMut(&T) -> bool> Source<T> for FilterSource<S, P> {
+    fn take(&mut self) -> Option<T> {
+        while let Some(thing) = self.source.take() {
+            if (self.predicate)(&thing) {
+                return Some(thing);
+            }
+        }
+        None
+    }
+
+    fn poll(&mut self) -> Poll<Option<T>> {
+        loop {
+            match self.source.poll() {
+                Poll::Ready(Some(thing)) => {
+                    if (self.predicate)(&thing) {
+                        return Poll::Ready(Some(thing));
+                    }
+                }
+                other => return other,
+            }
+        }
+    }
+}
+
+pub struct ChainSource<A, B> {
+    first: A,
+    second: B,
+    first_done: bool,
+}
+
+impl<T, A: Source<T>, B: Source<T>> Source<T> for ChainSource<A, B> {
+    fn take(&mut self) -> Option<T> {
+        if !self.first_done {
+            match self.first.take() {
+                Some(thing) => return Some(thing),
+                None => self.first_done = true,
+            }
+        }
+        self.second.take()
+    }
+
+    fn poll(&mut self) -> Poll<Option<T>> {
+        if !self.first_done {
+            match self.first.poll() {
+                Poll::Ready(None) => self.first_done = true,
+                other => return other,
+            }
+        }
+        self.second.poll()
+    }
+}
+
+pub struct LimitSource<S> {
+    source: S,
+    remaining: usize,
+}
+
+impl<T, S: Source<T>> Source<T> for LimitSource<S> {
+    fn take(&mut self) -> Option<T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        self.source.take()
+    }
+
+    fn poll(&mut self) -> Poll<Option<T>> {
+        if self.remaining == 0 {
+            return Poll::Ready(None);
+        }
+        let polled = self.source.poll();
+        if let Poll::Ready(Some(_)) = polled {
+            self.remaining -= 1;
+        }
+        polled
+    }
+}
+
+pub struct CycleSource<S, T> {
+    source: Option<S>,
+    taken: Vec<T>,
+    position: usize,
+}
+
+impl<T: Clone, S: Source<T>> CycleSource<S, T> {
+    fn repeat(&mut self) -> Option<T> {
+        if self.taken.is_empty() {
+            return None;
+        }
+        let thing = self.taken[self.position].clone();
+        self.position = (self.position + 1) % self.taken.len();
+        Some(thing)
+    }
+}
+
+impl<T: Clone, S: Source<T>> Source<T> for CycleSource<S, T> {
+    fn take(&mut self) -> Option<T> {
+        if let Some(source) = &mut self.source {
+            match source.take() {
+                Some(thing) => {
+                    self.taken.push(thing.clone());
+                    return Some(thing);
+                }
+                None => self.source = None,
+            }
+        }
+        self.repeat()
+    }
+
+    fn poll(&mut self) -> Poll<Option<T>> {
+        if let Some(source) = &mut self.source {
+            match source.poll() {
+                Poll::Ready(Some(thing)) => {
+                    self.taken.push(thing.clone());
+                    return Poll::Ready(Some(thing));
+                }
+                Poll::Ready(None) => self.source = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(self.repeat())
+    }
+}
+
+pub struct ZipSource<A, B, T> {
+    first: A,
+    second: B,
+    // What the first source returned while the second one was pending.
+    waiting: Option<T>,
+}
+
+impl<T, U, A: Source<T>, B: Source<U>> Source<(T, U)> for ZipSource<A, B, T> {
+    fn take(&mut self) -> Option<(T, U)> {
+        let first = match self.waiting.take() {
+            Some(first) => first,
+            None => self.first.take()?,
+        };
+        let second = self.second.take()?;
+        Some((first, second))
+    }
+
+    fn poll(&mut self) -> Poll<Option<(T, U)>> {
+        let first = match self.waiting.take() {
+            Some(first) => first,
+            None => match self.first.poll() {
+                Poll::Ready(Some(first)) => first,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            },
+        };
+        match self.second.poll() {
+            Poll::Ready(Some(second)) => Poll::Ready(Some((first, second))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => {
+                self.waiting = Some(first);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Turns any `Iterator` into a `Source`.
+pub struct IterSource<I>(pub I);
+
+impl<I: Iterator> Source<I::Item> for IterSource<I> {
+    fn take(&mut self) -> Option<I::Item> {
+        self.0.next()
+    }
+}
+
+pub struct MapSink<S, F, T> {
+    sink: S,
+    f: F,
+    phantom: PhantomData<fn(T)>,
+}
+
+impl<T, U, S: Sink<T>, F: FnMut(U) -> T> Sink<U> for MapSink<S, F, T> {
+    fn put(&mut self, thing: U) -> Result<(), SinkError> {
+        self.sink.put((self.f)(thing))
+    }
+
+    fn close(&mut self) {
+        self.sink.close();
+    }
+}
+
+pub struct FilterSink<S, P> {
+    sink: S,
+    predicate: P,
+}
+
+impl<T, S: Sink<T>, P: FnMut(&T) -> bool> Sink<T> for FilterSink<S, P> {
+    fn put(&mut self, thing: T) -> Result<(), SinkError> {
+        if (self.predicate)(&thing) {
+            self.sink.put(thing)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn close(&mut self) {
+        self.sink.close();
+    }
+}
+
+pub struct TeeSink<A, B, T> {
+    first: A,
+    second: B,
+    first_pending: Option<T>,
+    second_pending: Option<T>,
+}
+
+/// Things are put into both sinks even if the first one fails. A sink that is full
+/// keeps the thing until the next `put`, which first hands it over and is refused
+/// with `Full` as long as that doesn't work. That way retrying after `Full` never
+/// puts a thing twice into the sink that did accept it.
+impl<T: Clone, A: Sink<T>, B: Sink<T>> Sink<T> for TeeSink<A, B, T> {
+    fn put(&mut self, thing: T) -> Result<(), SinkError> {
+        let first = flush(&mut self.first, &mut self.first_pending);
+        let second = flush(&mut self.second, &mut self.second_pending);
+        if first == Err(SinkError::Full) || second == Err(SinkError::Full) {
+            return Err(SinkError::Full);
+        }
+        let first = put_or_keep(&mut self.first, &mut self.first_pending, thing.clone());
+        let second = put_or_keep(&mut self.second, &mut self.second_pending, thing);
+        first.and(second)
+    }
+
+    fn close(&mut self) {
+        let _ = flush(&mut self.first, &mut self.first_pending);
+        let _ = flush(&mut self.second, &mut self.second_pending);
+        self.first.close();
+        self.second.close();
+    }
+}
+
+/// Puts everything into every one of its sinks.
+/// Errors and full sinks are handled like with `TeeSink`.
+pub struct BroadcastSink<T> {
+    sinks: Vec<Box<dyn Sink<T> + Send>>,
+    pending: Vec<Option<T>>,
+}
+
+impl<T> BroadcastSink<T> {
+    pub fn new(sinks: Vec<Box<dyn Sink<T> + Send>>) -> Self {
+        let pending = sinks.iter().map(|_| None).collect();
+        BroadcastSink { sinks, pending }
+    }
+}
+
+impl<T: Clone> Sink<T> for BroadcastSink<T> {
+    fn put(&mut self, thing: T) -> Result<(), SinkError> {
+        let mut full = false;
+        for (sink, pending) in self.sinks.iter_mut().zip(&mut self.pending) {
+            full |= flush(sink.as_mut(), pending) == Err(SinkError::Full);
+        }
+        if full {
+            return Err(SinkError::Full);
+        }
+        let mut result = Ok(());
+        for (sink, pending) in self.sinks.iter_mut().zip(&mut self.pending) {
+            result = result.and(put_or_keep(sink.as_mut(), pending, thing.clone()));
+        }
+        result
+    }
+
+    fn close(&mut self) {
+        for (sink, pending) in self.sinks.iter_mut().zip(&mut self.pending) {
+            let _ = flush(sink.as_mut(), pending);
+            sink.close();
+        }
+    }
+}
+
+/// Hands the thing a full sink refused earlier over to it again.
+fn flush<T: Clone, S: Sink<T> + ?Sized>(sink: &mut S, pending: &mut Option<T>) -> Result<(), SinkError> {
+    if let Some(thing) = pending.take() {
+        put_or_keep(sink, pending, thing)?;
+        if pending.is_some() {
+            return Err(SinkError::Full);
+        }
+    }
+    Ok(())
+}
+
+/// Puts the thing into the sink, keeping it in `pending` if the sink is full.
+fn put_or_keep<T: Clone, S: Sink<T> + ?Sized>(sink: &mut S, pending: &mut Option<T>, thing: T) -> Result<(), SinkError> {
+    match sink.put(thing.clone()) {
+        Err(SinkError::Full) => {
+            *pending = Some(thing);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Collects things into a `Vec` until it holds `capacity` of them after which it is full.
+pub struct CollectSink<T> {
+    collected: Vec<T>,
+    capacity: usize,
+    closed: bool,
+}
+
+impl<T> CollectSink<T> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        CollectSink {
+            collected: Vec::new(),
+            capacity,
+            closed: false,
+        }
+    }
+
+    pub fn collected(&self) -> &[T] {
+        &self.collected
+    }
+
+    pub fn into_inner(self) -> Vec<T> {
+        self.collected
+    }
+}
+
+impl<T> Sink<T> for CollectSink<T> {
+    fn put(&mut self, thing: T) -> Result<(), SinkError> {
+        if self.closed {
+            Err(SinkError::Closed)
+        } else if self.collected.len() < self.capacity {
+            self.collected.push(thing);
+            Ok(())
+        } else {
+            Err(SinkError::Full)
+        }
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+    }
+}
+
+/// How numbers are written as text. Machines only deal in `u64`s, so the format
+/// decides whether those bits are read and written as unsigned integers, signed
+/// integers or floats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NumberFormat {
+    Unsigned,
+    Signed,
+    Float,
+}
+
+impl NumberFormat {
+    pub fn parse(self, token: &str) -> Option<u64> {
+        match self {
+            NumberFormat::Unsigned => token.parse().ok(),
+            NumberFormat::Signed => token.parse::<i64>().ok().map(|n| n as u64),
+            NumberFormat::Float => token.parse::<f64>().ok().map(f64::to_bits),
+        }
+    }
+
+    pub fn format(self, value: u64) -> String {
+        match self {
+            NumberFormat::Unsigned => value.to_string(),
+            NumberFormat::Signed => (value as i64).to_string(),
+            NumberFormat::Float => f64::from_bits(value).to_string(),
+        }
+    }
+}
+
+/// Reads whitespace separated numbers from a `BufRead`.
+/// The source runs out at the end of the input or at the first error, which can
+/// then be inspected with `error`.
+pub struct TextSource<R> {
+    reader: R,
+    format: NumberFormat,
+    buffer: VecDeque<u64>,
+    error: Option<io::Error>,
+}
+
+impl<R: BufRead> TextSource<R> {
+    pub fn new(reader: R, format: NumberFormat) -> Self {
+        TextSource {
+            reader,
+            format,
+            buffer: VecDeque::new(),
+            error: None,
+        }
+    }
+
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    fn fill_buffer(&mut self) -> Result<bool, io::Error> {
+        let mut line = String::new();
+        while self.buffer.is_empty() {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(false);
+            }
+            for token in line.split_whitespace() {
+                match self.format.parse(token) {
+                    Some(number) => self.buffer.push_back(number),
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("\"{}\" is not a valid {:?} number", token, self.format),
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+// Not built on `StdinLock` because a locked stdin can't be sent to another thread.
+impl TextSource<BufReader<Stdin>> {
+    pub fn stdin(format: NumberFormat) -> Self {
+        TextSource::new(BufReader::new(io::stdin()), format)
+    }
+}
+
+impl<R: BufRead> Source<u64> for TextSource<R> {
+    fn take(&mut self) -> Option<u64> {
+        if self.buffer.is_empty() && self.error.is_none() {
+            match self.fill_buffer() {
+                Ok(_) => {},
+                Err(err) => self.error = Some(err),
+            }
+        }
+        self.buffer.pop_front()
+    }
+}
+
+/// Writes numbers to a `Write`, one per line.
+/// The sink closes at the first error, which can then be inspected with `error`.
+/// The writer is flushed when the sink is closed or dropped.
+pub struct TextSink<W: Write> {
+    writer: W,
+    format: NumberFormat,
+    error: Option<io::Error>,
+    closed: bool,
+}
+
+impl<W: Write> TextSink<W> {
+    pub fn new(writer: W, format: NumberFormat) -> Self {
+        TextSink {
+            writer,
+            format,
+            error: None,
+            closed: false,
+        }
+    }
+
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl TextSink<Stdout> {
+    pub fn stdout(format: NumberFormat) -> Self {
+        TextSink::new(io::stdout(), format)
+    }
+}
+
+impl<W: Write> Sink<u64> for TextSink<W> {
+    fn put(&mut self, thing: u64) -> Result<(), SinkError> {
+        if self.closed {
+            return Err(SinkError::Closed);
+        }
+        writeln!(self.writer, "{}", self.format.format(thing)).map_err(|err| {
+            self.error = Some(err);
+            self.closed = true;
+            SinkError::Closed
+        })
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        let _ = self.writer.flush();
+    }
+}
+
+impl<W: Write> Drop for TextSink<W> {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Reads raw little-endian `u64`s from a `Read`.
+/// A trailing partial number is reported as an `UnexpectedEof` error.
+pub struct BinarySource<R> {
+    reader: R,
+    error: Option<io::Error>,
+}
+
+impl<R: Read> BinarySource<R> {
+    pub fn new(reader: R) -> Self {
+        BinarySource {
+            reader,
+            error: None,
+        }
+    }
+
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    fn read_number(&mut self) -> Result<Option<u64>, io::Error> {
+        let mut bytes = [0; 8];
+        let mut read = 0;
+        while read < bytes.len() {
+            match self.reader.read(&mut bytes[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the input ended in the middle of a number",
+                    ))
+                }
+                Ok(n) => read += n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(Some(u64::from_le_bytes(bytes)))
+    }
+}
+
+impl BinarySource<Stdin> {
+    pub fn stdin() -> Self {
+        BinarySource::new(io::stdin())
+    }
+}
+
+impl<R: Read> Source<u64> for BinarySource<R> {
+    fn take(&mut self) -> Option<u64> {
+        if self.error.is_some() {
+            return None;
+        }
+        match self.read_number() {
+            Ok(number) => number,
+            Err(err) => {
+                self.error = Some(err);
+                None
+            }
+        }
+    }
+}
+
+/// Writes raw little-endian `u64`s to a `Write`.
+/// The sink closes at the first error, which can then be inspected with `error`.
+/// The writer is flushed when the sink is closed or dropped.
+pub struct BinarySink<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+    closed: bool,
+}
+
+impl<W: Write> BinarySink<W> {
+    pub fn new(writer: W) -> Self {
+        BinarySink {
+            writer,
+            error: None,
+            closed: false,
+        }
+    }
+
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl BinarySink<Stdout> {
+    pub fn stdout() -> Self {
+        BinarySink::new(io::stdout())
+    }
+}
+
+impl<W: Write> Sink<u64> for BinarySink<W> {
+    fn put(&mut self, thing: u64) -> Result<(), SinkError> {
+        if self.closed {
+            return Err(SinkError::Closed);
+        }
+        self.writer.write_all(&thing.to_le_bytes()).map_err(|err| {
+            self.error = Some(err);
+            self.closed = true;
+            SinkError::Closed
+        })
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        let _ = self.writer.flush();
+    }
+}
+
+impl<W: Write> Drop for BinarySink<W> {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
@@ -0,0 +1,97 @@
+use std::env;
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+use super::{Coverage, CoverageError};
+use crate::moo::{parse_program_from_file, parse_program_from_string};
+use crate::profiler::BranchCounts;
+use crate::program::Program;
+use crate::vm::{MooMachine, Status};
+
+const SIGN: &str = "icmp I0 0i;
+jless negative;
+load 1i O0; jump 1f;
+negative: load -1i O0;
+1: load 0i R0;
+";
+
+fn run(program: &Program, input: i64) -> Coverage {
+    let coverage = Arc::new(Mutex::new(Coverage::new(program.clone())));
+    let mut machine = MooMachine::new(
+        program.clone(),
+        vec![Box::new(vec![input as u64])],
+        vec![Box::new(Vec::new())],
+    );
+    machine.add_observer(Box::new(coverage.clone()));
+    while machine.tick().unwrap() != Status::Halted {}
+    drop(machine);
+    Arc::try_unwrap(coverage).ok().unwrap().into_inner().unwrap()
+}
+
+#[test]
+fn coverage_test() {
+    let program = parse_program_from_string(SIGN).unwrap();
+    let coverage = run(&program, 5);
+    assert_eq!(coverage.unexecuted(), vec![4]);
+    assert_eq!(coverage.executed(0), 1);
+    assert_eq!(coverage.branch(1), Some(BranchCounts { taken: 0, not_taken: 1 }));
+    assert_eq!(coverage.branch(3), None);
+}
+
+#[test]
+fn merge_test() {
+    let program = parse_program_from_string(SIGN).unwrap();
+    let mut coverage = run(&program, 5);
+    coverage.merge(&run(&program, -5)).unwrap();
+    coverage.merge(&run(&program, -2)).unwrap();
+    assert_eq!(coverage.unexecuted(), Vec::<u64>::new());
+    assert_eq!(coverage.executed(5), 3);
+    assert_eq!(coverage.branch(1), Some(BranchCounts { taken: 2, not_taken: 1 }));
+
+    let other = parse_program_from_string("load 0i R0;").unwrap();
+    assert_eq!(coverage.merge(&Coverage::new(other)), Err(CoverageError::DifferentPrograms));
+}
+
+#[test]
+fn lcov_test() {
+    let program = parse_program_from_string(SIGN).unwrap();
+    assert_eq!(
+        run(&program, -5).lcov(),
+        "TN:
+SF:main
+BRDA:2,1,0,1
+BRDA:2,1,1,0
+BRF:2
+BRH:1
+DA:1,1
+DA:2,1
+DA:3,0
+DA:4,1
+DA:5,1
+LF:5
+LH:4
+end_of_record
+",
+    );
+    // A jump that never ran has no branch counts at all.
+    let program = parse_program_from_string("jump 1f; jless 1f; 1: load 0i R0;").unwrap();
+    assert!(run(&program, 0).lcov().contains("BRDA:1,1,0,-\nBRDA:1,1,1,-\nBRF:2\nBRH:0\n"));
+}
+
+#[test]
+fn lcov_per_file_test() {
+    let directory = env::temp_dir().join(format!("moofloom_coverage_{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join("main.moo"), ".include \"lib.moo\";\njump double;\n").unwrap();
+    fs::write(directory.join("lib.moo"), ".export double;\n\ndouble: iadd I0 1i O0;\n").unwrap();
+
+    let program = parse_program_from_file(directory.join("main.moo")).unwrap();
+    let lcov = run(&program, 3).lcov();
+    let records: Vec<_> = lcov.split("end_of_record\n").filter(|record| !record.is_empty()).collect();
+    assert_eq!(records.len(), 2);
+    let lib = records.iter().find(|record| record.contains("lib.moo\n")).unwrap();
+    assert!(lib.contains("DA:3,1\n"));
+    let main = records.iter().find(|record| record.contains("main.moo\n")).unwrap();
+    assert!(main.contains("DA:2,1\n"));
+    fs::remove_dir_all(directory).unwrap();
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::profiler::{BranchCounts, Profile};
+use crate::program::{Program, SourceLocation};
+use crate::vm::{Command, MachineObserver, Status};
+
+#[cfg(test)]
+mod coverage_test;
+
+/// Records which instructions of a program were executed and which way its
+/// conditional jumps went. Add it to machines running the program as an observer.
+/// It is a view of the counts of a `Profile`.
+#[derive(Clone, Debug)]
+pub struct Coverage {
+    profile: Profile,
+}
+
+impl Coverage {
+    pub fn new(program: Program) -> Self {
+        Coverage {
+            profile: Profile::new(program),
+        }
+    }
+
+    /// Adds the counts of another run of the same program.
+    pub fn merge(&mut self, other: &Coverage) -> Result<(), CoverageError> {
+        if self.program() != other.program() {
+            return Err(CoverageError::DifferentPrograms);
+        }
+        self.profile.add_counts(&other.profile);
+        Ok(())
+    }
+
+    fn program(&self) -> &Program {
+        self.profile.program()
+    }
+
+    /// How many times the instruction at `address` was executed.
+    pub fn executed(&self, address: u64) -> u64 {
+        self.profile.executed(address)
+    }
+
+    /// The addresses of the instructions that were never executed.
+    pub fn unexecuted(&self) -> Vec<u64> {
+        (0..self.program().len() as u64)
+            .filter(|&address| self.executed(address) == 0)
+            .collect()
+    }
+
+    /// How many times the conditional jump at `address` jumped and fell through, or
+    /// `None` if there's no conditional jump there.
+    pub fn branch(&self, address: u64) -> Option<BranchCounts> {
+        self.profile.branch(address)
+    }
+
+    /// Writes the coverage in the lcov tracefile format with a record for every
+    /// unit, so for programs parsed from files every `.moo` file gets a record
+    /// keyed by its path.
+    ///
+    /// A line is as covered as its most executed instruction. Each conditional jump
+    /// is a branch block numbered by its address, where branch 0 is jumping and 1
+    /// is falling through. Instructions without a source location are left out.
+    pub fn lcov(&self) -> String {
+        let mut units: BTreeMap<&str, Vec<(&SourceLocation, u64)>> = BTreeMap::new();
+        let program = self.program();
+        for address in 0..program.len() as u64 {
+            if let Some(location) = program.location(address) {
+                units.entry(&location.unit).or_default().push((location, address));
+            }
+        }
+
+        let mut lcov = String::new();
+        for (unit, instructions) in units {
+            writeln!(lcov, "TN:").unwrap();
+            writeln!(lcov, "SF:{}", unit).unwrap();
+            let (mut found, mut hit) = (0, 0);
+            for &(location, address) in &instructions {
+                let branch = match self.branch(address) {
+                    Some(branch) => branch,
+                    None => continue,
+                };
+                let executed = self.executed(address) > 0;
+                for (number, count) in [branch.taken, branch.not_taken].iter().enumerate() {
+                    let count = if executed { count.to_string() } else { "-".to_string() };
+                    writeln!(lcov, "BRDA:{},{},{},{}", location.line, address, number, count).unwrap();
+                }
+                found += 2;
+                hit += [branch.taken, branch.not_taken].iter().filter(|&&count| count > 0).count();
+            }
+            if found > 0 {
+                writeln!(lcov, "BRF:{}", found).unwrap();
+                writeln!(lcov, "BRH:{}", hit).unwrap();
+            }
+
+            let mut lines: BTreeMap<usize, u64> = BTreeMap::new();
+            for &(location, address) in &instructions {
+                let count = lines.entry(location.line).or_insert(0);
+                *count = (*count).max(self.executed(address));
+            }
+            for (line, count) in &lines {
+                writeln!(lcov, "DA:{},{}", line, count).unwrap();
+            }
+            writeln!(lcov, "LF:{}", lines.len()).unwrap();
+            writeln!(lcov, "LH:{}", lines.values().filter(|&&count| count > 0).count()).unwrap();
+            writeln!(lcov, "end_of_record").unwrap();
+        }
+        lcov
+    }
+}
+
+impl MachineObserver for Coverage {
+    fn before_instruction(&mut self, address: u64, command: &Command) {
+        self.profile.before_instruction(address, command);
+    }
+
+    fn jump_taken(&mut self, from: u64, to: u64) {
+        self.profile.jump_taken(from, to);
+    }
+
+    fn after_instruction(&mut self, address: u64, status: Status) {
+        self.profile.after_instruction(address, status);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CoverageError {
+    /// Coverage can only be merged between runs of the same program.
+    DifferentPrograms,
+}
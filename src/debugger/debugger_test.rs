@@ -0,0 +1,189 @@
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+
+use super::{Debugger, DebuggerError, Stop, Watchpoint};
+use crate::moo::parse_program_from_string;
+use crate::vm::{Channel, MooMachine, Trace, VmError};
+
+const COUNTDOWN: &str = "
+    load 3i R0;
+    loop: isub R0 1i R0;
+    load R0 O0;
+    icmp R0 0i;
+    jgre loop;
+    load -0.5f R1;
+";
+
+fn debugger(source: &str) -> Debugger {
+    let program = parse_program_from_string(source).unwrap();
+    Debugger::new(MooMachine::new(program, Vec::new(), vec![Box::new(Vec::new())]))
+}
+
+#[test]
+fn step_test() {
+    let mut debugger = debugger(COUNTDOWN);
+    assert_eq!(debugger.step(), Stop::Step);
+    assert_eq!(debugger.step_over(), Stop::Step);
+    assert_eq!(debugger.program_counter(), 2);
+    assert_eq!(debugger.register_i64(0), 2);
+}
+
+#[test]
+fn breakpoint_test() {
+    let mut debugger = debugger(COUNTDOWN);
+    assert_eq!(debugger.break_at_label("loop"), Ok(1));
+    assert_eq!(debugger.break_at_label("nope"), Err(DebuggerError::UnknownLabel("nope".to_string())));
+    assert_eq!(debugger.continue_until_break(), Stop::Breakpoint(1));
+    assert_eq!(debugger.register_u64(0), 3);
+    assert_eq!(debugger.continue_until_break(), Stop::Breakpoint(1));
+    assert_eq!(debugger.register_u64(0), 2);
+    assert_eq!(debugger.compare(), Some(std::cmp::Ordering::Greater));
+
+    assert!(debugger.remove_breakpoint(1));
+    debugger.break_at_address(5);
+    assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), vec![5]);
+    assert_eq!(debugger.continue_until_break(), Stop::Breakpoint(5));
+    assert_eq!(debugger.continue_until_break(), Stop::Halted);
+    assert_eq!(debugger.register_f64(1), -0.5);
+    assert_eq!(debugger.register_i64(0), 0);
+}
+
+#[test]
+fn watchpoint_test() {
+    let mut debugger = debugger(COUNTDOWN);
+    debugger.watch(Watchpoint::Output(0));
+    assert_eq!(
+        debugger.continue_until_break(),
+        Stop::Watchpoint { address: 2, watchpoint: Watchpoint::Output(0), value: 2 },
+    );
+    debugger.watch(Watchpoint::Register(1));
+    assert!(debugger.unwatch(Watchpoint::Output(0)));
+    assert!(!debugger.unwatch(Watchpoint::Output(0)));
+    assert_eq!(
+        debugger.continue_until_break(),
+        Stop::Watchpoint { address: 5, watchpoint: Watchpoint::Register(1), value: (-0.5f64).to_bits() },
+    );
+    assert_eq!(debugger.step(), Stop::Halted);
+}
+
+#[test]
+fn blocked_and_error_test() {
+    let program = parse_program_from_string("load I0 R0; load I0 R0;").unwrap();
+    let (sender, receiver) = channel();
+    let mut debugger = Debugger::new(MooMachine::new(program, vec![Box::new(receiver)], Vec::new()));
+    assert_eq!(debugger.continue_until_break(), Stop::Blocked(Channel::Input(0)));
+    sender.send(1).unwrap();
+    drop(sender);
+    assert_eq!(debugger.continue_until_break(), Stop::Error(VmError::InputClosed(0)));
+    assert_eq!(debugger.into_inner().program_counter(), 1);
+}
+
+#[test]
+fn step_back_test() {
+    let mut debugger = debugger(COUNTDOWN);
+    assert!(!debugger.step_back());
+    debugger.step();
+    debugger.step();
+    assert_eq!(debugger.history_len(), 2);
+    assert!(debugger.step_back());
+    assert_eq!(debugger.program_counter(), 1);
+    assert_eq!(debugger.register_i64(0), 3);
+    assert!(debugger.step_back());
+    assert_eq!(debugger.program_counter(), 0);
+    // R0 was never written before the first tick.
+    assert_eq!(debugger.machine().snapshot().registers.get(&0), None);
+    assert_eq!(debugger.history_len(), 0);
+}
+
+#[test]
+fn history_limit_test() {
+    let mut debugger = debugger(COUNTDOWN);
+    debugger.set_history_limit(2);
+    for _ in 0..3 {
+        debugger.step();
+    }
+    assert_eq!(debugger.history_len(), 2);
+    assert!(debugger.step_back());
+    assert!(debugger.step_back());
+    assert!(!debugger.step_back());
+    assert_eq!(debugger.program_counter(), 1);
+
+    debugger.set_history_limit(0);
+    debugger.step();
+    assert_eq!(debugger.history_len(), 0);
+}
+
+#[test]
+fn keeps_trace_and_observers_test() {
+    let program = parse_program_from_string(COUNTDOWN).unwrap();
+    let mut machine = MooMachine::new(program, Vec::new(), vec![Box::new(Vec::new())]);
+    let observer = Arc::new(Mutex::new(Trace::new(0, 1)));
+    machine.add_observer(Box::new(Arc::clone(&observer)));
+    machine.start_trace();
+
+    let mut debugger = Debugger::new(machine);
+    debugger.step();
+    debugger.step();
+    let mut machine = debugger.into_inner();
+    assert_eq!(machine.take_trace().unwrap().entries().len(), 2);
+    assert_eq!(machine.take_observers().len(), 1);
+    assert_eq!(observer.lock().unwrap().entries().len(), 2);
+}
+
+#[test]
+fn reverse_continue_test() {
+    let mut debugger = debugger(COUNTDOWN);
+    debugger.break_at_address(5);
+    assert_eq!(debugger.continue_until_break(), Stop::Breakpoint(5));
+    assert_eq!(debugger.compare(), Some(std::cmp::Ordering::Equal));
+
+    debugger.watch(Watchpoint::Register(0));
+    // Stops before the tick that last wrote R0.
+    assert_eq!(
+        debugger.reverse_continue(),
+        Stop::Watchpoint { address: 1, watchpoint: Watchpoint::Register(0), value: 0 },
+    );
+    assert_eq!(debugger.program_counter(), 1);
+    assert_eq!(debugger.register_i64(0), 1);
+    assert!(debugger.unwatch(Watchpoint::Register(0)));
+
+    debugger.break_at_label("loop").unwrap();
+    assert_eq!(debugger.reverse_continue(), Stop::Breakpoint(1));
+    assert_eq!(debugger.register_i64(0), 2);
+    assert!(debugger.remove_breakpoint(1));
+    assert_eq!(debugger.reverse_continue(), Stop::Start);
+    assert_eq!(debugger.program_counter(), 0);
+
+    // Running forwards again ends up in the same place.
+    assert_eq!(debugger.continue_until_break(), Stop::Breakpoint(5));
+    assert_eq!(debugger.register_i64(0), 0);
+}
+
+#[test]
+fn step_back_redelivers_inputs_test() {
+    let program = parse_program_from_string("1: uadd I0 I1 R0; uadd R0 I0 R1; jump 1b;").unwrap();
+    let (first_sender, first_receiver) = channel();
+    let (second_sender, second_receiver) = channel();
+    let mut debugger = Debugger::new(MooMachine::new(
+        program,
+        vec![Box::new(first_receiver), Box::new(second_receiver)],
+        Vec::new(),
+    ));
+    first_sender.send(1).unwrap();
+    assert_eq!(debugger.step(), Stop::Blocked(Channel::Input(1)));
+    second_sender.send(2).unwrap();
+    first_sender.send(10).unwrap();
+    assert_eq!(debugger.step(), Stop::Step);
+    assert_eq!(debugger.step(), Stop::Step);
+    assert_eq!(debugger.register_u64(1), 13);
+
+    assert_eq!(debugger.reverse_continue(), Stop::Start);
+    assert_eq!(debugger.register_u64(1), 0);
+    // The same inputs are handed out again and nothing is left blocked this time.
+    assert_eq!(debugger.step(), Stop::Step);
+    assert_eq!(debugger.register_u64(0), 3);
+    assert_eq!(debugger.step(), Stop::Step);
+    assert_eq!(debugger.register_u64(1), 13);
+    assert_eq!(debugger.step(), Stop::Step);
+    assert_eq!(debugger.step(), Stop::Blocked(Channel::Input(0)));
+}
@@ -0,0 +1,252 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::vm::{Channel, MooMachine, ObserverId, Status, Trace, TraceEntry, Undo, VmError};
+
+#[cfg(test)]
+mod debugger_test;
+
+/// How many ticks can be stepped back over when no other limit is set.
+pub const DEFAULT_HISTORY_LIMIT: usize = 100_000;
+
+/// Runs a `MooMachine` under control, stopping at breakpoints and watchpoints.
+///
+/// A breakpoint stops the machine before the instruction at its address is
+/// executed. A watchpoint stops it after an instruction that wrote the watched
+/// register or output channel.
+///
+/// The latest ticks, up to the history limit, are recorded so they can be stepped
+/// back over. Inputs taken by ticks that are stepped back over are handed out again
+/// when the ticks are run again, but outputs are put again.
+pub struct Debugger {
+    machine: MooMachine,
+    breakpoints: BTreeSet<u64>,
+    watchpoints: Vec<Watchpoint>,
+    history: VecDeque<(Undo, TraceEntry)>,
+    history_limit: usize,
+    /// Records what a step did. The machine has it as an observer.
+    recorder: Arc<Mutex<Trace>>,
+    recorder_id: ObserverId,
+}
+
+impl Debugger {
+    pub fn new(mut machine: MooMachine) -> Self {
+        // Only the entries of the recorder are used, so its channels don't matter.
+        let recorder = Arc::new(Mutex::new(Trace::new(0, 0)));
+        let recorder_id = machine.add_observer(Box::new(Arc::clone(&recorder)));
+        Debugger {
+            machine,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            history: VecDeque::new(),
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            recorder,
+            recorder_id,
+        }
+    }
+
+    pub fn machine(&self) -> &MooMachine {
+        &self.machine
+    }
+
+    /// Gives the machine back without the observer the debugger added to it.
+    pub fn into_inner(mut self) -> MooMachine {
+        self.machine.remove_observer(self.recorder_id);
+        self.machine
+    }
+
+    /// Sets how many ticks can be stepped back over. Older ticks are forgotten.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+        while self.history.len() > limit {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn break_at_address(&mut self, address: u64) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Sets a breakpoint at the address of `label` and returns the address.
+    pub fn break_at_label(&mut self, label: &str) -> Result<u64, DebuggerError> {
+        let address = *self
+            .machine
+            .program()
+            .labels()
+            .get(label)
+            .ok_or_else(|| DebuggerError::UnknownLabel(label.to_string()))?;
+        self.breakpoints.insert(address);
+        Ok(address)
+    }
+
+    /// Removes the breakpoint at `address`. Returns whether there was one.
+    pub fn remove_breakpoint(&mut self, address: u64) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u64> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn watch(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    /// Removes `watchpoint`. Returns whether it was being watched.
+    pub fn unwatch(&mut self, watchpoint: Watchpoint) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|&watched| watched != watchpoint);
+        self.watchpoints.len() != count
+    }
+
+    /// Ticks the machine once.
+    pub fn step(&mut self) -> Stop {
+        let address = self.machine.program_counter();
+        let undo = self.machine.undo_point();
+        let result = self.machine.tick();
+        let entry = self.recorder.lock().unwrap().pop();
+        // Ticks after halting don't do anything so there's nothing to record.
+        let hit = entry.and_then(|entry| {
+            let hit = self.hit_watchpoint(&entry);
+            self.history.push_back((undo, entry));
+            if self.history.len() > self.history_limit {
+                self.history.pop_front();
+            }
+            hit
+        });
+        match result {
+            Ok(Status::Running) => hit
+                .map(|(watchpoint, value)| Stop::Watchpoint { address, watchpoint, value })
+                .unwrap_or(Stop::Step),
+            Ok(Status::Blocked { channel }) => Stop::Blocked(channel),
+            Ok(Status::Halted) => Stop::Halted,
+            Err(error) => Stop::Error(error),
+        }
+    }
+
+    /// Moo has no calls yet, so this is the same as `step`.
+    pub fn step_over(&mut self) -> Stop {
+        self.step()
+    }
+
+    /// Ticks the machine until it reaches a breakpoint, hits a watchpoint, blocks,
+    /// halts or fails. The instruction at the program counter is always executed,
+    /// so continuing from a breakpoint doesn't stop at it again straight away.
+    pub fn continue_until_break(&mut self) -> Stop {
+        loop {
+            match self.step() {
+                Stop::Step => {}
+                stop => return stop,
+            }
+            let address = self.machine.program_counter();
+            if self.breakpoints.contains(&address) {
+                return Stop::Breakpoint(address);
+            }
+        }
+    }
+
+    /// Takes back the latest tick. Returns false if there was nothing to take back.
+    pub fn step_back(&mut self) -> bool {
+        self.undo_tick().is_some()
+    }
+
+    /// Steps back until the program counter is at a breakpoint or a tick that hit a
+    /// watchpoint has been taken back. The latest tick is always taken back, so
+    /// reverse continuing from a breakpoint doesn't stop at it again straight away.
+    pub fn reverse_continue(&mut self) -> Stop {
+        while let Some(entry) = self.undo_tick() {
+            if let Some((watchpoint, value)) = self.hit_watchpoint(&entry) {
+                return Stop::Watchpoint {
+                    address: entry.program_counter,
+                    watchpoint,
+                    value,
+                };
+            }
+            let address = self.machine.program_counter();
+            if self.breakpoints.contains(&address) {
+                return Stop::Breakpoint(address);
+            }
+        }
+        Stop::Start
+    }
+
+    /// How many ticks can be stepped back over.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    fn undo_tick(&mut self) -> Option<TraceEntry> {
+        let (undo, entry) = self.history.pop_back()?;
+        self.machine.undo(undo, &entry.inputs);
+        Some(entry)
+    }
+
+    pub fn program_counter(&self) -> u64 {
+        self.machine.program_counter()
+    }
+
+    pub fn compare(&self) -> Option<Ordering> {
+        self.machine.compare()
+    }
+
+    pub fn register_u64(&self, register: u64) -> u64 {
+        self.machine.register(register)
+    }
+
+    pub fn register_i64(&self, register: u64) -> i64 {
+        self.machine.register(register) as i64
+    }
+
+    pub fn register_f64(&self, register: u64) -> f64 {
+        f64::from_bits(self.machine.register(register))
+    }
+
+    fn hit_watchpoint(&self, entry: &TraceEntry) -> Option<(Watchpoint, u64)> {
+        self.watchpoints.iter().find_map(|&watchpoint| {
+            let writes = match watchpoint {
+                Watchpoint::Register(watched) => entry
+                    .register_writes
+                    .iter()
+                    .find(|&&(register, _)| register == watched),
+                Watchpoint::Output(watched) => entry.outputs.iter().find(|&&(channel, _)| channel == watched),
+            };
+            writes.map(|&(_, value)| (watchpoint, value))
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Watchpoint {
+    /// Stops after anything is written to the register.
+    Register(u64),
+    /// Stops after anything is put into the output channel.
+    Output(u64),
+}
+
+/// Why the debugger gave control back.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stop {
+    /// A single step finished without hitting anything.
+    Step,
+    /// The program counter reached the breakpoint at this address.
+    Breakpoint(u64),
+    /// The instruction at `address` wrote `value` to what `watchpoint` watches.
+    Watchpoint {
+        address: u64,
+        watchpoint: Watchpoint,
+        value: u64,
+    },
+    Blocked(Channel),
+    Halted,
+    Error(VmError),
+    /// Stepping back reached the point where debugging started.
+    Start,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DebuggerError {
+    UnknownLabel(String),
+}
@@ -1,5 +1,16 @@
-mod common;
+pub mod cfg;
+pub mod codegen;
+pub mod common;
+pub mod coverage;
+pub mod debugger;
+pub mod linker;
+pub mod lint;
+pub mod loom;
 pub mod moo;
+pub mod optimize;
+pub mod profiler;
 pub mod program;
+pub mod puzzle;
+pub mod typecheck;
 pub mod vm;
 
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use super::{LinkError, Linker};
+use crate::moo::parse_program_from_string;
+use crate::program::Program;
+use crate::vm::Command;
+
+#[test]
+fn link_relocates_labels_test() {
+    let main = parse_program_from_string("start: jump sqrt; jump start;").unwrap();
+    let library = parse_program_from_string(".export sqrt; load 1f R0; sqrt: jump sqrt;").unwrap();
+    let mut linker = Linker::new();
+    linker.add_unit("main", main).add_unit("library", library);
+    let program = linker.link().unwrap();
+
+    let mut labels = HashMap::new();
+    labels.insert("main:start".to_string(), 0);
+    labels.insert("sqrt".to_string(), 3);
+    assert_eq!(program.labels(), &labels);
+    assert_eq!(program[0], Command::Jump("sqrt".to_string()));
+    assert_eq!(program[1], Command::Jump("main:start".to_string()));
+    assert_eq!(program[3], Command::Jump("sqrt".to_string()));
+    assert!(program.exports().contains("sqrt"));
+}
+
+#[test]
+fn private_labels_do_not_clash_test() {
+    let first = parse_program_from_string("loop: jump loop;").unwrap();
+    let second = parse_program_from_string("loop: jump loop;").unwrap();
+    let mut linker = Linker::new();
+    linker.add_unit("first", first).add_unit("second", second);
+    let program = linker.link().unwrap();
+
+    assert_eq!(program.get_address("first:loop"), 0);
+    assert_eq!(program.get_address("second:loop"), 1);
+    assert_eq!(program[0], Command::Jump("first:loop".to_string()));
+    assert_eq!(program[1], Command::Jump("second:loop".to_string()));
+}
+
+#[test]
+fn private_labels_are_not_visible_to_other_units_test() {
+    let main = parse_program_from_string("jump helper;").unwrap();
+    let library = parse_program_from_string("helper: load 1f R0;").unwrap();
+    let mut linker = Linker::new();
+    linker.add_unit("main", main).add_unit("library", library);
+    assert_eq!(
+        linker.link(),
+        Err(LinkError::UndefinedSymbol {
+            symbol: "helper".to_string(),
+            unit: "main".to_string(),
+        })
+    );
+}
+
+#[test]
+fn duplicate_export_test() {
+    let first = parse_program_from_string(".export f; f: load 1f R0;").unwrap();
+    let second = parse_program_from_string(".export f; f: load 2f R0;").unwrap();
+    let mut linker = Linker::new();
+    linker.add_unit("first", first).add_unit("second", second);
+    assert_eq!(
+        linker.link(),
+        Err(LinkError::DuplicateSymbol {
+            symbol: "f".to_string(),
+            first_unit: "first".to_string(),
+            second_unit: "second".to_string(),
+        })
+    );
+}
+
+#[test]
+fn undefined_export_test() {
+    let program = parse_program_from_string(".export missing; load 1f R0;").unwrap();
+    let mut linker = Linker::new();
+    linker.add_unit("main", program);
+    assert_eq!(
+        linker.link(),
+        Err(LinkError::UndefinedExport {
+            symbol: "missing".to_string(),
+            unit: "main".to_string(),
+        })
+    );
+}
+
+#[test]
+fn duplicate_unit_test() {
+    let mut linker = Linker::new();
+    linker
+        .add_unit("main", Program::new(Vec::new(), HashMap::new()))
+        .add_unit("main", Program::new(Vec::new(), HashMap::new()));
+    assert_eq!(linker.link(), Err(LinkError::DuplicateUnit("main".to_string())));
+}
+
+#[test]
+fn register_out_of_range_test() {
+    let program = parse_program_from_string("load 1u R15;").unwrap();
+    let mut linker = Linker::new();
+    linker.set_register_count(16).add_unit("main", program.clone());
+    assert!(linker.link().is_ok());
+
+    let mut linker = Linker::new();
+    linker
+        .set_register_count(15)
+        .add_unit("main", Program::new(Vec::new(), HashMap::new()))
+        .add_unit("library", program);
+    assert_eq!(
+        linker.link(),
+        Err(LinkError::RegisterOutOfRange {
+            register: 15,
+            unit: "library".to_string(),
+        })
+    );
+}
+
+#[test]
+fn entry_unit_keeps_private_labels_test() {
+    let main = parse_program_from_string("start: jump sqrt; jump start;").unwrap();
+    let library = parse_program_from_string(".export sqrt; sqrt: jump start; start: jump sqrt;").unwrap();
+    let mut linker = Linker::new();
+    linker.add_unit("library", library).add_entry_unit("main", main);
+    let program = linker.link().unwrap();
+
+    let mut labels = HashMap::new();
+    labels.insert("start".to_string(), 0);
+    labels.insert("sqrt".to_string(), 2);
+    labels.insert("library:start".to_string(), 3);
+    assert_eq!(program.labels(), &labels);
+    assert_eq!(program[1], Command::Jump("start".to_string()));
+    assert_eq!(program[2], Command::Jump("library:start".to_string()));
+}
+
+#[test]
+fn entry_label_clashes_with_export_test() {
+    let main = parse_program_from_string("sqrt: jump sqrt;").unwrap();
+    let library = parse_program_from_string(".export sqrt; sqrt: jump sqrt;").unwrap();
+    let mut linker = Linker::new();
+    linker.add_entry_unit("main", main).add_unit("library", library);
+    assert_eq!(
+        linker.link(),
+        Err(LinkError::DuplicateSymbol {
+            symbol: "sqrt".to_string(),
+            first_unit: "main".to_string(),
+            second_unit: "library".to_string(),
+        })
+    );
+}
@@ -0,0 +1,218 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::program::Program;
+use crate::vm::Param;
+
+#[cfg(test)]
+mod linker_test;
+
+/// Merges several separately parsed `Program`s into one.
+///
+/// Units are laid out in the order they were added, so the first unit starts at
+/// address 0 and is where execution begins. Exported labels keep their names and
+/// are visible to every unit. Private labels are only visible inside their own unit
+/// and get qualified as `unit:label` in the linked program so that two units can
+/// both have, say, a private `loop` label. Only the private labels of the entry
+/// unit, if there is one, keep their names.
+#[derive(Default)]
+pub struct Linker {
+    units: Vec<(String, Program)>,
+    has_entry: bool,
+    register_count: Option<u64>,
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Linker {
+            units: Vec::new(),
+            has_entry: false,
+            register_count: None,
+        }
+    }
+
+    pub fn add_unit<S: Into<String>>(&mut self, name: S, program: Program) -> &mut Self {
+        self.units.push((name.into(), program));
+        self
+    }
+
+    /// Adds the unit execution starts in, like the main file of a program that
+    /// `.include`s others. It is laid out first and its private labels keep their
+    /// names in the linked program. Adding another entry unit replaces it.
+    pub fn add_entry_unit<S: Into<String>>(&mut self, name: S, program: Program) -> &mut Self {
+        if self.has_entry {
+            self.units.remove(0);
+        }
+        self.units.insert(0, (name.into(), program));
+        self.has_entry = true;
+        self
+    }
+
+    /// Rejects units that use a register past `R{count - 1}`, for programs that are
+    /// run by machines with a fixed number of registers.
+    pub fn set_register_count(&mut self, count: u64) -> &mut Self {
+        self.register_count = Some(count);
+        self
+    }
+
+    pub fn link(self) -> Result<Program, LinkError> {
+        // Collect the exported symbols first so that any unit can refer to a symbol
+        // exported by a unit that comes after it.
+        let mut exported: HashMap<&str, &str> = HashMap::new();
+        let mut names = HashSet::new();
+        for (name, unit) in &self.units {
+            if !names.insert(name.as_str()) {
+                return Err(LinkError::DuplicateUnit(name.clone()));
+            }
+            for symbol in unit.exports() {
+                if !unit.labels().contains_key(symbol) {
+                    return Err(LinkError::UndefinedExport {
+                        symbol: symbol.clone(),
+                        unit: name.clone(),
+                    });
+                }
+                if let Some(first) = exported.insert(symbol, name) {
+                    return Err(LinkError::DuplicateSymbol {
+                        symbol: symbol.clone(),
+                        first_unit: first.to_string(),
+                        second_unit: name.clone(),
+                    });
+                }
+            }
+        }
+
+        // The private labels of the entry unit share the names of exported labels.
+        if self.has_entry {
+            let (name, unit) = &self.units[0];
+            for label in unit.labels().keys() {
+                match exported.get(label.as_str()) {
+                    Some(&exporter) if !unit.exports().contains(label) => {
+                        return Err(LinkError::DuplicateSymbol {
+                            symbol: label.clone(),
+                            first_unit: name.clone(),
+                            second_unit: exporter.to_string(),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut commands = Vec::new();
+        let mut labels = HashMap::new();
+        let mut exports = HashSet::new();
+        // Locations are only kept if every unit has them.
+        let mut locations = Some(Vec::new());
+        for (index, (name, unit)) in self.units.iter().enumerate() {
+            let qualify = index > 0 || !self.has_entry;
+            let base = commands.len() as u64;
+            for (label, address) in unit.labels() {
+                labels.insert(linked_name(name, unit, label, qualify), base + address);
+            }
+            for command in unit.commands() {
+                if let Some(count) = self.register_count {
+                    if let Some(register) = out_of_range(command.params(), count) {
+                        return Err(LinkError::RegisterOutOfRange {
+                            register,
+                            unit: name.clone(),
+                        });
+                    }
+                }
+                let mut command = command.clone();
+                if let Some(label) = command.label_mut() {
+                    *label = if unit.labels().contains_key(label.as_str()) {
+                        linked_name(name, unit, label, qualify)
+                    } else if exported.contains_key(label.as_str()) {
+                        label.clone()
+                    } else {
+                        return Err(LinkError::UndefinedSymbol {
+                            symbol: label.clone(),
+                            unit: name.clone(),
+                        });
+                    };
+                }
+                commands.push(command);
+            }
+            exports.extend(unit.exports().iter().cloned());
+            if unit.locations().len() == unit.len() {
+                if let Some(locations) = &mut locations {
+                    locations.extend(unit.locations().iter().cloned());
+                }
+            } else {
+                locations = None;
+            }
+        }
+        Ok(Program::new(commands, labels)
+            .with_exports(exports)
+            .with_locations(locations.unwrap_or_default()))
+    }
+}
+
+/// Returns the first register in `params` that isn't below `count`.
+fn out_of_range(params: Vec<Param>, count: u64) -> Option<u64> {
+    params.into_iter().find_map(|param| match param {
+        Param::Register(register) if register >= count => Some(register),
+        _ => None,
+    })
+}
+
+/// Returns the name `label` of `unit` has in the linked program.
+fn linked_name(unit_name: &str, unit: &Program, label: &str, qualify: bool) -> String {
+    if !qualify || unit.exports().contains(label) {
+        label.to_string()
+    } else {
+        format!("{}:{}", unit_name, label)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LinkError {
+    DuplicateUnit(String),
+    DuplicateSymbol {
+        symbol: String,
+        first_unit: String,
+        second_unit: String,
+    },
+    UndefinedSymbol {
+        symbol: String,
+        unit: String,
+    },
+    UndefinedExport {
+        symbol: String,
+        unit: String,
+    },
+    RegisterOutOfRange {
+        register: u64,
+        unit: String,
+    },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::DuplicateUnit(unit) => write!(f, "unit \"{}\" was added twice", unit),
+            LinkError::DuplicateSymbol {
+                symbol,
+                first_unit,
+                second_unit,
+            } => write!(
+                f,
+                "symbol \"{}\" is exported by both \"{}\" and \"{}\"",
+                symbol, first_unit, second_unit
+            ),
+            LinkError::UndefinedSymbol { symbol, unit } => write!(
+                f,
+                "\"{}\" refers to \"{}\" which is not defined in it or exported by any other unit",
+                unit, symbol
+            ),
+            LinkError::UndefinedExport { symbol, unit } => write!(
+                f,
+                "\"{}\" exports \"{}\" but does not define it",
+                unit, symbol
+            ),
+            LinkError::RegisterOutOfRange { register, unit } => {
+                write!(f, "\"{}\" uses R{} which is past the last register", unit, register)
+            }
+        }
+    }
+}
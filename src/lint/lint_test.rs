@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+
+use super::{Level, Lint, LintConfig};
+use crate::moo::parse_program_from_string;
+
+/// The lints of `lint` found in `source`, as (address, message).
+fn only(lint: Lint, source: &str) -> Vec<(u64, String)> {
+    let mut config = LintConfig::default();
+    for other in Lint::ALL.iter().copied().filter(|&other| other != lint) {
+        config.set_level(other, Level::Allow);
+    }
+    let program = parse_program_from_string(source).unwrap();
+    config
+        .lint(&program)
+        .warnings
+        .into_iter()
+        .map(|warning| {
+            assert_eq!(warning.lint, lint);
+            (warning.address, warning.message)
+        })
+        .collect()
+}
+
+fn addresses(lint: Lint, source: &str) -> Vec<u64> {
+    only(lint, source).into_iter().map(|(address, _)| address).collect()
+}
+
+#[test]
+fn missing_compare_test() {
+    let warnings = only(
+        Lint::MissingCompare,
+        "load I0 R0; jineg R0 1f; icmp R0 0i; 1: jeq 2f; 2: load R0 O0;",
+    );
+    assert_eq!(warnings, vec![(3, "`jeq` can be reached without a compare before it".to_string())]);
+    assert!(addresses(
+        Lint::MissingCompare,
+        "icmp I0 0i; jless 1f; jump 2f; 1: jgre 2f; 2: jeq 3f; 3: load 0u O0;"
+    )
+    .is_empty());
+}
+
+#[test]
+fn missing_compare_in_loop_test() {
+    // The compare at the end of the loop doesn't help the first time around.
+    assert_eq!(
+        addresses(Lint::MissingCompare, "load 0u R0; 1: jneq 2f; 2: uadd R0 1u R0; ucmp R0 5u; jless 1b;"),
+        vec![1]
+    );
+}
+
+#[test]
+fn dead_store_test() {
+    assert_eq!(
+        only(Lint::DeadStore, "load 1u R0; load 2u R0; load R0 O0;"),
+        vec![(0, "the value written to R0 is never read".to_string())]
+    );
+    assert!(addresses(Lint::DeadStore, "load 0u R0; 1: uadd R0 1u R0; ucmp R0 5u; jless 1b; load R0 O0;").is_empty());
+}
+
+#[test]
+fn unreachable_code_test() {
+    assert_eq!(
+        only(Lint::UnreachableCode, "jump 1f; load 1u O0; load 2u O0; 1: load 3u O0; jump 2f; load 4u O0; 2: load 5u O0;"),
+        vec![
+            (1, "the instructions up to 2 can't be reached".to_string()),
+            (5, "this instruction can't be reached".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn infinite_spin_test() {
+    assert_eq!(addresses(Lint::InfiniteSpin, "1: jump 1b;"), vec![0]);
+    assert_eq!(addresses(Lint::InfiniteSpin, "load I0 R0; 1: uadd R0 1u R0; jump 1b;"), vec![1]);
+    assert_eq!(addresses(Lint::InfiniteSpin, "ucmp I0 0u; 1: jeq 1b; load 1u O0;"), vec![1]);
+    assert!(addresses(Lint::InfiniteSpin, "load 0u R0; 1: uadd R0 1u R0; ucmp R0 10u; jless 1b;").is_empty());
+    assert!(addresses(Lint::InfiniteSpin, "1: load I0 O0; jump 1b;").is_empty());
+    // The first pass may leave the loop.
+    assert!(addresses(Lint::InfiniteSpin, "icmp I0 0i; top: jeq done; jump top; done: load 1u O0;").is_empty());
+}
+
+#[test]
+fn division_by_zero_test() {
+    assert_eq!(
+        only(Lint::DivisionByZero, "udiv I0 0u O0; fdiv I0 0f O0; idiv I0 R0 O0; idiv I0 0i O0;"),
+        vec![
+            (0, "`udiv` divides by zero".to_string()),
+            (1, "`fdiv` divides by zero".to_string()),
+            (3, "`idiv` divides by zero".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn unread_register_test() {
+    let source = "load 1u R0; load 2u R1; load R1 O0; load 3u R0;";
+    assert_eq!(
+        only(Lint::UnreadRegister, source),
+        vec![(0, "R0 is written but never read".to_string())]
+    );
+    // Stores to registers that are never read aren't dead stores as well.
+    assert!(addresses(Lint::DeadStore, source).is_empty());
+}
+
+#[test]
+fn unused_label_test() {
+    let source = "jump used; unused: load 1u O0; used: load 2u O0;";
+    assert_eq!(
+        only(Lint::UnusedLabel, source),
+        vec![(1, "label `unused` is never jumped to".to_string())]
+    );
+
+    let exports: HashSet<String> = vec!["unused".to_string()].into_iter().collect();
+    let program = parse_program_from_string(source).unwrap().with_exports(exports);
+    let report = LintConfig::default().lint(&program);
+    assert!(report.warnings.iter().all(|warning| warning.lint != Lint::UnusedLabel));
+}
+
+#[test]
+fn type_lints_test() {
+    assert_eq!(
+        only(Lint::TypeMismatch, "load 3i R0; fadd R0 1f O0;"),
+        vec![(1, "R0 is read as a float but may hold a signed integer".to_string())]
+    );
+    assert_eq!(addresses(Lint::UseBeforeDefinition, "load R0 O0;"), vec![0]);
+}
+
+#[test]
+fn levels_test() {
+    let program = parse_program_from_string("load 1u R0; udiv R0 0u O0; jump 1f; load 2u O0; 1: load 3u O0;").unwrap();
+    let report = LintConfig::default().lint(&program);
+    let lints: Vec<_> = report.warnings.iter().map(|warning| (warning.address, warning.lint)).collect();
+    assert_eq!(lints, vec![(1, Lint::DivisionByZero), (3, Lint::UnreachableCode)]);
+    assert!(report.passed());
+
+    let config = LintConfig::default()
+        .with_level(Lint::DivisionByZero, Level::Deny)
+        .with_level(Lint::UnreachableCode, Level::Allow);
+    let report = config.lint(&program);
+    assert_eq!(report.warnings.len(), 1);
+    assert!(!report.passed());
+    assert_eq!(report.warnings[0].to_string(), "error[division-by-zero] 1: `udiv` divides by zero");
+}
+
+#[test]
+fn names_test() {
+    for &lint in Lint::ALL.iter() {
+        assert_eq!(Lint::from_name(lint.name()), Some(lint));
+    }
+    assert_eq!(Lint::from_name("dead-stores"), None);
+}
@@ -0,0 +1,421 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::cfg::Cfg;
+use crate::program::Program;
+use crate::typecheck::{TypeCheck, TypeWarning};
+use crate::vm::{Command, Param};
+
+#[cfg(test)]
+mod lint_test;
+
+/// A kind of mistake the linter looks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Lint {
+    /// A conditional jump that can be reached without a compare before it, which
+    /// makes the machine fail.
+    MissingCompare,
+    /// A register write that is always overwritten or never read afterwards.
+    DeadStore,
+    /// Instructions that can't be reached, because there's a `jump` before them.
+    UnreachableCode,
+    /// A loop without inputs or outputs that can't be left once entered.
+    InfiniteSpin,
+    /// A division by a constant zero, which makes the machine fail.
+    DivisionByZero,
+    /// A register that is written but never read anywhere.
+    UnreadRegister,
+    /// A label no jump goes to and that isn't exported.
+    UnusedLabel,
+    /// A register read as a different type than was written to it.
+    TypeMismatch,
+    /// A register that may be read before anything is written to it.
+    UseBeforeDefinition,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 9] = [
+        Lint::MissingCompare,
+        Lint::DeadStore,
+        Lint::UnreachableCode,
+        Lint::InfiniteSpin,
+        Lint::DivisionByZero,
+        Lint::UnreadRegister,
+        Lint::UnusedLabel,
+        Lint::TypeMismatch,
+        Lint::UseBeforeDefinition,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::MissingCompare => "missing-compare",
+            Lint::DeadStore => "dead-store",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::InfiniteSpin => "infinite-spin",
+            Lint::DivisionByZero => "division-by-zero",
+            Lint::UnreadRegister => "unread-register",
+            Lint::UnusedLabel => "unused-label",
+            Lint::TypeMismatch => "type-mismatch",
+            Lint::UseBeforeDefinition => "use-before-definition",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.iter().copied().find(|lint| lint.name() == name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    /// The lint isn't checked.
+    Allow,
+    Warn,
+    /// Finding the mistake fails the report.
+    Deny,
+}
+
+/// Which lints are checked and how seriously. Every lint warns unless set otherwise.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LintConfig {
+    levels: HashMap<Lint, Level>,
+}
+
+impl LintConfig {
+    pub fn with_level(mut self, lint: Lint, level: Level) -> Self {
+        self.set_level(lint, level);
+        self
+    }
+
+    pub fn set_level(&mut self, lint: Lint, level: Level) {
+        self.levels.insert(lint, level);
+    }
+
+    pub fn level(&self, lint: Lint) -> Level {
+        self.levels.get(&lint).copied().unwrap_or(Level::Warn)
+    }
+
+    /// Checks `program` for every lint that isn't allowed.
+    pub fn lint(&self, program: &Program) -> LintReport {
+        let mut linter = Linter {
+            config: self,
+            program,
+            cfg: Cfg::new(program),
+            warnings: Vec::new(),
+        };
+        linter.missing_compare();
+        linter.dead_stores();
+        linter.unreachable_code();
+        linter.infinite_spins();
+        linter.division_by_zero();
+        linter.unread_registers();
+        linter.unused_labels();
+        linter.types();
+        let mut warnings = linter.warnings;
+        warnings.sort_by_key(|warning| (warning.address, warning.lint));
+        LintReport { warnings }
+    }
+}
+
+/// A mistake found by the linter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    pub lint: Lint,
+    pub level: Level,
+    /// The address of the instruction the mistake is at.
+    pub address: u64,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = if self.level == Level::Deny { "error" } else { "warning" };
+        write!(f, "{}[{}] {}: {}", level, self.lint.name(), self.address, self.message)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LintReport {
+    /// The warnings in order of address.
+    pub warnings: Vec<Warning>,
+}
+
+impl LintReport {
+    /// Whether none of the warnings are of denied lints.
+    pub fn passed(&self) -> bool {
+        self.warnings.iter().all(|warning| warning.level != Level::Deny)
+    }
+}
+
+struct Linter<'a> {
+    config: &'a LintConfig,
+    program: &'a Program,
+    cfg: Cfg,
+    warnings: Vec<Warning>,
+}
+
+impl Linter<'_> {
+    fn enabled(&self, lint: Lint) -> bool {
+        self.config.level(lint) != Level::Allow
+    }
+
+    fn warn(&mut self, lint: Lint, address: u64, message: String) {
+        self.warnings.push(Warning {
+            lint,
+            level: self.config.level(lint),
+            address,
+            message,
+        });
+    }
+
+    /// Whether the instruction at `address` can be reached.
+    fn reachable_addresses(&self) -> Vec<bool> {
+        let reachable = self.cfg.reachable();
+        (0..self.program.len() as u64)
+            .map(|address| reachable[self.cfg.block_at(address)])
+            .collect()
+    }
+
+    fn missing_compare(&mut self) {
+        if !self.enabled(Lint::MissingCompare) {
+            return;
+        }
+        // Whether a compare has happened on every way into each block.
+        let blocks = self.cfg.blocks();
+        let mut compared: Vec<Option<bool>> = vec![None; blocks.len()];
+        let order = self.cfg.reverse_postorder();
+        // Blocks other programs can jump to aren't checked as it isn't known what they compared.
+        if !compared.is_empty() {
+            compared[0] = Some(false);
+        }
+        let compares = |id: usize, mut compared: bool| {
+            for command in &self.program.commands()[blocks[id].start as usize..blocks[id].end as usize] {
+                compared |= is_compare(command);
+            }
+            compared
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &id in &order {
+                let out = match compared[id] {
+                    Some(compared) => compares(id, compared),
+                    None => continue,
+                };
+                for &successor in &blocks[id].successors {
+                    let joined = Some(compared[successor].map_or(out, |compared| compared && out));
+                    if joined != compared[successor] {
+                        compared[successor] = joined;
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        let mut missing = Vec::new();
+        for (id, block) in blocks.iter().enumerate() {
+            let mut compared = match compared[id] {
+                Some(compared) => compared,
+                None => continue,
+            };
+            for address in block.start..block.end {
+                let command = &self.program[address as usize];
+                if !compared && uses_compare(command) {
+                    missing.push((address, command.mnemonic()));
+                }
+                compared |= is_compare(command);
+            }
+        }
+        for (address, mnemonic) in missing {
+            self.warn(
+                Lint::MissingCompare,
+                address,
+                format!("`{}` can be reached without a compare before it", mnemonic),
+            );
+        }
+    }
+
+    fn dead_stores(&mut self) {
+        if !self.enabled(Lint::DeadStore) {
+            return;
+        }
+        let read = self.read_registers();
+        let live_after = self.cfg.live_after(self.program);
+        let reachable = self.reachable_addresses();
+        for (address, command) in self.program.commands().iter().enumerate() {
+            if let Some(Param::Register(register)) = command.destination() {
+                // Registers that are never read are reported as unread instead.
+                if reachable[address] && read.contains(&register) && !live_after[address].contains(&register) {
+                    self.warn(
+                        Lint::DeadStore,
+                        address as u64,
+                        format!("the value written to R{} is never read", register),
+                    );
+                }
+            }
+        }
+    }
+
+    fn unreachable_code(&mut self) {
+        if !self.enabled(Lint::UnreachableCode) {
+            return;
+        }
+        let reachable = self.reachable_addresses();
+        let mut address = 0;
+        while address < reachable.len() {
+            if reachable[address] {
+                address += 1;
+                continue;
+            }
+            let end = (address..reachable.len())
+                .find(|&end| reachable[end])
+                .unwrap_or(reachable.len());
+            let message = if end - address == 1 {
+                "this instruction can't be reached".to_string()
+            } else {
+                format!("the instructions up to {} can't be reached", end - 1)
+            };
+            self.warn(Lint::UnreachableCode, address as u64, message);
+            address = end;
+        }
+    }
+
+    fn infinite_spins(&mut self) {
+        if !self.enabled(Lint::InfiniteSpin) {
+            return;
+        }
+        let blocks = self.cfg.blocks();
+        let mut spins = Vec::new();
+        for found in self.cfg.loops() {
+            let commands: Vec<&Command> = found
+                .body
+                .iter()
+                .flat_map(|&id| &self.program.commands()[blocks[id].start as usize..blocks[id].end as usize])
+                .collect();
+            let io = commands.iter().any(|command| {
+                command
+                    .params()
+                    .iter()
+                    .any(|param| matches!(param, Param::Input(_) | Param::Output(_)))
+            });
+            let exits = found.body.iter().any(|&id| {
+                blocks[id].halts || blocks[id].successors.iter().any(|successor| !found.body.contains(successor))
+            });
+            // A loop of nothing but jumps decides the same way every time around, so
+            // it can only be left on the first pass, through a conditional jump out of it.
+            let only_jumps = commands.iter().all(|command| command.label().is_some());
+            let jumps_out = commands
+                .iter()
+                .filter(|command| command.is_conditional_jump())
+                .filter_map(|command| self.program.labels().get(command.label()?))
+                .any(|&target| target as usize >= self.program.len() || !found.body.contains(&self.cfg.block_at(target)));
+            if !io && (!exits || (only_jumps && !jumps_out)) {
+                spins.push(blocks[found.header].start);
+            }
+        }
+        for address in spins {
+            self.warn(
+                Lint::InfiniteSpin,
+                address,
+                "this loop never ends once entered and does no input or output".to_string(),
+            );
+        }
+    }
+
+    fn division_by_zero(&mut self) {
+        if !self.enabled(Lint::DivisionByZero) {
+            return;
+        }
+        let mut divisions = Vec::new();
+        for (address, command) in self.program.commands().iter().enumerate() {
+            let divisor = match *command {
+                Command::IDiv(_, divisor, _) | Command::UDiv(_, divisor, _) | Command::FDiv(_, divisor, _) => divisor,
+                _ => continue,
+            };
+            let zero = match divisor {
+                Param::FConstant(divisor) => divisor == 0.,
+                Param::IConstant(divisor) => divisor == 0,
+                Param::UConstant(divisor) => divisor == 0,
+                _ => false,
+            };
+            if zero {
+                divisions.push((address as u64, command.mnemonic()));
+            }
+        }
+        for (address, mnemonic) in divisions {
+            self.warn(Lint::DivisionByZero, address, format!("`{}` divides by zero", mnemonic));
+        }
+    }
+
+    fn unread_registers(&mut self) {
+        if !self.enabled(Lint::UnreadRegister) {
+            return;
+        }
+        let read = self.read_registers();
+        let mut reported = BTreeSet::new();
+        for (address, command) in self.program.commands().iter().enumerate() {
+            if let Some(Param::Register(register)) = command.destination() {
+                if !read.contains(&register) && reported.insert(register) {
+                    self.warn(
+                        Lint::UnreadRegister,
+                        address as u64,
+                        format!("R{} is written but never read", register),
+                    );
+                }
+            }
+        }
+    }
+
+    fn unused_labels(&mut self) {
+        if !self.enabled(Lint::UnusedLabel) {
+            return;
+        }
+        let used: BTreeSet<&str> = self.program.commands().iter().filter_map(Command::label).collect();
+        let mut unused: Vec<(u64, String)> = self
+            .program
+            .labels()
+            .iter()
+            .filter(|&(label, _)| !used.contains(label.as_str()) && !self.program.exports().contains(label))
+            .map(|(label, &address)| (address, label.clone()))
+            .collect();
+        unused.sort();
+        for (address, label) in unused {
+            self.warn(Lint::UnusedLabel, address, format!("label `{}` is never jumped to", label));
+        }
+    }
+
+    fn types(&mut self) {
+        if !self.enabled(Lint::TypeMismatch) && !self.enabled(Lint::UseBeforeDefinition) {
+            return;
+        }
+        for warning in TypeCheck::new(self.program).warnings() {
+            let lint = match warning {
+                TypeWarning::Mismatch { .. } => Lint::TypeMismatch,
+                TypeWarning::UseBeforeDefinition { .. } => Lint::UseBeforeDefinition,
+            };
+            if self.enabled(lint) {
+                self.warn(lint, warning.address(), warning.message());
+            }
+        }
+    }
+
+    /// The registers some instruction reads.
+    fn read_registers(&self) -> BTreeSet<u64> {
+        self.program
+            .commands()
+            .iter()
+            .flat_map(Command::operands)
+            .filter_map(|param| match param {
+                Param::Register(register) => Some(register),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn is_compare(command: &Command) -> bool {
+    matches!(command, Command::ICmp(..) | Command::UCmp(..) | Command::FCmp(..))
+}
+
+fn uses_compare(command: &Command) -> bool {
+    matches!(command, Command::JGre(_) | Command::JLess(_) | Command::JEq(_) | Command::JNeq(_))
+}
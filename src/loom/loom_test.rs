@@ -0,0 +1,281 @@
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+use super::{LoomBuilder, LoomError, LoomStatus, MachineStats};
+use crate::common::{unbounded_channel, Source};
+use crate::moo::parse_program_from_string;
+use crate::vm::{Channel, VmError};
+
+#[test]
+fn pipeline_test() {
+    let mut builder = LoomBuilder::new();
+    let doubler = builder.add_machine(parse_program_from_string("uadd I0 I0 O0; uadd I0 I0 O0;").unwrap());
+    let incrementer = builder.add_machine(parse_program_from_string("uadd I0 1u O0; uadd I0 1u O0;").unwrap());
+    let (sender, mut receiver) = channel();
+    builder
+        .input(doubler, 0, Box::new(vec![1u64, 2, 3, 4]))
+        .connect(doubler, 0, incrementer, 0)
+        .output(incrementer, 0, Box::new(sender));
+    let mut loom = builder.build().unwrap();
+
+    assert_eq!(loom.run(100), Ok(LoomStatus::Halted));
+    assert_eq!(receiver.take(), Some(4));
+    assert_eq!(receiver.take(), Some(8));
+    assert_eq!(loom.cycles(), 3);
+    assert_eq!(
+        loom.stats(doubler),
+        &MachineStats { executed: 2, blocked: 0, halted_at: Some(2) },
+    );
+    assert_eq!(
+        loom.stats(incrementer),
+        &MachineStats { executed: 2, blocked: 0, halted_at: Some(2) },
+    );
+}
+
+#[test]
+fn machines_wait_for_each_other_test() {
+    let mut builder = LoomBuilder::new();
+    // The consumer is added first so it has to wait a cycle for every value.
+    let consumer = builder.add_machine(parse_program_from_string("load I0 O0; load I0 O0;").unwrap());
+    let producer = builder.add_machine(parse_program_from_string("load 1u O0; load 2u O0;").unwrap());
+    let (sender, mut receiver) = channel();
+    builder
+        .connect(producer, 0, consumer, 0)
+        .output(consumer, 0, Box::new(sender));
+    let mut loom = builder.build().unwrap();
+
+    assert_eq!(loom.run(100), Ok(LoomStatus::Halted));
+    assert_eq!(receiver.take(), Some(1));
+    assert_eq!(receiver.take(), Some(2));
+    assert_eq!(loom.stats(consumer).blocked, 1);
+    assert_eq!(loom.stats(consumer).executed, 2);
+}
+
+#[test]
+fn deadlock_test() {
+    let mut builder = LoomBuilder::new();
+    let first = builder.add_machine(parse_program_from_string("load I0 O0;").unwrap());
+    let second = builder.add_machine(parse_program_from_string("load I0 O0;").unwrap());
+    builder.connect(first, 0, second, 0).connect(second, 0, first, 0);
+    let mut loom = builder.build().unwrap();
+
+    assert_eq!(
+        loom.run(100),
+        Ok(LoomStatus::Deadlocked {
+            blocked: vec![(first, Channel::Input(0)), (second, Channel::Input(0))],
+        }),
+    );
+    assert_eq!(loom.cycles(), 2);
+}
+
+#[test]
+fn run_stops_after_max_cycles_test() {
+    let mut builder = LoomBuilder::new();
+    builder.add_machine(parse_program_from_string("1: jump 1b;").unwrap());
+    let mut loom = builder.build().unwrap();
+    assert_eq!(loom.run(10), Ok(LoomStatus::Running));
+    assert_eq!(loom.cycles(), 10);
+}
+
+#[test]
+fn invalid_topology_test() {
+    let mut builder = LoomBuilder::new();
+    let machine = builder.add_machine(parse_program_from_string("load I1 R0;").unwrap());
+    builder.input(machine, 1, Box::new(vec![1u64]));
+    assert_eq!(
+        builder.build().err(),
+        Some(LoomError::ChannelNotConnected { machine, channel: Channel::Input(0) }),
+    );
+
+    let mut builder = LoomBuilder::new();
+    let machine = builder.add_machine(parse_program_from_string("load 1u O0;").unwrap());
+    builder
+        .output(machine, 0, Box::new(Vec::new()))
+        .output(machine, 0, Box::new(Vec::new()));
+    assert_eq!(
+        builder.build().err(),
+        Some(LoomError::ChannelConnectedTwice { machine, channel: Channel::Output(0) }),
+    );
+
+    // Channels the program uses have to be connected even past the last one that is.
+    let mut builder = LoomBuilder::new();
+    let machine = builder.add_machine(parse_program_from_string("uadd I0 I2 O0; load I1 O3;").unwrap());
+    builder
+        .input(machine, 0, Box::new(vec![1u64]))
+        .output(machine, 0, Box::new(Vec::new()));
+    assert_eq!(
+        builder.build().err(),
+        Some(LoomError::ChannelNotConnected { machine, channel: Channel::Input(1) }),
+    );
+
+    let mut builder = LoomBuilder::new();
+    let machine = builder.add_machine(parse_program_from_string("load 1u O1;").unwrap());
+    builder.output(machine, 0, Box::new(Vec::new()));
+    assert_eq!(
+        builder.build().err(),
+        Some(LoomError::ChannelNotConnected { machine, channel: Channel::Output(1) }),
+    );
+}
+
+#[test]
+fn halted_machines_close_their_outputs_test() {
+    let mut builder = LoomBuilder::new();
+    let producer = builder.add_machine(parse_program_from_string("load 1u O0;").unwrap());
+    let consumer = builder.add_machine(parse_program_from_string("1: load I0 O0; jump 1b;").unwrap());
+    let (sink, mut source) = unbounded_channel();
+    builder
+        .connect(producer, 0, consumer, 0)
+        .output(consumer, 0, Box::new(sink));
+    let mut loom = builder.build().unwrap();
+
+    assert_eq!(
+        loom.run(100),
+        Err(LoomError::Machine { machine: consumer, error: VmError::InputClosed(0) }),
+    );
+    assert_eq!(source.take(), Some(1));
+    assert_eq!(source.take(), None);
+}
+
+#[test]
+fn rendezvous_test() {
+    let mut builder = LoomBuilder::new();
+    let producer = builder.add_machine(parse_program_from_string("load 1u O0; load 2u O0;").unwrap());
+    let consumer = builder.add_machine(parse_program_from_string("load I0 O0; load I0 O0;").unwrap());
+    let (sink, mut source) = unbounded_channel();
+    builder
+        .connect_bounded(producer, 0, consumer, 0, 0)
+        .output(consumer, 0, Box::new(sink));
+    let mut loom = builder.build().unwrap();
+
+    assert_eq!(loom.run(100), Ok(LoomStatus::Halted));
+    assert_eq!(source.take(), Some(1));
+    assert_eq!(source.take(), Some(2));
+    // The producer has to wait for the consumer to ask for every value.
+    assert_eq!(
+        loom.stats(producer),
+        &MachineStats { executed: 2, blocked: 2, halted_at: Some(4) },
+    );
+    assert_eq!(
+        loom.stats(consumer),
+        &MachineStats { executed: 2, blocked: 2, halted_at: Some(4) },
+    );
+}
+
+#[test]
+fn full_channel_deadlock_test() {
+    let mut builder = LoomBuilder::new();
+    let producer = builder.add_machine(parse_program_from_string("load 1u O0; load 2u O0;").unwrap());
+    let consumer = builder.add_machine(parse_program_from_string("load I1 R0;").unwrap());
+    let (_never_sink, never_source) = unbounded_channel::<u64>();
+    builder
+        .connect_bounded(producer, 0, consumer, 0, 1)
+        .input(consumer, 1, Box::new(never_source));
+    let mut loom = builder.build().unwrap();
+
+    assert_eq!(
+        loom.run(100),
+        Ok(LoomStatus::Deadlocked {
+            blocked: vec![(producer, Channel::Output(0)), (consumer, Channel::Input(1))],
+        }),
+    );
+}
+
+fn run_counting_network() -> (Vec<u64>, Vec<MachineStats>, u64) {
+    let mut builder = LoomBuilder::new();
+    let counter = builder.add_machine(
+        parse_program_from_string("load 0u R0; 1: uadd R0 1u R0; load R0 O0; ucmp R0 5u; jless 1b;").unwrap(),
+    );
+    let squarer = builder.add_machine(parse_program_from_string("1: load I0 R0; umul R0 R0 O0; jump 1b;").unwrap());
+    let (sink, mut source) = unbounded_channel();
+    builder
+        .connect_bounded(counter, 0, squarer, 0, 1)
+        .output(squarer, 0, Box::new(sink));
+    let mut loom = builder.build().unwrap();
+    // The counter closes its output when it halts, which ends the squarer.
+    assert_eq!(
+        loom.run(1000),
+        Err(LoomError::Machine { machine: squarer, error: VmError::InputClosed(0) }),
+    );
+
+    let mut outputs = Vec::new();
+    while let Some(output) = source.take() {
+        outputs.push(output);
+    }
+    let stats = vec![loom.stats(counter).clone(), loom.stats(squarer).clone()];
+    (outputs, stats, loom.cycles())
+}
+
+#[test]
+fn deterministic_test() {
+    let first = run_counting_network();
+    assert_eq!(first.0, vec![1, 4, 9, 16, 25]);
+    for _ in 0..10 {
+        assert_eq!(run_counting_network(), first);
+    }
+}
+
+#[test]
+fn parallel_pipeline_test() {
+    let mut builder = LoomBuilder::new();
+    let stages: Vec<_> = (0..50)
+        .map(|_| builder.add_machine(parse_program_from_string("1: uadd I0 1u O0; jump 1b;").unwrap()))
+        .collect();
+    let (sink, mut source) = unbounded_channel();
+    builder.input(stages[0], 0, Box::new((0..100u64).collect::<Vec<_>>()));
+    for pair in stages.windows(2) {
+        builder.connect_bounded(pair[0], 0, pair[1], 0, 2);
+    }
+    builder.output(stages[49], 0, Box::new(sink));
+    let mut loom = builder.build().unwrap();
+
+    // The first stage fails with InputClosed once its input runs out, which is how
+    // this pipeline ends.
+    match loom.run_parallel(4, Duration::from_secs(5)) {
+        Err(LoomError::Machine { machine, error: VmError::InputClosed(0) }) => {
+            assert_eq!(machine, stages[0])
+        },
+        other => panic!("expected the first stage to run out of input but got {:?}", other),
+    }
+    let mut outputs = Vec::new();
+    while let Some(output) = source.take() {
+        outputs.push(output);
+    }
+    // Stages are stopped as soon as the first one fails so not everything has
+    // necessarily made it through, but what did is in order.
+    assert!(outputs.iter().enumerate().all(|(i, &output)| output == i as u64 + 50));
+    assert_eq!(loom.stats(stages[0]).executed, 200);
+}
+
+#[test]
+fn parallel_halt_test() {
+    let mut builder = LoomBuilder::new();
+    let machines: Vec<_> = (0..20)
+        .map(|_| {
+            builder.add_machine(
+                parse_program_from_string("load 0u R0; 1: uadd R0 1u R0; ucmp R0 1000u; jless 1b;").unwrap(),
+            )
+        })
+        .collect();
+    let mut loom = builder.build().unwrap();
+
+    assert_eq!(loom.run_parallel(4, Duration::from_secs(5)), Ok(LoomStatus::Halted));
+    for machine in machines {
+        assert_eq!(loom.stats(machine).executed, 1 + 3 * 1000);
+    }
+}
+
+#[test]
+fn parallel_deadlock_times_out_test() {
+    let mut builder = LoomBuilder::new();
+    let first = builder.add_machine(parse_program_from_string("load I0 O0;").unwrap());
+    let second = builder.add_machine(parse_program_from_string("load I0 O0;").unwrap());
+    builder.connect(first, 0, second, 0).connect(second, 0, first, 0);
+    let mut loom = builder.build().unwrap();
+
+    assert_eq!(
+        loom.run_parallel(2, Duration::from_millis(50)),
+        Ok(LoomStatus::Deadlocked {
+            blocked: vec![(first, Channel::Input(0)), (second, Channel::Input(0))],
+        }),
+    );
+}
@@ -0,0 +1,273 @@
+use crate::common::{bounded_channel, unbounded_channel};
+use crate::program::Program;
+use crate::vm::{BoxedSink, BoxedSource, Channel, MooMachine, Param, Status, VmError};
+
+mod parallel;
+
+#[cfg(test)]
+mod loom_test;
+
+/// Identifies a machine in a `Loom`. Machines are numbered in the order they were added.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MachineId(usize);
+
+impl MachineId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// Describes the machines of a `Loom` and how their channels are connected.
+///
+/// Every input and output channel a machine has must be connected to exactly one
+/// thing: another machine, an external `Source` or an external `Sink`. A machine's
+/// channels have to be numbered from 0 without gaps, like `MooMachine` expects,
+/// and every channel its program uses has to be connected.
+#[derive(Default)]
+pub struct LoomBuilder {
+    programs: Vec<Program>,
+    inputs: Vec<Vec<NumberedChannel<BoxedSource>>>,
+    outputs: Vec<Vec<NumberedChannel<BoxedSink>>>,
+}
+
+type NumberedChannel<T> = (u64, T);
+
+impl LoomBuilder {
+    pub fn new() -> Self {
+        LoomBuilder {
+            programs: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn add_machine(&mut self, program: Program) -> MachineId {
+        self.programs.push(program);
+        self.inputs.push(Vec::new());
+        self.outputs.push(Vec::new());
+        MachineId(self.programs.len() - 1)
+    }
+
+    /// Connects output channel `output` of `from` to input channel `input` of `to`
+    /// with a channel that never fills up.
+    pub fn connect(&mut self, from: MachineId, output: u64, to: MachineId, input: u64) -> &mut Self {
+        let (sink, source) = unbounded_channel();
+        self.output(from, output, Box::new(sink));
+        self.input(to, input, Box::new(source))
+    }
+
+    /// Like `connect` but the channel holds at most `capacity` values. See
+    /// `bounded_channel` for what a capacity of 0 means.
+    pub fn connect_bounded(
+        &mut self,
+        from: MachineId,
+        output: u64,
+        to: MachineId,
+        input: u64,
+        capacity: usize,
+    ) -> &mut Self {
+        let (sink, source) = bounded_channel(capacity);
+        self.output(from, output, Box::new(sink));
+        self.input(to, input, Box::new(source))
+    }
+
+    /// Feeds input channel `input` of `to` from a source outside of the loom.
+    pub fn input(&mut self, to: MachineId, input: u64, source: BoxedSource) -> &mut Self {
+        self.inputs[to.0].push((input, source));
+        self
+    }
+
+    /// Sends output channel `output` of `from` to a sink outside of the loom.
+    pub fn output(&mut self, from: MachineId, output: u64, sink: BoxedSink) -> &mut Self {
+        self.outputs[from.0].push((output, sink));
+        self
+    }
+
+    pub fn build(self) -> Result<Loom, LoomError> {
+        let mut machines = Vec::new();
+        for (index, ((program, inputs), outputs)) in self
+            .programs
+            .into_iter()
+            .zip(self.inputs)
+            .zip(self.outputs)
+            .enumerate()
+        {
+            let machine = MachineId(index);
+            let inputs = order_channels(inputs, |channel| (machine, Channel::Input(channel)))?;
+            let outputs = order_channels(outputs, |channel| (machine, Channel::Output(channel)))?;
+            if let Some(channel) = unconnected_channel(&program, inputs.len() as u64, outputs.len() as u64) {
+                return Err(LoomError::ChannelNotConnected { machine, channel });
+            }
+            machines.push(MooMachine::new(program, inputs, outputs));
+        }
+        let count = machines.len();
+        Ok(Loom {
+            machines,
+            statuses: vec![Status::Running; count],
+            stats: vec![MachineStats::default(); count],
+            cycles: 0,
+            idle_cycles: 0,
+        })
+    }
+}
+
+/// Sorts the channels of a machine by their number and checks that every number
+/// from 0 up is used exactly once.
+fn order_channels<T, F>(mut channels: Vec<(u64, T)>, describe: F) -> Result<Vec<T>, LoomError>
+where
+    F: Fn(u64) -> (MachineId, Channel),
+{
+    channels.sort_by_key(|&(channel, _)| channel);
+    let mut ordered = Vec::new();
+    for (channel, thing) in channels {
+        if channel < ordered.len() as u64 {
+            let (machine, channel) = describe(channel);
+            return Err(LoomError::ChannelConnectedTwice { machine, channel });
+        }
+        if channel > ordered.len() as u64 {
+            let (machine, channel) = describe(ordered.len() as u64);
+            return Err(LoomError::ChannelNotConnected { machine, channel });
+        }
+        ordered.push(thing);
+    }
+    Ok(ordered)
+}
+
+/// The lowest channel `program` uses that isn't among the first `inputs` input or
+/// `outputs` output channels.
+fn unconnected_channel(program: &Program, inputs: u64, outputs: u64) -> Option<Channel> {
+    program
+        .commands()
+        .iter()
+        .flat_map(|command| command.params())
+        .filter_map(|param| match param {
+            Param::Input(channel) if channel >= inputs => Some(Channel::Input(channel)),
+            Param::Output(channel) if channel >= outputs => Some(Channel::Output(channel)),
+            _ => None,
+        })
+        .min_by_key(|&channel| match channel {
+            Channel::Input(channel) => (0, channel),
+            Channel::Output(channel) => (1, channel),
+        })
+}
+
+/// A network of `MooMachine`s stepped together.
+///
+/// Every cycle each machine that hasn't halted is ticked once, in the order the
+/// machines were added. Anything a machine outputs during a cycle can be taken by
+/// machines ticked after it in the same cycle. The channels between machines are
+/// single threaded, so given the same programs and external inputs a loom always
+/// runs exactly the same way. Once a machine halts its outputs are closed, so
+/// machines still waiting on them fail with `InputClosed` instead of blocking.
+pub struct Loom {
+    machines: Vec<MooMachine>,
+    statuses: Vec<Status>,
+    stats: Vec<MachineStats>,
+    cycles: u64,
+    // Consecutive cycles in which no machine executed anything.
+    idle_cycles: u64,
+}
+
+impl Loom {
+    /// Runs a single cycle.
+    pub fn step(&mut self) -> Result<LoomStatus, LoomError> {
+        for (index, machine) in self.machines.iter_mut().enumerate() {
+            if self.statuses[index] == Status::Halted {
+                continue;
+            }
+            let status = machine.tick().map_err(|error| LoomError::Machine {
+                machine: MachineId(index),
+                error,
+            })?;
+            let stats = &mut self.stats[index];
+            match status {
+                Status::Running => stats.executed += 1,
+                Status::Blocked { .. } => stats.blocked += 1,
+                Status::Halted => {
+                    stats.halted_at = Some(self.cycles);
+                    machine.close_outputs();
+                }
+            }
+            self.statuses[index] = status;
+        }
+        self.cycles += 1;
+        if self.statuses.contains(&Status::Running) {
+            self.idle_cycles = 0;
+        } else {
+            self.idle_cycles += 1;
+        }
+        Ok(self.status())
+    }
+
+    /// Runs cycles until every machine has halted, the loom is deadlocked or
+    /// `max_cycles` more cycles have been run.
+    pub fn run(&mut self, max_cycles: u64) -> Result<LoomStatus, LoomError> {
+        let mut status = self.status();
+        for _ in 0..max_cycles {
+            status = self.step()?;
+            if status != LoomStatus::Running {
+                break;
+            }
+        }
+        Ok(status)
+    }
+
+    /// The status after the latest cycle.
+    ///
+    /// The loom is deadlocked when no machine executed anything during the latest
+    /// two cycles and at least one of them is blocked rather than halted. One idle
+    /// cycle isn't enough as a machine waiting on a rendezvous channel only lets the
+    /// sender through after it has been blocked for a cycle.
+    pub fn status(&self) -> LoomStatus {
+        let mut blocked = Vec::new();
+        for (index, status) in self.statuses.iter().enumerate() {
+            if let Status::Blocked { channel } = *status {
+                blocked.push((MachineId(index), channel));
+            }
+        }
+        if self.cycles == 0 || self.idle_cycles == 0 {
+            LoomStatus::Running
+        } else if blocked.is_empty() {
+            LoomStatus::Halted
+        } else if self.idle_cycles >= 2 {
+            LoomStatus::Deadlocked { blocked }
+        } else {
+            LoomStatus::Running
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn stats(&self, machine: MachineId) -> &MachineStats {
+        &self.stats[machine.0]
+    }
+}
+
+/// How a machine in a `Loom` has spent its cycles.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MachineStats {
+    /// Cycles in which the machine executed an instruction.
+    pub executed: u64,
+    /// Cycles in which the machine was blocked on a channel.
+    pub blocked: u64,
+    /// The cycle in which the machine was found to have halted.
+    pub halted_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoomStatus {
+    Running,
+    /// Every machine has halted.
+    Halted,
+    /// No machine can make progress. Lists the machines that are waiting on a channel.
+    Deadlocked { blocked: Vec<(MachineId, Channel)> },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoomError {
+    ChannelConnectedTwice { machine: MachineId, channel: Channel },
+    ChannelNotConnected { machine: MachineId, channel: Channel },
+    Machine { machine: MachineId, error: VmError },
+}
@@ -0,0 +1,185 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Loom, LoomError, LoomStatus, MachineId, MachineStats};
+use crate::vm::{MooMachine, Status};
+
+/// How many ticks a worker runs a machine for before moving on to the next one.
+const SLICE: usize = 64;
+
+/// A machine borrowed from a `Loom` for the length of a parallel run.
+struct Task<'a> {
+    id: MachineId,
+    machine: &'a mut MooMachine,
+    status: &'a mut Status,
+    stats: &'a mut MachineStats,
+}
+
+/// What the workers of a parallel run share.
+struct Shared<'a> {
+    queues: Vec<Mutex<VecDeque<Task<'a>>>>,
+    live: AtomicUsize,
+    stop: AtomicBool,
+    last_progress: Mutex<Instant>,
+    error: Mutex<Option<LoomError>>,
+    timeout: Duration,
+}
+
+impl<'a> Shared<'a> {
+    /// Takes a machine from the front of the worker's own queue or, if that's
+    /// empty, steals one from the back of another worker's queue.
+    fn next_task(&self, worker: usize) -> Option<Task<'a>> {
+        if let Some(task) = self.queues[worker].lock().unwrap().pop_front() {
+            return Some(task);
+        }
+        let count = self.queues.len();
+        (1..count)
+            .map(|offset| (worker + offset) % count)
+            .find_map(|victim| self.queues[victim].lock().unwrap().pop_back())
+    }
+
+    fn timed_out(&self) -> bool {
+        self.last_progress.lock().unwrap().elapsed() > self.timeout
+    }
+
+    fn fail(&self, error: LoomError) {
+        self.error.lock().unwrap().get_or_insert(error);
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Loom {
+    /// Runs the machines on `threads` threads until every machine has halted or no
+    /// machine has executed anything for `timeout`.
+    ///
+    /// Unlike `run` this doesn't step the machines in lockstep, so results only
+    /// stay the same between runs if the machines' programs don't depend on the
+    /// order in which they run. Machines are spread over the threads and threads
+    /// that run out of runnable machines steal them from the others. A blocked
+    /// machine is put back in its queue and retried later.
+    ///
+    /// The `executed` and `blocked` counts of the machines' stats keep counting
+    /// ticks but `cycles` and `halted_at` are left alone as there are no cycles.
+    pub fn run_parallel(&mut self, threads: usize, timeout: Duration) -> Result<LoomStatus, LoomError> {
+        let threads = threads.max(1);
+        let mut queues: Vec<VecDeque<Task>> = (0..threads).map(|_| VecDeque::new()).collect();
+        let mut live = 0;
+        let tasks = self
+            .machines
+            .iter_mut()
+            .zip(self.statuses.iter_mut())
+            .zip(self.stats.iter_mut())
+            .enumerate();
+        for (index, ((machine, status), stats)) in tasks {
+            if *status != Status::Halted {
+                live += 1;
+                queues[index % threads].push_back(Task {
+                    id: MachineId(index),
+                    machine,
+                    status,
+                    stats,
+                });
+            }
+        }
+
+        let shared = Shared {
+            queues: queues.into_iter().map(Mutex::new).collect(),
+            live: AtomicUsize::new(live),
+            stop: AtomicBool::new(false),
+            last_progress: Mutex::new(Instant::now()),
+            error: Mutex::new(None),
+            timeout,
+        };
+        thread::scope(|scope| {
+            for worker in 0..threads {
+                let shared = &shared;
+                scope.spawn(move || run_worker(shared, worker));
+            }
+        });
+
+        let error = shared.error.into_inner().unwrap();
+        // The queues borrow the machines, so they have to go before the status can
+        // be looked at.
+        drop(shared.queues);
+        match error {
+            Some(error) => Err(error),
+            None => Ok(self.parallel_status()),
+        }
+    }
+
+    fn parallel_status(&self) -> LoomStatus {
+        let blocked: Vec<_> = self
+            .statuses
+            .iter()
+            .enumerate()
+            .filter_map(|(index, status)| match *status {
+                Status::Blocked { channel } => Some((MachineId(index), channel)),
+                _ => None,
+            })
+            .collect();
+        if self.statuses.iter().all(|status| *status == Status::Halted) {
+            LoomStatus::Halted
+        } else if blocked.is_empty() {
+            LoomStatus::Running
+        } else {
+            LoomStatus::Deadlocked { blocked }
+        }
+    }
+}
+
+fn run_worker(shared: &Shared, worker: usize) {
+    while !shared.stop.load(Ordering::SeqCst) && shared.live.load(Ordering::SeqCst) > 0 {
+        let task = match shared.next_task(worker) {
+            Some(task) => task,
+            None => {
+                // Every live machine is being run by another worker.
+                if shared.timed_out() {
+                    shared.stop.store(true, Ordering::SeqCst);
+                }
+                thread::yield_now();
+                continue;
+            }
+        };
+
+        let mut progressed = false;
+        for _ in 0..SLICE {
+            match task.machine.tick() {
+                Ok(Status::Running) => {
+                    task.stats.executed += 1;
+                    *task.status = Status::Running;
+                    progressed = true;
+                }
+                Ok(status) => {
+                    if let Status::Blocked { .. } = status {
+                        task.stats.blocked += 1;
+                    }
+                    *task.status = status;
+                    break;
+                }
+                Err(error) => {
+                    shared.fail(LoomError::Machine { machine: task.id, error });
+                    return;
+                }
+            }
+        }
+
+        if progressed {
+            *shared.last_progress.lock().unwrap() = Instant::now();
+        }
+        if *task.status == Status::Halted {
+            task.machine.close_outputs();
+            shared.live.fetch_sub(1, Ordering::SeqCst);
+        } else {
+            shared.queues[worker].lock().unwrap().push_back(task);
+            if !progressed {
+                if shared.timed_out() {
+                    shared.stop.store(true, Ordering::SeqCst);
+                }
+                thread::yield_now();
+            }
+        }
+    }
+}
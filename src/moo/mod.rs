@@ -1,52 +1,312 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
 use std::io::{self, Read};
-use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
+use crate::linker::{LinkError, Linker};
+use crate::program::{Program, SourceLocation};
 use crate::vm::{Command, Param};
-use crate::program::Program;
 
 #[cfg(test)]
 mod parser_test;
 
-pub fn parse_program<R: Read>(mut r: R) -> Result<Program, MooParseError> {
-    let mut source = String::new();
-    r.read_to_string(&mut source)?;
-    parse_program_from_string(&source)
+/// Parses a program from `r`. Relative `.include`s are resolved against the current
+/// working directory.
+pub fn parse_program<R: Read>(r: R) -> Result<Program, MooParseError> {
+    MooParser::new().parse(r)
 }
 
+/// Parses a program from a string. Relative `.include`s are resolved against the
+/// current working directory.
 pub fn parse_program_from_string(source: &str) -> Result<Program, MooParseError> {
+    MooParser::new().parse_string(source)
+}
+
+/// Parses the program in the file at `path`. Relative `.include`s are resolved
+/// against the directory of the file that contains them.
+pub fn parse_program_from_file<P: AsRef<Path>>(path: P) -> Result<Program, MooParseError> {
+    MooParser::new().parse_file(path)
+}
+
+/// Parses programs like the `parse_program` functions, with limits on what the
+/// programs may use.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MooParser {
+    register_count: Option<u64>,
+}
+
+impl MooParser {
+    pub fn new() -> Self {
+        MooParser { register_count: None }
+    }
+
+    /// Rejects programs that use a register past `R{count - 1}`, for programs that
+    /// are run by machines with a fixed number of registers.
+    pub fn with_register_count(mut self, count: u64) -> Self {
+        self.register_count = Some(count);
+        self
+    }
+
+    pub fn parse<R: Read>(&self, mut r: R) -> Result<Program, MooParseError> {
+        let mut source = String::new();
+        r.read_to_string(&mut source)?;
+        self.parse_string(&source)
+    }
+
+    pub fn parse_string(&self, source: &str) -> Result<Program, MooParseError> {
+        let (program, includes) = parse_unit(source, "main", self.register_count)?;
+        if includes.is_empty() {
+            return Ok(program);
+        }
+        let mut resolver = IncludeResolver::new(self.register_count);
+        resolver.linker.add_entry_unit("main", program);
+        let directory = env::current_dir()?;
+        for include in includes {
+            resolver.include(directory.join(include), false)?;
+        }
+        Ok(resolver.linker.link()?)
+    }
+
+    pub fn parse_file<P: AsRef<Path>>(&self, path: P) -> Result<Program, MooParseError> {
+        let mut resolver = IncludeResolver::new(self.register_count);
+        resolver.include(path.as_ref().to_path_buf(), true)?;
+        Ok(resolver.linker.link()?)
+    }
+}
+
+/// Parses every file reachable through `.include`s exactly once and adds each of
+/// them to the linker as its own unit.
+struct IncludeResolver {
+    linker: Linker,
+    stack: Vec<PathBuf>,
+    visited: HashSet<PathBuf>,
+    register_count: Option<u64>,
+}
+
+impl IncludeResolver {
+    fn new(register_count: Option<u64>) -> Self {
+        IncludeResolver {
+            linker: Linker::new(),
+            stack: Vec::new(),
+            visited: HashSet::new(),
+            register_count,
+        }
+    }
+
+    fn include(&mut self, path: PathBuf, entry: bool) -> Result<(), MooParseError> {
+        let canonical = fs::canonicalize(&path)?;
+        if self.stack.contains(&canonical) {
+            let mut cycle = self.stack.clone();
+            cycle.push(canonical);
+            return Err(MooParseError::IncludeCycle(cycle));
+        }
+        if !self.visited.insert(canonical.clone()) {
+            return Ok(());
+        }
+
+        let name = path.display().to_string();
+        let (program, includes) = parse_unit(&fs::read_to_string(&path)?, &name, self.register_count)?;
+        if entry {
+            self.linker.add_entry_unit(name, program);
+        } else {
+            self.linker.add_unit(name, program);
+        }
+
+        let directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        self.stack.push(canonical);
+        for include in includes {
+            self.include(directory.join(include), false)?;
+        }
+        self.stack.pop();
+        Ok(())
+    }
+}
+
+/// Parses a single source file into a program without resolving its includes.
+/// The paths the source wants to include are returned alongside the program.
+fn parse_unit(
+    source: &str,
+    unit: &str,
+    register_count: Option<u64>,
+) -> Result<(Program, Vec<String>), MooParseError> {
     let mut instructions = Vec::new();
+    let mut locations = Vec::new();
     let mut labels = HashMap::new();
-    let mut line_count = 0;
+    let mut exports = HashSet::new();
+    let mut includes = Vec::new();
+    // Local labels like `.loop` belong to the closest global label defined before them.
+    let mut scope = String::new();
+    let mut anonymous: HashMap<String, Vec<u64>> = HashMap::new();
+
+    let mut line_number = 1;
+    for statement in source.split(';') {
+        let line = statement.trim();
+        let leading = statement.len() - statement.trim_start().len();
+        let statement_line = line_number + statement[..leading].matches('\n').count();
+        line_number += statement.matches('\n').count();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some((directive, argument)) = split_directive(line) {
+            match directive.as_str() {
+                ".include" => includes.push(parse_include_path(argument)?),
+                ".export" => exports.extend(
+                    argument
+                        .split(',')
+                        .map(|s| s.trim())
+                        .filter(|s| !s.is_empty())
+                        .map(|s| s.to_string()),
+                ),
+                _ => return Err(MooParseError::InvalidDirective(line.to_string())),
+            }
+            continue;
+        }
 
-    for line in source
-        .split(';')
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
-    {   
         let label_and_instruction: Vec<_> = line.split(':').map(|s| s.trim()).collect();
-        match label_and_instruction.len() {
-            1 => {
-                instructions.push(parse_instruction(label_and_instruction[0])?);
-            },
+        let address = instructions.len() as u64;
+        let instruction = match label_and_instruction.len() {
+            1 => label_and_instruction[0],
             2 => {
-                labels.insert(label_and_instruction[0].to_string(), line_count);
-                instructions.push(parse_instruction(label_and_instruction[1])?);
+                let label = label_and_instruction[0];
+                if label.starts_with('.') {
+                    labels.insert(format!("{}{}", scope, label), address);
+                } else if is_anonymous_label(label) {
+                    anonymous.entry(label.to_string()).or_default().push(address);
+                    labels.insert(anonymous_label_name(label, address), address);
+                } else if label.starts_with(|c: char| c.is_ascii_digit()) {
+                    return Err(MooParseError::InvalidLabel(label.to_string()));
+                } else {
+                    scope = label.to_string();
+                    labels.insert(label.to_string(), address);
+                }
+                label_and_instruction[1]
             },
             _ => return Err(MooParseError::InvalidLineStructure(line.to_string())),
+        };
+
+        let mut command = parse_instruction(instruction)?;
+        if let Some(count) = register_count {
+            for param in command.params() {
+                if let Param::Register(register) = param {
+                    if register >= count {
+                        return Err(MooParseError::RegisterOutOfRange(line.to_string()));
+                    }
+                }
+            }
+        }
+        if let Some(label) = command.label_mut() {
+            if label.starts_with('.') {
+                *label = format!("{}{}", scope, label);
+            }
         }
-        line_count += 1;
+        instructions.push(command);
+        locations.push(SourceLocation {
+            unit: unit.to_string(),
+            line: statement_line,
+        });
+    }
+
+    // Anonymous references can point forward so they can only be resolved once
+    // every label in the unit is known.
+    for (address, command) in instructions.iter_mut().enumerate() {
+        if let Some(label) = command.label_mut() {
+            if let Some(resolved) = resolve_anonymous_reference(label, address as u64, &anonymous)? {
+                *label = resolved;
+            }
+        }
+    }
+    let program = Program::new(instructions, labels)
+        .with_exports(exports)
+        .with_locations(locations);
+    Ok((program, includes))
+}
+
+/// Splits a directive line like `.include "lib.moo"` into the lowercased directive
+/// and its argument. Returns `None` for lines that are not directives, including
+/// lines that start with a local label like `.loop: jump .loop`.
+fn split_directive(line: &str) -> Option<(String, &str)> {
+    if !line.starts_with('.') {
+        return None;
+    }
+    let (directive, argument) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, ""),
+    };
+    let directive = directive.to_lowercase();
+    match directive.as_str() {
+        ".include" | ".export" => Some((directive, argument)),
+        _ if line.contains(':') => None,
+        _ => Some((directive, argument)),
+    }
+}
+
+/// Anonymous labels are labels made up only of digits. They can be defined any
+/// number of times and are referred to with `1f` (the next `1` after the
+/// referring instruction) and `1b` (the closest `1` at or before it).
+///
+/// Label operands are never parsed with `parse_param`, so `1f` in a label
+/// position is always a forward reference and never the float constant 1.
+/// To keep this unambiguous, non-anonymous labels may not start with a digit.
+fn is_anonymous_label(label: &str) -> bool {
+    !label.is_empty() && label.chars().all(|c| c.is_ascii_digit())
+}
+
+/// The name an anonymous label defined at `address` gets in `Program`'s label map.
+/// As ordinary labels can't start with a digit this can't clash with them.
+fn anonymous_label_name(label: &str, address: u64) -> String {
+    format!("{}@{}", label, address)
+}
+
+fn resolve_anonymous_reference(
+    reference: &str,
+    address: u64,
+    anonymous: &HashMap<String, Vec<u64>>,
+) -> Result<Option<String>, MooParseError> {
+    if is_anonymous_label(reference) {
+        return Err(MooParseError::InvalidLabel(reference.to_string()));
+    }
+    let (label, forward) = if let Some(label) = reference.strip_suffix('f') {
+        (label, true)
+    } else if let Some(label) = reference.strip_suffix('b') {
+        (label, false)
+    } else {
+        return Ok(None);
+    };
+    if !is_anonymous_label(label) {
+        return Ok(None);
+    }
+    let definitions = anonymous.get(label).map(|d| d.as_slice()).unwrap_or(&[]);
+    let target = if forward {
+        definitions.iter().find(|&&d| d > address)
+    } else {
+        definitions.iter().rev().find(|&&d| d <= address)
+    };
+    match target {
+        Some(&target) => Ok(Some(anonymous_label_name(label, target))),
+        None => Err(MooParseError::UnresolvedLabel(reference.to_string())),
+    }
+}
+
+fn parse_include_path(argument: &str) -> Result<String, MooParseError> {
+    if argument.len() >= 2 && argument.starts_with('"') && argument.ends_with('"') {
+        Ok(argument[1..argument.len() - 1].to_string())
+    } else {
+        Err(MooParseError::InvalidDirective(format!(".include {}", argument)))
     }
-    Ok(Program::new(instructions, labels))
 }
 
 fn parse_instruction(instruction: &str) -> Result<Command, MooParseError> {
     let params: Vec<_> = instruction.split(' ').collect();
-    match &*params[0].to_lowercase().trim() {
+    match params[0].to_lowercase().trim() {
         i @ "fadd" | i @ "fsub" | i @ "fmul" | i @ "fdiv" |
         i @ "iadd" | i @ "isub" | i @ "imul" | i @ "idiv" |
         i @ "uadd" | i @ "usub" | i @ "umul" | i @ "udiv" 
         => {
-            if let (p1, p2, p3 @ Param::Register(_)) = parse_three_params(&params)? {
+            if let (p1, p2, p3 @ Param::Register(_)) | (p1, p2, p3 @ Param::Output(_)) =
+                parse_three_params(&params)?
+            {
+                check_operands(instruction, &[p1, p2])?;
                 Ok(
                     match i {
                     "fadd" => Command::FAdd(p1, p2, p3),
@@ -66,28 +326,89 @@ fn parse_instruction(instruction: &str) -> Result<Command, MooParseError> {
                 )
             } else {
                 Err(MooParseError::InvalidSyntax(format!(
-                    "Third parameter in \"{}\" should be a register",
-                    instruction.to_string()
+                    "Third parameter in \"{}\" should be a register or an output",
+                    instruction
                 )))
             }
         },
         "load" => {
             match parse_two_params(&params)? {
-                (p1, p2 @ Param::Register(_)) => {
+                (p1, p2 @ Param::Register(_)) | (p1, p2 @ Param::Output(_)) => {
+                    check_operands(instruction, &[p1])?;
                     Ok(Command::Load(p1, p2))
                 }
                 _ => Err(MooParseError::InvalidParam(instruction.to_string())),
             }
         },
-        "jump" => {
+        i @ "icmp" | i @ "ucmp" | i @ "fcmp" => {
+            let (p1, p2) = parse_two_params(&params)?;
+            check_operands(instruction, &[p1, p2])?;
+            Ok(match i {
+                "icmp" => Command::ICmp(p1, p2),
+                "ucmp" => Command::UCmp(p1, p2),
+                "fcmp" => Command::FCmp(p1, p2),
+                _ => unreachable!(),
+            })
+        },
+        i @ "jump" | i @ "jgre" | i @ "jless" | i @ "jeq" | i @ "jneq" => {
             // We just take whatever is after the jump and trim it to make it a label.
-            Ok(Command::Jump(instruction[4..instruction.len()].trim().to_string()))
+            let label = parse_label(operands(instruction))?;
+            Ok(match i {
+                "jump" => Command::Jump(label),
+                "jgre" => Command::JGre(label),
+                "jless" => Command::JLess(label),
+                "jeq" => Command::JEq(label),
+                "jneq" => Command::JNeq(label),
+                _ => unreachable!(),
+            })
+        },
+        i @ "jfneg" | i @ "jineg" => {
+            let operands = operands(instruction);
+            let (number, label) = match operands.find(char::is_whitespace) {
+                Some(split) => (&operands[..split], &operands[split..]),
+                None => return Err(MooParseError::InvalidParamAmount),
+            };
+            let number = parse_param(number)?;
+            check_operands(instruction, &[number])?;
+            let label = parse_label(label)?;
+            Ok(match i {
+                "jfneg" => Command::JFNeg(number, label),
+                "jineg" => Command::JINeg(number, label),
+                _ => unreachable!(),
+            })
         },
         _ => Err(MooParseError::CommandNotFound(instruction.to_string())),
     }
 }
 
-pub fn parse_three_params(params: &Vec<&str>) -> Result<(Param, Param, Param), MooParseError> {
+/// Fails if any of the params an instruction reads from is an output.
+fn check_operands(instruction: &str, operands: &[Param]) -> Result<(), MooParseError> {
+    if operands.iter().any(|operand| matches!(operand, Param::Output(_))) {
+        Err(MooParseError::InvalidSyntax(format!("\"{}\" reads from an output", instruction)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns everything after the mnemonic of `instruction`.
+fn operands(instruction: &str) -> &str {
+    let instruction = instruction.trim();
+    match instruction.find(char::is_whitespace) {
+        Some(i) => instruction[i..].trim(),
+        None => "",
+    }
+}
+
+fn parse_label(label: &str) -> Result<String, MooParseError> {
+    let label = label.trim();
+    if label.is_empty() {
+        Err(MooParseError::InvalidParamAmount)
+    } else {
+        Ok(label.to_string())
+    }
+}
+
+pub fn parse_three_params(params: &[&str]) -> Result<(Param, Param, Param), MooParseError> {
     if params.len() == 4 {
         let param1 = parse_param(params[1])?;
         let param2 = parse_param(params[2])?;
@@ -98,7 +419,7 @@ pub fn parse_three_params(params: &Vec<&str>) -> Result<(Param, Param, Param), M
     }
 }
 
-pub fn parse_two_params(params: &Vec<&str>) -> Result<(Param, Param), MooParseError> {
+pub fn parse_two_params(params: &[&str]) -> Result<(Param, Param), MooParseError> {
     if params.len() == 3 {
         let param1 = parse_param(params[1])?;
         let param2 = parse_param(params[2])?;
@@ -110,11 +431,21 @@ pub fn parse_two_params(params: &Vec<&str>) -> Result<(Param, Param), MooParseEr
 
 pub fn parse_param(param: &str) -> Result<Param, MooParseError> {
     let param = param.trim().to_lowercase();
-    if param.starts_with('r') {
-        param[1..]
+    if let Some(register) = param.strip_prefix('r') {
+        register
             .parse()
             .map(Param::Register)
             .map_err(|_| MooParseError::InvalidParam(param.to_string()))
+    } else if let Some(channel) = param.strip_prefix('i') {
+        channel
+            .parse()
+            .map(Param::Input)
+            .map_err(|_| MooParseError::InvalidParam(param.to_string()))
+    } else if let Some(channel) = param.strip_prefix('o') {
+        channel
+            .parse()
+            .map(Param::Output)
+            .map_err(|_| MooParseError::InvalidParam(param.to_string()))
     } else if param.ends_with('f') {
         param[..(param.len() - 1)]
             .parse()
@@ -144,6 +475,12 @@ pub enum MooParseError {
     InvalidParam(String),
     InvalidSyntax(String),
     InvalidLineStructure(String),
+    InvalidDirective(String),
+    InvalidLabel(String),
+    UnresolvedLabel(String),
+    RegisterOutOfRange(String),
+    IncludeCycle(Vec<PathBuf>),
+    LinkError(LinkError),
 }
 
 impl From<io::Error> for MooParseError {
@@ -151,3 +488,9 @@ impl From<io::Error> for MooParseError {
         MooParseError::IOError(err)
     }
 }
+
+impl From<LinkError> for MooParseError {
+    fn from(err: LinkError) -> Self {
+        MooParseError::LinkError(err)
+    }
+}
@@ -1,6 +1,9 @@
 use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
 
-use crate::moo::{parse_param, parse_program_from_string, MooParseError};
+use crate::moo::{parse_param, parse_program_from_file, parse_program_from_string, MooParseError, MooParser};
 use crate::vm::{Command, Param};
 use crate::program::Program;
 
@@ -367,6 +370,206 @@ fn jump_parse_test() {
     assert_eq!(program, expected_program);
 }
 
+#[test]
+fn export_directive_parsing_test() {
+    let program = parse_program_from_string(".export a, b; a: load 1f R0; b: load 2f R0;").unwrap();
+    assert!(program.exports().contains("a"));
+    assert!(program.exports().contains("b"));
+    assert_eq!(program.get_address("b"), 1);
+}
+
+#[test]
+fn invalid_directive_test() {
+    match parse_program_from_string(".frobnicate; load 1f R0;") {
+        Err(MooParseError::InvalidDirective(_)) => {},
+        other => panic!("expected an InvalidDirective error but got {:?}", other),
+    }
+    match parse_program_from_string(".include lib.moo;") {
+        Err(MooParseError::InvalidDirective(_)) => {},
+        other => panic!("expected an InvalidDirective error but got {:?}", other),
+    }
+}
+
+fn include_test_directory(name: &str) -> PathBuf {
+    let directory = env::temp_dir().join(format!("moofloom_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+#[test]
+fn include_parsing_test() {
+    let directory = include_test_directory("include");
+    fs::write(
+        directory.join("main.moo"),
+        ".include \"lib/math.moo\"; .include \"lib/math.moo\"; start: jump square;",
+    ).unwrap();
+    fs::create_dir_all(directory.join("lib")).unwrap();
+    fs::write(
+        directory.join("lib/math.moo"),
+        ".include \"util.moo\"; .export square; square: jump helper;",
+    ).unwrap();
+    fs::write(
+        directory.join("lib/util.moo"),
+        ".export helper; helper: load 1f R0;",
+    ).unwrap();
+
+    let program = parse_program_from_file(directory.join("main.moo")).unwrap();
+    assert_eq!(program.len(), 3);
+    assert_eq!(program.get_address("square"), 1);
+    assert_eq!(program.get_address("helper"), 2);
+    assert_eq!(program[0], Command::Jump("square".to_string()));
+    assert_eq!(program[1], Command::Jump("helper".to_string()));
+    let location = program.location(2).unwrap();
+    assert!(location.unit.ends_with("util.moo"));
+    assert_eq!(location.line, 1);
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn include_keeps_main_labels_test() {
+    let directory = include_test_directory("include_main_labels");
+    fs::write(directory.join("main.moo"), ".include \"lib.moo\"; loop: jump square; jump loop;").unwrap();
+    fs::write(directory.join("lib.moo"), ".export square; square: jump loop; loop: jump square;").unwrap();
+
+    let program = parse_program_from_file(directory.join("main.moo")).unwrap();
+    assert_eq!(program.get_address("loop"), 0);
+    assert_eq!(program[1], Command::Jump("loop".to_string()));
+    let library_loop = format!("{}:loop", directory.join("lib.moo").display());
+    assert_eq!(program.get_address(&library_loop), 3);
+    assert_eq!(program[2], Command::Jump(library_loop));
+
+    let source = format!(".include \"{}\"; loop: jump square; jump loop;", directory.join("lib.moo").display());
+    let program = parse_program_from_string(&source).unwrap();
+    assert_eq!(program.get_address("loop"), 0);
+    assert_eq!(program[1], Command::Jump("loop".to_string()));
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn include_cycle_test() {
+    let directory = include_test_directory("include_cycle");
+    fs::write(directory.join("a.moo"), ".include \"b.moo\"; load 1f R0;").unwrap();
+    fs::write(directory.join("b.moo"), ".include \"a.moo\"; load 2f R0;").unwrap();
+
+    match parse_program_from_file(directory.join("a.moo")) {
+        Err(MooParseError::IncludeCycle(cycle)) => {
+            assert_eq!(cycle.len(), 3);
+            assert_eq!(cycle[0], cycle[2]);
+        },
+        other => panic!("expected an IncludeCycle error but got {:?}", other),
+    }
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn invalid_operand_and_destination_test() {
+    for source in &["icmp O0 1i;", "load O0 R1;", "fadd O0 1f R0;", "uadd 1u O1 R0;", "jineg O0 top; top: load 1u R0;"] {
+        match parse_program_from_string(source) {
+            Err(MooParseError::InvalidSyntax(message)) => assert!(message.ends_with("reads from an output"), "{}", message),
+            other => panic!("expected {} to read from an output but got {:?}", source, other),
+        }
+    }
+    for source in &["load 1u I0;", "load 1u 2u;", "iadd 1i 2i I0;", "fmul 1f 2f 3f;"] {
+        assert!(parse_program_from_string(source).is_err(), "{} should not parse", source);
+    }
+    assert!(parse_program_from_string("uadd I0 R1 O0; load I1 O1;").is_ok());
+}
+
+#[test]
+fn compare_and_conditional_jump_parsing_test() {
+    let source = "top: icmp R0 1i; ucmp 1u R1; fcmp R2 2f; jgre top; jless top; jeq top; jneq top; \
+        jfneg R0 top; jineg -1i top;";
+    let program = parse_program_from_string(source).unwrap();
+    let top = || "top".to_string();
+    assert_eq!(program[0], Command::ICmp(Param::Register(0), Param::IConstant(1)));
+    assert_eq!(program[1], Command::UCmp(Param::UConstant(1), Param::Register(1)));
+    assert_eq!(program[2], Command::FCmp(Param::Register(2), Param::FConstant(2.)));
+    assert_eq!(program[3], Command::JGre(top()));
+    assert_eq!(program[4], Command::JLess(top()));
+    assert_eq!(program[5], Command::JEq(top()));
+    assert_eq!(program[6], Command::JNeq(top()));
+    assert_eq!(program[7], Command::JFNeg(Param::Register(0), top()));
+    assert_eq!(program[8], Command::JINeg(Param::IConstant(-1), top()));
+}
+
+#[test]
+fn local_label_parsing_test() {
+    let source = r#"first: load 0u R0;
+    .loop: jump .loop;
+    second: load 0u R0;
+    .loop: jump .loop;
+    jump first.loop;"#;
+    let program = parse_program_from_string(source).unwrap();
+    assert_eq!(program.get_address("first.loop"), 1);
+    assert_eq!(program.get_address("second.loop"), 3);
+    assert_eq!(program[1], Command::Jump("first.loop".to_string()));
+    assert_eq!(program[3], Command::Jump("second.loop".to_string()));
+    assert_eq!(program[4], Command::Jump("first.loop".to_string()));
+}
+
+#[test]
+fn anonymous_label_parsing_test() {
+    let source = r#"1: jump 1f;
+    1: jump 1b;
+    jump 1b;
+    jfneg 1f 1f;
+    1: jump 2f;
+    2: jump 1b;"#;
+    let program = parse_program_from_string(source).unwrap();
+    assert_eq!(program[0], Command::Jump("1@1".to_string()));
+    assert_eq!(program[1], Command::Jump("1@1".to_string()));
+    assert_eq!(program[2], Command::Jump("1@1".to_string()));
+    assert_eq!(program[3], Command::JFNeg(Param::FConstant(1.), "1@4".to_string()));
+    assert_eq!(program[4], Command::Jump("2@5".to_string()));
+    assert_eq!(program[5], Command::Jump("1@4".to_string()));
+    assert_eq!(program.get_address("1@4"), 4);
+}
+
+#[test]
+fn unresolved_anonymous_label_test() {
+    match parse_program_from_string("1: jump 1f;") {
+        Err(MooParseError::UnresolvedLabel(_)) => {},
+        other => panic!("expected an UnresolvedLabel error but got {:?}", other),
+    }
+    match parse_program_from_string("jump 3b; 3: jump 3b;") {
+        Err(MooParseError::UnresolvedLabel(_)) => {},
+        other => panic!("expected an UnresolvedLabel error but got {:?}", other),
+    }
+}
+
+#[test]
+fn invalid_label_test_digit_prefix() {
+    match parse_program_from_string("1f: jump 1f;") {
+        Err(MooParseError::InvalidLabel(_)) => {},
+        other => panic!("expected an InvalidLabel error but got {:?}", other),
+    }
+}
+
+#[test]
+fn register_count_test() {
+    let parser = MooParser::new().with_register_count(16);
+    assert_eq!(parser.parse_string("uadd R0 R15 R7;").unwrap(), parse_program_from_string("uadd R0 R15 R7;").unwrap());
+    match parser.parse_string("load 1u R0;\nloop: uadd R0 R16 R0;") {
+        Err(MooParseError::RegisterOutOfRange(statement)) => assert_eq!(statement, "loop: uadd R0 R16 R0"),
+        other => panic!("expected a RegisterOutOfRange error but got {:?}", other),
+    }
+    assert!(parse_program_from_string("load 1u R16;").is_ok());
+}
+
+#[test]
+fn input_and_output_param_parsing_test() {
+    assert_eq!(parse_param("I0").unwrap(), Param::Input(0));
+    assert_eq!(parse_param("o12").unwrap(), Param::Output(12));
+    invalid_param_check("I");
+    invalid_param_check("O1.5");
+    let program = parse_program_from_string("fadd I0 1f O1; load I1 O0;").unwrap();
+    assert_eq!(
+        program[0],
+        Command::FAdd(Param::Input(0), Param::FConstant(1.), Param::Output(1)),
+    );
+    assert_eq!(program[1], Command::Load(Param::Input(1), Param::Output(0)));
+}
+
 //TODO: do this test once parsing for u and i arithmetic operators work
 // #[test]
 // fn big_do_everything_loop_program() {
@@ -374,3 +577,12 @@ fn jump_parse_test() {
 
 //     "#;
 // }
+
+#[test]
+fn source_location_test() {
+    let program = parse_program_from_string("load 1u R0;\n\n  load 2u R1; load 3u R2\n;\nload 4u R3;").unwrap();
+    let lines: Vec<_> = program.locations().iter().map(|location| location.line).collect();
+    assert_eq!(lines, vec![1, 3, 3, 5]);
+    assert_eq!(program.location(0).unwrap().unit, "main");
+    assert_eq!(program.location(4), None);
+}
@@ -0,0 +1,370 @@
+use std::cmp::Ordering;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+
+use crate::cfg::Cfg;
+use crate::program::Program;
+use crate::vm::{Command, Param};
+
+/// Rewrites instructions whose operands are all known into `load`s of their results
+/// and replaces registers that hold known constants by the constants, following the
+/// values along the control flow graph. Conditional jumps that are known to jump
+/// become `jump`s.
+///
+/// Results are computed the way `MooMachine` computes them. Instructions that would
+/// fail, like divisions by zero, are left alone, as are float results that aren't
+/// finite. Registers are taken to start out as 0, so the folded program behaves the
+/// same as the original one when it is run from the start on a new machine. Nothing
+/// is assumed where the program can be entered at an exported label.
+pub fn fold_constants(program: &Program) -> Program {
+    let cfg = Cfg::new(program);
+    let order = cfg.reverse_postorder();
+    let mut entries: Vec<Option<Facts>> = vec![None; cfg.blocks().len()];
+    // Nothing is known where other programs can jump in.
+    for &entry in cfg.entries() {
+        entries[entry] = Some(Facts::unknown());
+    }
+    if !entries.is_empty() && !program.exports().iter().any(|label| program.labels().get(label) == Some(&0)) {
+        entries[0] = Some(Facts::start());
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &id in &order {
+            let mut facts = match &entries[id] {
+                Some(facts) => facts.clone(),
+                None => continue,
+            };
+            let block = &cfg.blocks()[id];
+            for address in block.start..block.end {
+                fold(&program[address as usize], &mut facts);
+            }
+            for &successor in &block.successors {
+                changed |= match &mut entries[successor] {
+                    Some(entry) => entry.join(&facts),
+                    entry @ None => {
+                        *entry = Some(facts.clone());
+                        true
+                    }
+                };
+            }
+        }
+    }
+
+    let mut folded = program.clone();
+    for (id, block) in cfg.blocks().iter().enumerate() {
+        let mut facts = match entries[id].take() {
+            Some(facts) => facts,
+            None => continue,
+        };
+        for address in block.start..block.end {
+            let command = fold(&program[address as usize], &mut facts);
+            if command != program[address as usize] {
+                folded.replace(address, command);
+            }
+        }
+    }
+    folded
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Known<T> {
+    Value(T),
+    Varying,
+}
+
+/// What is known about the machine at a point in the program.
+#[derive(Clone, Debug)]
+struct Facts {
+    // The constant each register holds, written the way it is loaded.
+    registers: BTreeMap<u64, Known<Param>>,
+    // What registers that aren't in the map hold.
+    rest: Known<Param>,
+    // `Value(None)` until something has been compared.
+    compare: Known<Option<Ordering>>,
+}
+
+const ZERO: Known<Param> = Known::Value(Param::UConstant(0));
+
+impl Facts {
+    /// Registers start out as 0 and nothing has been compared.
+    fn start() -> Self {
+        Facts {
+            registers: BTreeMap::new(),
+            rest: ZERO,
+            compare: Known::Value(None),
+        }
+    }
+
+    fn unknown() -> Self {
+        Facts {
+            registers: BTreeMap::new(),
+            rest: Known::Varying,
+            compare: Known::Varying,
+        }
+    }
+
+    fn register(&self, register: u64) -> Known<Param> {
+        self.registers.get(&register).copied().unwrap_or(self.rest)
+    }
+
+    fn write(&mut self, into: Param, known: Known<Param>) {
+        if let Param::Register(register) = into {
+            self.registers.insert(register, known);
+        }
+    }
+
+    /// Keeps only what is also known in `other`. Returns whether anything was lost.
+    fn join(&mut self, other: &Facts) -> bool {
+        let mut changed = false;
+        for (&register, known) in self.registers.iter_mut() {
+            if *known != Known::Varying && !same(*known, other.register(register)) {
+                *known = Known::Varying;
+                changed = true;
+            }
+        }
+        for (&register, &known) in &other.registers {
+            if let Entry::Vacant(entry) = self.registers.entry(register) {
+                if self.rest != Known::Varying && !same(self.rest, known) {
+                    entry.insert(Known::Varying);
+                    changed = true;
+                }
+            }
+        }
+        if self.rest != Known::Varying && !same(self.rest, other.rest) {
+            self.rest = Known::Varying;
+            changed = true;
+        }
+        if self.compare != Known::Varying && self.compare != other.compare {
+            self.compare = Known::Varying;
+            changed = true;
+        }
+        changed
+    }
+}
+
+/// Whether two facts about a register agree on its bits.
+fn same(a: Known<Param>, b: Known<Param>) -> bool {
+    match (a, b) {
+        (Known::Value(a), Known::Value(b)) => bits(a) == bits(b),
+        (Known::Varying, Known::Varying) => true,
+        _ => false,
+    }
+}
+
+/// The bits `load` stores for a constant.
+fn bits(constant: Param) -> u64 {
+    match constant {
+        Param::FConstant(float) => float.to_bits(),
+        Param::IConstant(integer) => integer as u64,
+        Param::UConstant(integer) => integer,
+        _ => unreachable!("only constants are known"),
+    }
+}
+
+/// A number in one of the domains instructions compute in, read the way
+/// `MooMachine` reads it.
+trait Number: Copy + PartialOrd {
+    /// Converts a constant operand into the domain.
+    fn from_constant(param: Param) -> Option<Self>;
+
+    /// Reads the bits of a register in the domain.
+    fn from_bits(bits: u64) -> Option<Self>;
+
+    /// The constant that is read as `self`, if there is one.
+    fn constant(self) -> Option<Param>;
+
+    fn read(param: Param, facts: &Facts) -> Option<Self> {
+        match param {
+            Param::Register(register) => match facts.register(register) {
+                Known::Value(value) => Self::from_bits(bits(value)),
+                Known::Varying => None,
+            },
+            _ => Self::from_constant(param),
+        }
+    }
+
+    /// Replaces a register that holds a known value by a constant in the domain.
+    fn substitute(param: Param, facts: &Facts) -> Param {
+        match param {
+            Param::Register(_) => Self::read(param, facts).and_then(Self::constant).unwrap_or(param),
+            _ => param,
+        }
+    }
+}
+
+impl Number for f64 {
+    fn from_constant(param: Param) -> Option<Self> {
+        match param {
+            Param::FConstant(float) => Some(float),
+            Param::IConstant(integer) => Some(integer as f64),
+            Param::UConstant(integer) => Some(integer as f64),
+            _ => None,
+        }
+    }
+
+    fn from_bits(bits: u64) -> Option<Self> {
+        // Reading NaN or infinity from a register fails.
+        Some(f64::from_bits(bits)).filter(|float| float.is_finite())
+    }
+
+    fn constant(self) -> Option<Param> {
+        Some(Param::FConstant(self)).filter(|_| self.is_finite())
+    }
+}
+
+impl Number for i64 {
+    fn from_constant(param: Param) -> Option<Self> {
+        match param {
+            Param::FConstant(float) => Some(float as i64),
+            Param::IConstant(integer) => Some(integer),
+            Param::UConstant(integer) => Some(integer as i64),
+            _ => None,
+        }
+    }
+
+    fn from_bits(bits: u64) -> Option<Self> {
+        Some(bits as i64)
+    }
+
+    fn constant(self) -> Option<Param> {
+        Some(Param::IConstant(self))
+    }
+}
+
+impl Number for u64 {
+    fn from_constant(param: Param) -> Option<Self> {
+        match param {
+            Param::FConstant(float) => Some(float as u64),
+            Param::IConstant(integer) => Some(integer as u64),
+            Param::UConstant(integer) => Some(integer),
+            _ => None,
+        }
+    }
+
+    fn from_bits(bits: u64) -> Option<Self> {
+        Some(bits)
+    }
+
+    fn constant(self) -> Option<Param> {
+        Some(Param::UConstant(self))
+    }
+}
+
+/// Whether an instruction can store into `into` without panicking.
+fn storable(into: Param) -> bool {
+    matches!(into, Param::Register(_) | Param::Output(_))
+}
+
+/// Returns `command` with everything known folded in, and updates `facts` for its
+/// execution.
+fn fold(command: &Command, facts: &mut Facts) -> Command {
+    use self::Command::*;
+
+    match *command {
+        FAdd(a, b, into) => operation(FAdd, |a: f64, b| Some(a + b), a, b, into, facts),
+        FSub(a, b, into) => operation(FSub, |a: f64, b| Some(a - b), a, b, into, facts),
+        FMul(a, b, into) => operation(FMul, |a: f64, b| Some(a * b), a, b, into, facts),
+        FDiv(a, b, into) => operation(FDiv, |a: f64, b| if b == 0. { None } else { Some(a / b) }, a, b, into, facts),
+        UAdd(a, b, into) => operation(UAdd, |a: u64, b| Some(a.wrapping_add(b)), a, b, into, facts),
+        USub(a, b, into) => operation(USub, |a: u64, b| Some(a.wrapping_sub(b)), a, b, into, facts),
+        UMul(a, b, into) => operation(UMul, |a: u64, b| Some(a.wrapping_mul(b)), a, b, into, facts),
+        UDiv(a, b, into) => operation(UDiv, |a: u64, b| a.checked_div(b), a, b, into, facts),
+        IAdd(a, b, into) => operation(IAdd, |a: i64, b| Some(a.wrapping_add(b)), a, b, into, facts),
+        ISub(a, b, into) => operation(ISub, |a: i64, b| Some(a.wrapping_sub(b)), a, b, into, facts),
+        IMul(a, b, into) => operation(IMul, |a: i64, b| Some(a.wrapping_mul(b)), a, b, into, facts),
+        IDiv(a, b, into) => operation(IDiv, |a: i64, b| if b == 0 { None } else { Some(a.wrapping_div(b)) }, a, b, into, facts),
+        ICmp(a, b) => compare::<i64>(ICmp, a, b, facts),
+        UCmp(a, b) => compare::<u64>(UCmp, a, b, facts),
+        FCmp(a, b) => compare::<f64>(FCmp, a, b, facts),
+        Load(what, into) => {
+            if !storable(into) {
+                return command.clone();
+            }
+            let value = match what {
+                Param::Register(register) => match facts.register(register) {
+                    Known::Value(value) => Some(value),
+                    Known::Varying => None,
+                },
+                Param::FConstant(_) | Param::IConstant(_) | Param::UConstant(_) => Some(what),
+                Param::Input(_) | Param::Output(_) => None,
+            };
+            facts.write(into, value.map_or(Known::Varying, Known::Value));
+            Load(value.unwrap_or(what), into)
+        }
+        JINeg(number, ref label) => {
+            // Only registers, inputs and signed constants can be tested.
+            let number = match number {
+                Param::Register(_) => i64::substitute(number, facts),
+                _ => number,
+            };
+            match number {
+                Param::IConstant(number) if number < 0 => Jump(label.clone()),
+                _ => JINeg(number, label.clone()),
+            }
+        }
+        JFNeg(number, ref label) => {
+            let number = match number {
+                Param::Register(_) => f64::substitute(number, facts),
+                _ => number,
+            };
+            match number {
+                Param::FConstant(number) if number < 0. => Jump(label.clone()),
+                _ => JFNeg(number, label.clone()),
+            }
+        }
+        JGre(ref label) | JLess(ref label) | JEq(ref label) | JNeq(ref label) => {
+            let jumps = match (command, facts.compare) {
+                (_, Known::Value(None)) | (_, Known::Varying) => false,
+                (JGre(_), Known::Value(Some(ordering))) => ordering == Ordering::Greater,
+                (JLess(_), Known::Value(Some(ordering))) => ordering == Ordering::Less,
+                (JEq(_), Known::Value(Some(ordering))) => ordering == Ordering::Equal,
+                (_, Known::Value(Some(ordering))) => ordering != Ordering::Equal,
+            };
+            if jumps {
+                Jump(label.clone())
+            } else {
+                command.clone()
+            }
+        }
+        Jump(_) => command.clone(),
+    }
+}
+
+fn operation<T: Number>(
+    rebuild: fn(Param, Param, Param) -> Command,
+    op: fn(T, T) -> Option<T>,
+    a: Param,
+    b: Param,
+    into: Param,
+    facts: &mut Facts,
+) -> Command {
+    if !storable(into) {
+        return rebuild(a, b, into);
+    }
+    let result = match (T::read(a, facts), T::read(b, facts)) {
+        (Some(a), Some(b)) => op(a, b).and_then(T::constant),
+        _ => None,
+    };
+    match result {
+        Some(result) => {
+            facts.write(into, Known::Value(result));
+            Command::Load(result, into)
+        }
+        None => {
+            let command = rebuild(T::substitute(a, facts), T::substitute(b, facts), into);
+            facts.write(into, Known::Varying);
+            command
+        }
+    }
+}
+
+fn compare<T: Number>(rebuild: fn(Param, Param) -> Command, a: Param, b: Param, facts: &mut Facts) -> Command {
+    let ordering = match (T::read(a, facts), T::read(b, facts)) {
+        (Some(a), Some(b)) => a.partial_cmp(&b),
+        _ => None,
+    };
+    facts.compare = ordering.map_or(Known::Varying, |ordering| Known::Value(Some(ordering)));
+    rebuild(T::substitute(a, facts), T::substitute(b, facts))
+}
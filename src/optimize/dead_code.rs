@@ -0,0 +1,61 @@
+use super::remove;
+use crate::cfg::Cfg;
+use crate::program::Program;
+use crate::vm::{Command, Param};
+
+/// Removes the instructions that can't be reached from address 0 or an exported label.
+pub fn remove_unreachable(program: &Program) -> Program {
+    let cfg = Cfg::new(program);
+    let reachable = cfg.reachable();
+    let keep: Vec<bool> = (0..program.len() as u64)
+        .map(|address| reachable[cfg.block_at(address)])
+        .collect();
+    remove(program, &keep)
+}
+
+/// Removes writes to registers that are written again or never read afterwards.
+/// Instructions that take inputs or could fail are kept even when what they write
+/// is never read. Registers aren't looked at once the program halts.
+pub fn remove_dead_stores(program: &Program) -> Program {
+    let live_after = Cfg::new(program).live_after(program);
+    let keep: Vec<bool> = program
+        .commands()
+        .iter()
+        .enumerate()
+        .map(|(address, command)| match command.destination() {
+            Some(Param::Register(register)) => {
+                live_after[address].contains(&register) || !removable(command)
+            }
+            _ => true,
+        })
+        .collect();
+    remove(program, &keep)
+}
+
+/// Whether executing `command` only writes its destination.
+fn removable(command: &Command) -> bool {
+    use self::Command::*;
+
+    let operands = command.operands();
+    if operands.iter().any(|param| matches!(param, Param::Input(_) | Param::Output(_))) {
+        return false;
+    }
+    match *command {
+        // Reading NaN or infinity from a register fails.
+        FAdd(..) | FSub(..) | FMul(..) => !operands.iter().any(|param| matches!(param, Param::Register(_))),
+        FDiv(a, b, _) => !matches!(a, Param::Register(_)) && nonzero(b),
+        IDiv(_, b, _) | UDiv(_, b, _) => nonzero(b),
+        _ => true,
+    }
+}
+
+/// Whether `divisor` is a constant that isn't 0 in any domain.
+fn nonzero(divisor: Param) -> bool {
+    match divisor {
+        // Converting to an integer rounds towards 0.
+        Param::FConstant(divisor) => divisor.abs() >= 1.,
+        Param::IConstant(divisor) => divisor != 0,
+        Param::UConstant(divisor) => divisor != 0,
+        _ => false,
+    }
+}
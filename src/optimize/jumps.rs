@@ -0,0 +1,46 @@
+use std::collections::HashSet;
+
+use super::remove;
+use crate::program::Program;
+use crate::vm::Command;
+
+/// Points jumps that go to a `jump` at where that `jump` goes instead, following
+/// chains of them.
+pub fn thread_jumps(program: &Program) -> Program {
+    let mut threaded = program.clone();
+    for (address, command) in program.commands().iter().enumerate() {
+        let label = match command.label() {
+            Some(label) => label,
+            None => continue,
+        };
+        let mut target = label;
+        let mut seen = HashSet::new();
+        while let Some(&next) = program.labels().get(target) {
+            match program.commands().get(next as usize) {
+                // Jumps that go around in a circle are only followed once around.
+                Some(Command::Jump(next)) if seen.insert(next) => target = next,
+                _ => break,
+            }
+        }
+        if target != label {
+            let mut command = command.clone();
+            *command.label_mut().unwrap() = target.to_string();
+            threaded.replace(address as u64, command);
+        }
+    }
+    threaded
+}
+
+/// Removes `jump`s to the instruction right after them.
+pub fn remove_jumps_to_next(program: &Program) -> Program {
+    let keep: Vec<bool> = program
+        .commands()
+        .iter()
+        .enumerate()
+        .map(|(address, command)| match command {
+            Command::Jump(label) => program.labels().get(label) != Some(&(address as u64 + 1)),
+            _ => true,
+        })
+        .collect();
+    remove(program, &keep)
+}
@@ -0,0 +1,178 @@
+use std::fmt;
+
+use crate::program::Program;
+
+mod constants;
+mod dead_code;
+mod jumps;
+
+pub use self::constants::fold_constants;
+pub use self::dead_code::{remove_dead_stores, remove_unreachable};
+pub use self::jumps::{remove_jumps_to_next, thread_jumps};
+
+#[cfg(test)]
+mod optimize_test;
+
+/// A transformation of a `Program` that keeps what it does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    FoldConstants,
+    ThreadJumps,
+    RemoveJumpsToNext,
+    RemoveUnreachable,
+    RemoveDeadStores,
+}
+
+impl Pass {
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::FoldConstants => "fold-constants",
+            Pass::ThreadJumps => "thread-jumps",
+            Pass::RemoveJumpsToNext => "remove-jumps-to-next",
+            Pass::RemoveUnreachable => "remove-unreachable",
+            Pass::RemoveDeadStores => "remove-dead-stores",
+        }
+    }
+
+    pub fn run(self, program: &Program) -> Program {
+        match self {
+            Pass::FoldConstants => fold_constants(program),
+            Pass::ThreadJumps => thread_jumps(program),
+            Pass::RemoveJumpsToNext => remove_jumps_to_next(program),
+            Pass::RemoveUnreachable => remove_unreachable(program),
+            Pass::RemoveDeadStores => remove_dead_stores(program),
+        }
+    }
+}
+
+/// Runs passes over a program in order, over and over until they stop changing it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pipeline {
+    passes: Vec<Pass>,
+    max_rounds: usize,
+}
+
+impl Pipeline {
+    pub fn new(passes: Vec<Pass>) -> Self {
+        Pipeline { passes, max_rounds: 10 }
+    }
+
+    /// Stops after the passes have been run `max_rounds` times even if they still
+    /// change the program.
+    pub fn with_max_rounds(mut self, max_rounds: usize) -> Self {
+        self.max_rounds = max_rounds;
+        self
+    }
+
+    pub fn run(&self, program: &Program) -> (Program, OptimizationReport) {
+        let mut program = program.clone();
+        let mut report = OptimizationReport {
+            before: program.len(),
+            after: program.len(),
+            passes: Vec::new(),
+        };
+        for _ in 0..self.max_rounds {
+            let start = program.clone();
+            for &pass in &self.passes {
+                let before = program.len();
+                program = pass.run(&program);
+                report.passes.push(PassReport {
+                    pass,
+                    before,
+                    after: program.len(),
+                });
+            }
+            if program == start {
+                break;
+            }
+        }
+        report.after = program.len();
+        (program, report)
+    }
+}
+
+/// Every pass, in an order where each one leaves work for the ones after it.
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline::new(vec![
+            Pass::FoldConstants,
+            Pass::ThreadJumps,
+            Pass::RemoveUnreachable,
+            Pass::RemoveJumpsToNext,
+            Pass::RemoveDeadStores,
+        ])
+    }
+}
+
+/// Runs the default `Pipeline` over `program`.
+pub fn optimize(program: &Program) -> (Program, OptimizationReport) {
+    Pipeline::default().run(program)
+}
+
+/// How many instructions a program had before and after it was optimized.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OptimizationReport {
+    pub before: usize,
+    pub after: usize,
+    /// Every pass that was run, in order.
+    pub passes: Vec<PassReport>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PassReport {
+    pub pass: Pass,
+    pub before: usize,
+    pub after: usize,
+}
+
+impl fmt::Display for OptimizationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} instructions before, {} after", self.before, self.after)?;
+        for pass in &self.passes {
+            if pass.after != pass.before {
+                writeln!(f, "{:<20} {:>6} -> {}", pass.pass.name(), pass.before, pass.after)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Removes the instructions `keep` is false for. Labels at removed instructions move
+/// to the next instruction that is kept.
+fn remove(program: &Program, keep: &[bool]) -> Program {
+    if keep.iter().all(|&keep| keep) {
+        return program.clone();
+    }
+    let mut moved = Vec::with_capacity(program.len() + 1);
+    let mut next = 0;
+    for &keep in keep {
+        moved.push(next);
+        if keep {
+            next += 1;
+        }
+    }
+    moved.push(next);
+
+    let commands = program
+        .commands()
+        .iter()
+        .zip(keep)
+        .filter(|&(_, &keep)| keep)
+        .map(|(command, _)| command.clone())
+        .collect();
+    let labels = program
+        .labels()
+        .iter()
+        .map(|(label, &address)| (label.clone(), moved[(address as usize).min(program.len())]))
+        .collect();
+    let locations = program
+        .locations()
+        .iter()
+        .zip(keep)
+        .filter(|&(_, &keep)| keep)
+        .map(|(location, _)| location.clone())
+        .collect();
+    Program::new(commands, labels)
+        .with_exports(program.exports().clone())
+        .with_locations(locations)
+}
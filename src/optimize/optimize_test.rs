@@ -0,0 +1,206 @@
+use std::collections::HashSet;
+
+use super::{
+    fold_constants, optimize, remove_dead_stores, remove_jumps_to_next, remove_unreachable, thread_jumps, Pass,
+    Pipeline,
+};
+use crate::moo::parse_program_from_string;
+use crate::program::Program;
+use crate::vm::{MooMachine, Status};
+
+const REGISTERS: u64 = 8;
+
+/// Runs `program` on `inputs` until it halts and returns what it put into O0 and
+/// what its registers held at the end.
+fn run(program: &Program, inputs: &[u64]) -> (Vec<u64>, Vec<u64>) {
+    let mut machine = MooMachine::new(
+        program.clone(),
+        vec![Box::new(inputs.to_vec())],
+        vec![Box::new(Vec::new())],
+    );
+    machine.start_trace();
+    for _ in 0..10_000 {
+        if machine.tick().unwrap() == Status::Halted {
+            break;
+        }
+    }
+    let registers = (0..REGISTERS).map(|register| machine.register(register)).collect();
+    let outputs = machine
+        .take_trace()
+        .unwrap()
+        .entries()
+        .iter()
+        .flat_map(|entry| entry.outputs.iter().map(|&(_, value)| value))
+        .collect();
+    (outputs, registers)
+}
+
+fn listing(program: &Program) -> String {
+    program
+        .commands()
+        .iter()
+        .map(|command| command.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn folded(source: &str) -> String {
+    listing(&fold_constants(&parse_program_from_string(source).unwrap()))
+}
+
+#[test]
+fn fold_test() {
+    assert_eq!(folded("fadd 1f 2f R0;"), "load 3f R0");
+    assert_eq!(folded("load 3i R0; iadd R0 -5i R1; imul R1 R1 O0;"), "load 3i R0; load -2i R1; load 4i O0");
+    assert_eq!(folded("usub 0u 1u R0;"), format!("load {}u R0", u64::MAX));
+    // Constants are converted into the domain of the instruction.
+    assert_eq!(folded("uadd 2.5f -1i R0;"), format!("load {}u R0", 1u64));
+}
+
+#[test]
+fn registers_start_at_zero_test() {
+    assert_eq!(folded("uadd R0 1u R0; uadd R0 1u R0;"), "load 1u R0; load 2u R0");
+}
+
+#[test]
+fn propagate_into_varying_test() {
+    assert_eq!(
+        folded("load 1.5f R0; fmul R0 I0 R1; fadd R1 R0 O0;"),
+        "load 1.5f R0; fmul 1.5f I0 R1; fadd R1 1.5f O0"
+    );
+    // A float register read as an integer is converted the way the machine does.
+    assert_eq!(folded("load 1f R0; uadd R0 I0 O0;"), format!("load 1f R0; uadd {}u I0 O0", 1f64.to_bits()));
+}
+
+#[test]
+fn panics_are_kept_test() {
+    assert_eq!(folded("udiv 1u 0u R0;"), "udiv 1u 0u R0");
+    assert_eq!(folded("load 0i R1; idiv 1i R1 R0;"), "load 0i R1; idiv 1i 0i R0");
+    // Infinity can't be written as a constant.
+    let overflow = "fmul 1e200f 1e200f R0;";
+    let program = parse_program_from_string(overflow).unwrap();
+    assert_eq!(fold_constants(&program), program);
+}
+
+#[test]
+fn joins_test() {
+    // R0 is 1 on both ways into the last instruction, R1 isn't.
+    assert_eq!(
+        folded("load 1u R0; icmp I0 0i; jless 1f; load 2u R1; 1: uadd R0 R1 O0;"),
+        "load 1u R0; icmp I0 0i; jless 1@4; load 2u R1; uadd 1u R1 O0"
+    );
+    // R0 changes around the loop.
+    assert_eq!(
+        folded("load 0u R0; 1: uadd R0 1u R0; ucmp R0 5u; jless 1b;"),
+        "load 0u R0; uadd R0 1u R0; ucmp R0 5u; jless 1@1"
+    );
+}
+
+#[test]
+fn jumps_test() {
+    assert_eq!(
+        folded("ucmp 1u 2u; jless 1f; jgre 1f; 1: load -1i R0; jineg R0 1b; jfneg 1f 1b;"),
+        "ucmp 1u 2u; jump 1@3; jgre 1@3; load -1i R0; jump 1@3; jfneg 1f 1@3"
+    );
+}
+
+const PROGRAMS: [&str; 6] = [
+        "load 3i R0; iadd R0 4i R1; imul R1 I0 R2; load R2 O0; isub R1 R0 O0;",
+        "load 0u R0; load 1u R1; 1: uadd R0 R1 R2; load R1 R0; load R2 R1; load R2 O0; ucmp R2 50u; jless 1b;",
+        "load 2.5f R0; fmul R0 R0 R1; fdiv R1 0.5f R2; fsub R2 I0 R3; fcmp R3 0f; jgre 1f; load R3 O0; 1: load R1 O0;",
+        "load I0 R0; ucmp R0 10u; jless 1f; load 5u R1; jump 2f; 1: load 7u R1; 2: umul R1 R1 O0; jineg R1 2b;",
+        "load 1i R5; 1: iadd R5 R5 R5; load R5 O0; icmp R5 1000i; jless 1b; jineg -1i 2f; load 9u O0; 2: load 3u O0;",
+        "fadd 1f 2i R0; load R0 R1; uadd R1 0u O0; idiv 7i -2i O0; udiv 7u 2u O0;",
+];
+
+const INPUTS: [[u64; 3]; 3] = [[0, 3, 4], [12, 1, 1], [4611686018427387904, 4609434218613702656, 0]];
+
+#[test]
+fn differential_test() {
+    for source in PROGRAMS.iter() {
+        let program = parse_program_from_string(source).unwrap();
+        let folded = fold_constants(&program);
+        assert_eq!(folded.labels(), program.labels());
+        for inputs in INPUTS.iter() {
+            assert_eq!(run(&folded, inputs), run(&program, inputs), "{} on {:?}", source, inputs);
+        }
+    }
+}
+
+#[test]
+fn thread_jumps_test() {
+    let program = parse_program_from_string("jump a; a: jump b; b: jump c; c: load 1u O0; jeq a;").unwrap();
+    assert_eq!(listing(&thread_jumps(&program)), "jump c; jump c; jump c; load 1u O0; jeq c");
+
+    let program = parse_program_from_string("a: jump b; b: jump a; jump lib;").unwrap();
+    assert_eq!(thread_jumps(&program), program);
+}
+
+#[test]
+fn remove_jumps_to_next_test() {
+    let program = parse_program_from_string("jump a; a: load 1u O0; jump b; b: load 2u O0; jump c; c:jump a;").unwrap();
+    let removed = remove_jumps_to_next(&program);
+    assert_eq!(listing(&removed), "load 1u O0; load 2u O0; jump a");
+    assert_eq!(removed.get_address("a"), 0);
+    assert_eq!(removed.get_address("b"), 1);
+    assert_eq!(removed.get_address("c"), 2);
+}
+
+#[test]
+fn remove_unreachable_test() {
+    let program = parse_program_from_string("jump a; load 1u O0; lib: load 3u O0; a: load 2u O0;").unwrap();
+    let removed = remove_unreachable(&program);
+    assert_eq!(listing(&removed), "jump a; load 2u O0");
+    assert_eq!(removed.get_address("a"), 1);
+    assert_eq!(removed.get_address("lib"), 1);
+
+    let exports: HashSet<String> = vec!["lib".to_string()].into_iter().collect();
+    let removed = remove_unreachable(&program.with_exports(exports));
+    assert_eq!(listing(&removed), "jump a; load 3u O0; load 2u O0");
+}
+
+#[test]
+fn remove_dead_stores_test() {
+    let program = parse_program_from_string(
+        "load 1u R0; load 2u R0; load R0 O0; uadd I0 1u R1; fadd R2 1f R3; udiv 1u 0u R4; udiv 1u 2u R4; load 5u R5;",
+    )
+    .unwrap();
+    assert_eq!(
+        listing(&remove_dead_stores(&program)),
+        "load 2u R0; load R0 O0; uadd I0 1u R1; fadd R2 1f R3; udiv 1u 0u R4"
+    );
+    // Another program may read what is written before jumping to it.
+    let program = parse_program_from_string("load 1u R0; jump lib;").unwrap();
+    assert_eq!(remove_dead_stores(&program), program);
+}
+
+#[test]
+fn pipeline_test() {
+    let program = parse_program_from_string(
+        "load 2u R0; umul R0 R0 R1; ucmp R1 4u; jeq a; load 9u O0; a: jump b; b: uadd R1 I0 O0;",
+    )
+    .unwrap();
+    let (optimized, report) = optimize(&program);
+    // Compares aren't stores, so it stays.
+    assert_eq!(listing(&optimized), "ucmp 4u 4u; uadd 4u I0 O0");
+    assert_eq!((report.before, report.after), (7, 2));
+    assert!(report.to_string().starts_with("7 instructions before, 2 after\n"));
+
+    let (only_jumps, report) = Pipeline::new(vec![Pass::ThreadJumps, Pass::RemoveJumpsToNext]).run(&program);
+    assert_eq!(report.after, 6);
+    assert_eq!(only_jumps.get_address("b"), 5);
+    assert_eq!(report.passes[0].pass, Pass::ThreadJumps);
+}
+
+#[test]
+fn optimize_differential_test() {
+    for source in PROGRAMS.iter() {
+        let program = parse_program_from_string(source).unwrap();
+        let (optimized, report) = optimize(&program);
+        assert!(report.after <= report.before);
+        for inputs in INPUTS.iter() {
+            // Registers that aren't read again are gone, so only the outputs are compared.
+            assert_eq!(run(&optimized, inputs).0, run(&program, inputs).0, "{} on {:?}", source, inputs);
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::program::Program;
+use crate::vm::{Channel, Command, MachineObserver, MooMachine, ObserverId, Status, VmError};
+
+#[cfg(test)]
+mod profiler_test;
+
+/// Runs a `MooMachine` and records where its ticks go into a `Profile`, which the
+/// machine has as an observer while it is profiled.
+pub struct Profiler {
+    machine: MooMachine,
+    profile: Arc<Mutex<Profile>>,
+    profile_id: ObserverId,
+}
+
+impl Profiler {
+    pub fn new(mut machine: MooMachine) -> Self {
+        let profile = Arc::new(Mutex::new(Profile::new(machine.program().clone())));
+        let profile_id = machine.add_observer(Box::new(Arc::clone(&profile)));
+        Profiler { machine, profile, profile_id }
+    }
+
+    pub fn tick(&mut self) -> Result<Status, VmError> {
+        self.machine.tick()
+    }
+
+    /// Ticks the machine until it halts or fails, or `max_ticks` ticks have been run.
+    /// Returns the result of the last tick.
+    pub fn run(&mut self, max_ticks: u64) -> Result<Status, VmError> {
+        let mut status = Status::Running;
+        for _ in 0..max_ticks {
+            status = self.tick()?;
+            if status == Status::Halted {
+                break;
+            }
+        }
+        Ok(status)
+    }
+
+    pub fn machine(&self) -> &MooMachine {
+        &self.machine
+    }
+
+    pub fn profile(&self) -> MutexGuard<'_, Profile> {
+        self.profile.lock().unwrap()
+    }
+
+    /// Gives the machine back without the profile as an observer.
+    pub fn into_inner(mut self) -> (MooMachine, Profile) {
+        drop(self.machine.remove_observer(self.profile_id));
+        let profile = match Arc::try_unwrap(self.profile) {
+            Ok(profile) => profile.into_inner().unwrap(),
+            Err(_) => unreachable!("only the profiler holds the profile once the machine doesn't"),
+        };
+        (self.machine, profile)
+    }
+}
+
+/// How often a conditional jump jumped.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BranchCounts {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl BranchCounts {
+    /// The share of executions that jumped, or `None` if the jump never executed.
+    pub fn taken_ratio(&self) -> Option<f64> {
+        let total = self.taken + self.not_taken;
+        if total == 0 {
+            None
+        } else {
+            Some(self.taken as f64 / total as f64)
+        }
+    }
+}
+
+/// The instructions from one label up to the next one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockProfile {
+    /// The label at the start of the block. A block at the start of the program
+    /// without a label is called `<start>`.
+    pub name: String,
+    pub start: u64,
+    /// The address after the last instruction of the block.
+    pub end: u64,
+    /// Instructions executed in the block.
+    pub executed: u64,
+}
+
+/// Counts of what a machine did, per instruction. It is filled in by adding it to a
+/// machine as an observer.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    program: Program,
+    executed: Vec<u64>,
+    branches: Vec<BranchCounts>,
+    blocked: HashMap<Channel, u64>,
+    // Whether the instruction being executed has jumped.
+    jumped: bool,
+}
+
+impl Profile {
+    pub fn new(program: Program) -> Self {
+        let length = program.len();
+        Profile {
+            program,
+            executed: vec![0; length],
+            branches: vec![BranchCounts::default(); length],
+            blocked: HashMap::new(),
+            jumped: false,
+        }
+    }
+
+    fn record_executed(&mut self, address: u64, jumped: bool) {
+        let index = address as usize;
+        self.executed[index] += 1;
+        if self.program[index].is_conditional_jump() {
+            if jumped {
+                self.branches[index].taken += 1;
+            } else {
+                self.branches[index].not_taken += 1;
+            }
+        }
+    }
+
+    /// Adds the counts of `other`, which profiles the same program.
+    pub(crate) fn add_counts(&mut self, other: &Profile) {
+        for (executed, other) in self.executed.iter_mut().zip(&other.executed) {
+            *executed += other;
+        }
+        for (branch, other) in self.branches.iter_mut().zip(&other.branches) {
+            branch.taken += other.taken;
+            branch.not_taken += other.not_taken;
+        }
+        for (&channel, &count) in &other.blocked {
+            *self.blocked.entry(channel).or_insert(0) += count;
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// How many times the instruction at `address` was executed.
+    pub fn executed(&self, address: u64) -> u64 {
+        self.executed[address as usize]
+    }
+
+    /// How many instructions were executed in total.
+    pub fn total(&self) -> u64 {
+        self.executed.iter().sum()
+    }
+
+    /// Executions per opcode, most executed first.
+    pub fn by_opcode(&self) -> Vec<(&'static str, u64)> {
+        let mut counts = BTreeMap::new();
+        for (command, &executed) in self.program.commands().iter().zip(&self.executed) {
+            *counts.entry(command.mnemonic()).or_insert(0) += executed;
+        }
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by_key(|&(_, count)| Reverse(count));
+        counts
+    }
+
+    /// Executions per block, in program order.
+    pub fn blocks(&self) -> Vec<BlockProfile> {
+        let mut starts: BTreeMap<u64, &str> = BTreeMap::new();
+        for (label, &address) in self.program.labels() {
+            let name = starts.entry(address).or_insert(label);
+            if label_preference(label) < label_preference(name) {
+                *name = label;
+            }
+        }
+        if !self.program.is_empty() {
+            starts.entry(0).or_insert("<start>");
+        }
+        let starts: Vec<_> = starts
+            .into_iter()
+            .filter(|&(address, _)| (address as usize) < self.program.len())
+            .collect();
+        starts
+            .iter()
+            .enumerate()
+            .map(|(index, &(start, name))| {
+                let end = starts
+                    .get(index + 1)
+                    .map(|&(next, _)| next)
+                    .unwrap_or(self.program.len() as u64);
+                BlockProfile {
+                    name: name.to_string(),
+                    start,
+                    end,
+                    executed: self.executed[start as usize..end as usize].iter().sum(),
+                }
+            })
+            .collect()
+    }
+
+    /// The outcomes of the conditional jump at `address`, or `None` if there's no
+    /// conditional jump there.
+    pub fn branch(&self, address: u64) -> Option<BranchCounts> {
+        if self.program[address as usize].is_conditional_jump() {
+            Some(self.branches[address as usize])
+        } else {
+            None
+        }
+    }
+
+    /// How many ticks the machine spent blocked on `channel`. These are ticks, not
+    /// time: a machine that is ticked again straight away counts every retry.
+    pub fn blocked_ticks(&self, channel: Channel) -> u64 {
+        self.blocked.get(&channel).copied().unwrap_or(0)
+    }
+
+    /// Writes the profile as plain text tables.
+    pub fn table(&self) -> String {
+        let total = self.total();
+        let percent = |count: u64| {
+            if total == 0 {
+                0.
+            } else {
+                count as f64 * 100. / total as f64
+            }
+        };
+        let mut table = String::new();
+        writeln!(table, "{} instructions executed", total).unwrap();
+
+        writeln!(table, "\n{:>7} {:>10} {:>6}  instruction", "address", "count", "%").unwrap();
+        for (address, command) in self.program.commands().iter().enumerate() {
+            let count = self.executed[address];
+            write!(table, "{:>7} {:>10} {:>5.1}%  {}", address, count, percent(count), command).unwrap();
+            if let Some(branch) = self.branch(address as u64) {
+                write!(table, "  ({} taken, {} not taken)", branch.taken, branch.not_taken).unwrap();
+            }
+            writeln!(table).unwrap();
+        }
+
+        writeln!(table, "\n{:<7} {:>10} {:>6}", "opcode", "count", "%").unwrap();
+        for (mnemonic, count) in self.by_opcode() {
+            writeln!(table, "{:<7} {:>10} {:>5.1}%", mnemonic, count, percent(count)).unwrap();
+        }
+
+        writeln!(table, "\n{:<20} {:>10} {:>6}", "block", "count", "%").unwrap();
+        for block in self.blocks() {
+            writeln!(table, "{:<20} {:>10} {:>5.1}%", block.name, block.executed, percent(block.executed)).unwrap();
+        }
+
+        let mut blocked: Vec<_> = self.blocked.iter().collect();
+        blocked.sort_by_key(|&(&channel, _)| match channel {
+            Channel::Input(channel) => (0, channel),
+            Channel::Output(channel) => (1, channel),
+        });
+        if !blocked.is_empty() {
+            writeln!(table, "\n{:<7} {:>10}", "channel", "blocked").unwrap();
+            for (channel, count) in blocked {
+                let channel = match *channel {
+                    Channel::Input(channel) => format!("I{}", channel),
+                    Channel::Output(channel) => format!("O{}", channel),
+                };
+                writeln!(table, "{:<7} {:>10}", channel, count).unwrap();
+            }
+        }
+        table
+    }
+
+    /// Writes `source`, the source of `unit`, with how many instructions were
+    /// executed on each line in front of the line. Needs the program to know the
+    /// source locations of its instructions.
+    pub fn annotate(&self, unit: &str, source: &str) -> String {
+        let mut executed: HashMap<usize, u64> = HashMap::new();
+        let mut branches: HashMap<usize, BranchCounts> = HashMap::new();
+        for address in 0..self.program.len() as u64 {
+            let location = match self.program.location(address) {
+                Some(location) if location.unit == unit => location,
+                _ => continue,
+            };
+            *executed.entry(location.line).or_insert(0) += self.executed(address);
+            if let Some(branch) = self.branch(address) {
+                let counts = branches.entry(location.line).or_default();
+                counts.taken += branch.taken;
+                counts.not_taken += branch.not_taken;
+            }
+        }
+
+        let mut listing = String::new();
+        for (index, line) in source.lines().enumerate() {
+            match executed.get(&(index + 1)) {
+                Some(count) => write!(listing, "{:>10} | {}", count, line).unwrap(),
+                None => write!(listing, "{:>10} | {}", "", line).unwrap(),
+            }
+            if let Some(branch) = branches.get(&(index + 1)) {
+                write!(listing, "  # {} taken, {} not taken", branch.taken, branch.not_taken).unwrap();
+            }
+            writeln!(listing).unwrap();
+        }
+        listing
+    }
+}
+
+/// A profile can be added to a machine directly instead of running the machine
+/// with a `Profiler`.
+impl MachineObserver for Profile {
+    fn before_instruction(&mut self, _address: u64, _command: &Command) {
+        self.jumped = false;
+    }
+
+    fn jump_taken(&mut self, _from: u64, _to: u64) {
+        self.jumped = true;
+    }
+
+    fn after_instruction(&mut self, address: u64, status: Status) {
+        match status {
+            Status::Running => self.record_executed(address, self.jumped),
+            Status::Blocked { channel } => *self.blocked.entry(channel).or_insert(0) += 1,
+            Status::Halted => {}
+        }
+    }
+}
+
+/// Blocks are named after ordinary labels rather than anonymous ones where
+/// possible, and after shorter labels rather than longer ones.
+fn label_preference(label: &str) -> (bool, usize, &str) {
+    (label.contains('@'), label.len(), label)
+}
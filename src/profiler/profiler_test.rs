@@ -0,0 +1,110 @@
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+
+use super::{BlockProfile, BranchCounts, Profile, Profiler};
+use crate::moo::parse_program_from_string;
+use crate::vm::{Channel, MooMachine, Status};
+
+const COUNTDOWN: &str = "load 3i R0;
+loop: isub R0 1i R0;
+    icmp R0 0i; jgre loop;
+done: load R0 O0;
+";
+
+fn profiler(source: &str) -> Profiler {
+    let program = parse_program_from_string(source).unwrap();
+    Profiler::new(MooMachine::new(program, Vec::new(), vec![Box::new(Vec::new())]))
+}
+
+#[test]
+fn counts_test() {
+    let mut profiler = profiler(COUNTDOWN);
+    assert_eq!(profiler.run(100), Ok(Status::Halted));
+    let profile = profiler.profile();
+    assert_eq!(profile.total(), 11);
+    assert_eq!(profile.executed(0), 1);
+    assert_eq!(profile.executed(1), 3);
+    assert_eq!(profile.by_opcode(), vec![("icmp", 3), ("isub", 3), ("jgre", 3), ("load", 2)]);
+    assert_eq!(profile.branch(3), Some(BranchCounts { taken: 2, not_taken: 1 }));
+    assert_eq!(profile.branch(3).unwrap().taken_ratio(), Some(2. / 3.));
+    assert_eq!(profile.branch(2), None);
+    assert_eq!(
+        profile.blocks(),
+        vec![
+            BlockProfile { name: "<start>".to_string(), start: 0, end: 1, executed: 1 },
+            BlockProfile { name: "loop".to_string(), start: 1, end: 4, executed: 9 },
+            BlockProfile { name: "done".to_string(), start: 4, end: 5, executed: 1 },
+        ],
+    );
+}
+
+#[test]
+fn jump_to_next_instruction_test() {
+    // Both ways of a jump to the next instruction end up at the same address.
+    let mut not_taken = profiler("icmp 0i 1i; jgre 1f; 1: load 1u R0;");
+    not_taken.run(100).unwrap();
+    assert_eq!(not_taken.profile().branch(1), Some(BranchCounts { taken: 0, not_taken: 1 }));
+
+    let mut taken = profiler("icmp 1i 0i; jgre 1f; 1: load 1u R0;");
+    taken.run(100).unwrap();
+    assert_eq!(taken.profile().branch(1), Some(BranchCounts { taken: 1, not_taken: 0 }));
+}
+
+#[test]
+fn blocked_ticks_test() {
+    let program = parse_program_from_string("1: load I0 O0; jump 1b;").unwrap();
+    let (sender, receiver) = channel();
+    let mut profiler = Profiler::new(MooMachine::new(program, vec![Box::new(receiver)], vec![Box::new(Vec::new())]));
+    profiler.run(3).unwrap();
+    sender.send(1).unwrap();
+    profiler.run(4).unwrap();
+    let profile = profiler.profile();
+    assert_eq!(profile.blocked_ticks(Channel::Input(0)), 5);
+    assert_eq!(profile.blocked_ticks(Channel::Output(0)), 0);
+    assert_eq!(profile.total(), 2);
+    // Unconditional jumps aren't branches.
+    assert_eq!(profile.branch(1), None);
+    assert!(profile.table().contains("I0               5"));
+}
+
+#[test]
+fn table_test() {
+    let mut profiler = profiler(COUNTDOWN);
+    profiler.run(100).unwrap();
+    let table = profiler.profile().table();
+    assert!(table.starts_with("11 instructions executed\n"));
+    assert!(table.contains("      3          3  27.3%  jgre loop  (2 taken, 1 not taken)\n"));
+    assert!(table.contains("isub             3  27.3%\n"));
+    assert!(table.contains("loop                          9  81.8%\n"));
+}
+
+#[test]
+fn annotate_test() {
+    let mut profiler = profiler(COUNTDOWN);
+    profiler.run(100).unwrap();
+    let (_, profile) = profiler.into_inner();
+    let listing = profile.annotate("main", COUNTDOWN);
+    assert_eq!(
+        listing,
+        "         1 | load 3i R0;
+         3 | loop: isub R0 1i R0;
+         6 |     icmp R0 0i; jgre loop;  # 2 taken, 1 not taken
+         1 | done: load R0 O0;
+",
+    );
+    assert!(profile.annotate("other.moo", COUNTDOWN).starts_with("           | load 3i R0;\n"));
+}
+
+#[test]
+fn profile_as_observer_test() {
+    let mut profiler = profiler(COUNTDOWN);
+    profiler.run(100).unwrap();
+
+    let program = parse_program_from_string(COUNTDOWN).unwrap();
+    let profile = Arc::new(Mutex::new(Profile::new(program.clone())));
+    let mut machine = MooMachine::new(program, Vec::new(), vec![Box::new(Vec::new())]);
+    machine.add_observer(Box::new(profile.clone()));
+    while machine.tick().unwrap() != Status::Halted {}
+
+    assert_eq!(profile.lock().unwrap().table(), profiler.profile().table());
+}
@@ -1,12 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Index;
 
-use crate::vm::Command;
+use crate::vm::{Command, Param};
 
-#[derive(Debug, PartialEq)]
+/// Where an instruction was written.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    /// The unit the instruction is from, which is the path of the file it is in
+    /// for programs parsed from files.
+    pub unit: String,
+    /// Counted from 1.
+    pub line: usize,
+}
+
+#[derive(Clone, Debug)]
 pub struct Program {
     program: Vec<Command>,
     labels: HashMap<String, u64>,
+    exports: HashSet<String>,
+    locations: Vec<SourceLocation>,
 }
 
 impl Program {
@@ -14,9 +26,25 @@ impl Program {
         Program {
             program,
             labels,
+            exports: HashSet::new(),
+            locations: Vec::new(),
         }
     }
 
+    /// Sets where each instruction was written. `locations` should either be empty
+    /// or have one location for every instruction.
+    pub fn with_locations(mut self, locations: Vec<SourceLocation>) -> Self {
+        self.locations = locations;
+        self
+    }
+
+    /// Marks the given labels as visible to other programs when linking.
+    /// Labels that are not exported stay private to this program.
+    pub fn with_exports(mut self, exports: HashSet<String>) -> Self {
+        self.exports = exports;
+        self
+    }
+
     pub fn get_address(&self, label: &str) -> u64 {
         *self.labels.get(label).unwrap()
     }
@@ -24,6 +52,55 @@ impl Program {
     pub fn len(&self) -> usize {
         self.program.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.program.is_empty()
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        &self.program
+    }
+
+    /// Replaces the instruction at `address`, keeping the labels and source location there.
+    pub fn replace(&mut self, address: u64, command: Command) {
+        self.program[address as usize] = command;
+    }
+
+    pub fn labels(&self) -> &HashMap<String, u64> {
+        &self.labels
+    }
+
+    pub fn exports(&self) -> &HashSet<String> {
+        &self.exports
+    }
+
+    /// Where the instruction at `address` was written, if that is known.
+    pub fn location(&self, address: u64) -> Option<&SourceLocation> {
+        self.locations.get(address as usize)
+    }
+
+    pub fn locations(&self) -> &[SourceLocation] {
+        &self.locations
+    }
+
+    /// The highest numbered register any instruction uses.
+    pub fn highest_register(&self) -> Option<u64> {
+        self.program
+            .iter()
+            .flat_map(Command::params)
+            .filter_map(|param| match param {
+                Param::Register(register) => Some(register),
+                _ => None,
+            })
+            .max()
+    }
+}
+
+/// Source locations are only there for tools so they don't take part in comparisons.
+impl PartialEq for Program {
+    fn eq(&self, other: &Self) -> bool {
+        self.program == other.program && self.labels == other.labels && self.exports == other.exports
+    }
 }
 
 impl Index<usize> for Program {
@@ -32,4 +109,4 @@ impl Index<usize> for Program {
     fn index(&self, i: usize) -> &Command {
         &self.program[i]
     }
-}
\ No newline at end of file
+}
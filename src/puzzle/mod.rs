@@ -0,0 +1,297 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::common::{unbounded_channel, NumberFormat, Source};
+use crate::program::Program;
+use crate::vm::{BoxedSink, BoxedSource, Channel, Command, MooMachine, Param, Status, VmError};
+
+#[cfg(test)]
+mod puzzle_test;
+
+/// How many instructions a solution may execute when the puzzle doesn't say.
+pub const DEFAULT_MAX_CYCLES: u64 = 100_000;
+
+/// How many input and how many output channels a puzzle can have.
+pub const MAX_CHANNELS: usize = 256;
+
+/// A programming puzzle: the values fed to each input channel and the values a
+/// solution has to produce on each output channel.
+///
+/// Puzzles are written one setting per line and `#` starts a comment:
+///
+/// ```text
+/// name: Doubler
+/// max_instructions: 4
+/// max_cycles: 100
+/// allow: load uadd jump
+/// input 0: 1 2 3
+/// output 0: 2 4 6
+/// output 1 signed: -1 -2
+/// ```
+///
+/// Stream values are unsigned unless the channel number is followed by `signed` or
+/// `float`. A stream may be split over several lines, which are joined in order.
+/// Channel numbers go up to `MAX_CHANNELS` - 1.
+/// Every setting is optional. Without `allow` every instruction is allowed and
+/// without `max_cycles` `DEFAULT_MAX_CYCLES` is used.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Puzzle {
+    pub name: String,
+    pub inputs: Vec<Vec<u64>>,
+    pub outputs: Vec<Vec<u64>>,
+    pub max_instructions: Option<usize>,
+    pub max_cycles: Option<u64>,
+    pub allowed: Option<HashSet<String>>,
+}
+
+impl Puzzle {
+    /// Runs `program` against the puzzle.
+    ///
+    /// The program is first checked against the size limit, the allowed
+    /// instructions and the channels of the puzzle. It then runs until it halts or
+    /// tries to take from an input that has run out, which is how programs that
+    /// loop forever finish. Outputs are compared after the run.
+    pub fn check(&self, program: &Program) -> Report {
+        let size = program.len();
+        if let Some(failure) = self.check_program(program) {
+            return Report { failure: Some(failure), cycles: 0, size };
+        }
+
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|values| Box::new(values.clone()) as BoxedSource)
+            .collect();
+        let (sinks, mut sources): (Vec<_>, Vec<_>) = self.outputs.iter().map(|_| unbounded_channel()).unzip();
+        let outputs = sinks.into_iter().map(|sink| Box::new(sink) as BoxedSink).collect();
+        let mut machine = MooMachine::new(program.clone(), inputs, outputs);
+
+        let max_cycles = self.max_cycles.unwrap_or(DEFAULT_MAX_CYCLES);
+        let mut cycles = 0;
+        let mut run_failure = None;
+        loop {
+            match machine.tick() {
+                Ok(Status::Running) => {
+                    if cycles == max_cycles {
+                        run_failure = Some(Failure::CycleLimitExceeded { limit: max_cycles });
+                        break;
+                    }
+                    cycles += 1;
+                }
+                // Inputs and outputs never block as they're all in memory.
+                Ok(Status::Halted) | Ok(Status::Blocked { .. }) => break,
+                Err(VmError::InputClosed(_)) => break,
+                Err(error) => {
+                    run_failure = Some(Failure::Machine(error));
+                    break;
+                }
+            }
+        }
+
+        let produced: Vec<Vec<u64>> = sources
+            .iter_mut()
+            .map(|source| {
+                let mut values = Vec::new();
+                while let Some(value) = source.take() {
+                    values.push(value);
+                }
+                values
+            })
+            .collect();
+        // A wrong value is more useful to know about than running out of cycles, but
+        // a value that's missing because the run was cut short is not.
+        let failure = match (self.first_mismatch(&produced), run_failure) {
+            (Some(mismatch @ Failure::Mismatch { actual: Some(_), .. }), _) => Some(mismatch),
+            (_, Some(failure)) => Some(failure),
+            (mismatch, None) => mismatch,
+        };
+        Report { failure, cycles, size }
+    }
+
+    fn check_program(&self, program: &Program) -> Option<Failure> {
+        if let Some(limit) = self.max_instructions {
+            if program.len() > limit {
+                return Some(Failure::TooManyInstructions { limit });
+            }
+        }
+        for (address, command) in program.commands().iter().enumerate() {
+            let address = address as u64;
+            if let Some(allowed) = &self.allowed {
+                if !allowed.contains(command.mnemonic()) {
+                    return Some(Failure::InstructionNotAllowed {
+                        address,
+                        mnemonic: command.mnemonic(),
+                    });
+                }
+            }
+            for param in command.params() {
+                let channel = match param {
+                    Param::Input(channel) if channel >= self.inputs.len() as u64 => Channel::Input(channel),
+                    Param::Output(channel) if channel >= self.outputs.len() as u64 => Channel::Output(channel),
+                    _ => continue,
+                };
+                return Some(Failure::ChannelNotInPuzzle { address, channel });
+            }
+        }
+        None
+    }
+
+    fn first_mismatch(&self, produced: &[Vec<u64>]) -> Option<Failure> {
+        for (channel, (expected, actual)) in self.outputs.iter().zip(produced).enumerate() {
+            let length = expected.len().max(actual.len());
+            for index in 0..length {
+                let (expected, actual) = (expected.get(index).copied(), actual.get(index).copied());
+                if expected != actual {
+                    return Some(Failure::Mismatch {
+                        channel: channel as u64,
+                        index,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The result of checking a program against a `Puzzle`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    /// Why the program didn't solve the puzzle, if it didn't.
+    pub failure: Option<Failure>,
+    /// Instructions executed before the program finished.
+    pub cycles: u64,
+    /// Instructions in the program.
+    pub size: usize,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Failure {
+    TooManyInstructions { limit: usize },
+    InstructionNotAllowed { address: u64, mnemonic: &'static str },
+    /// The program uses a channel the puzzle doesn't have.
+    ChannelNotInPuzzle { address: u64, channel: Channel },
+    CycleLimitExceeded { limit: u64 },
+    /// Output `index` of `channel` was wrong. `None` means a value was missing or
+    /// shouldn't have been there.
+    Mismatch {
+        channel: u64,
+        index: usize,
+        expected: Option<u64>,
+        actual: Option<u64>,
+    },
+    Machine(VmError),
+}
+
+pub fn parse_puzzle_from_file<P: AsRef<Path>>(path: P) -> Result<Puzzle, PuzzleError> {
+    parse_puzzle_from_string(&fs::read_to_string(path)?)
+}
+
+pub fn parse_puzzle_from_string(source: &str) -> Result<Puzzle, PuzzleError> {
+    let mut puzzle = Puzzle::default();
+    for line in source.lines() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (key, value) = match line.find(':') {
+            Some(colon) => (line[..colon].trim(), line[colon + 1..].trim()),
+            None => return Err(PuzzleError::InvalidLine(line.to_string())),
+        };
+        let key: Vec<_> = key.split_whitespace().collect();
+        match key.as_slice() {
+            ["name"] => puzzle.name = value.to_string(),
+            ["max_instructions"] => puzzle.max_instructions = Some(parse_limit(value)?),
+            ["max_cycles"] => puzzle.max_cycles = Some(parse_limit(value)?),
+            ["allow"] => {
+                let mnemonics = Command::mnemonics();
+                let allowed = puzzle.allowed.get_or_insert_with(HashSet::new);
+                for mnemonic in value.split_whitespace() {
+                    if !mnemonics.contains(&mnemonic) {
+                        return Err(PuzzleError::UnknownInstruction(mnemonic.to_string()));
+                    }
+                    allowed.insert(mnemonic.to_string());
+                }
+            }
+            ["input", channel, format @ ..] => {
+                parse_stream(&mut puzzle.inputs, channel, format, value, line)?
+            }
+            ["output", channel, format @ ..] => {
+                parse_stream(&mut puzzle.outputs, channel, format, value, line)?
+            }
+            _ => return Err(PuzzleError::InvalidLine(line.to_string())),
+        }
+    }
+
+    for (index, stream) in puzzle.inputs.iter().enumerate() {
+        if stream.is_empty() {
+            return Err(PuzzleError::ChannelNotDefined(Channel::Input(index as u64)));
+        }
+    }
+    for (index, stream) in puzzle.outputs.iter().enumerate() {
+        if stream.is_empty() {
+            return Err(PuzzleError::ChannelNotDefined(Channel::Output(index as u64)));
+        }
+    }
+    Ok(puzzle)
+}
+
+fn parse_limit<T: std::str::FromStr>(value: &str) -> Result<T, PuzzleError> {
+    value.parse().map_err(|_| PuzzleError::InvalidNumber(value.to_string()))
+}
+
+/// Appends the values on a stream line to the stream of `channel`. Streams that
+/// come before `channel` and haven't been defined yet are left empty.
+fn parse_stream(
+    streams: &mut Vec<Vec<u64>>,
+    channel: &str,
+    format: &[&str],
+    values: &str,
+    line: &str,
+) -> Result<(), PuzzleError> {
+    let channel: usize = parse_limit(channel)?;
+    if channel >= MAX_CHANNELS {
+        return Err(PuzzleError::InvalidLine(line.to_string()));
+    }
+    let format = match format {
+        [] | ["unsigned"] => NumberFormat::Unsigned,
+        ["signed"] => NumberFormat::Signed,
+        ["float"] => NumberFormat::Float,
+        _ => return Err(PuzzleError::InvalidLine(line.to_string())),
+    };
+    if streams.len() <= channel {
+        streams.resize(channel + 1, Vec::new());
+    }
+    for value in values.split_whitespace() {
+        let value = format
+            .parse(value)
+            .ok_or_else(|| PuzzleError::InvalidNumber(value.to_string()))?;
+        streams[channel].push(value);
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum PuzzleError {
+    IOError(io::Error),
+    InvalidLine(String),
+    InvalidNumber(String),
+    UnknownInstruction(String),
+    /// A channel before the last one of its kind has no values.
+    ChannelNotDefined(Channel),
+}
+
+impl From<io::Error> for PuzzleError {
+    fn from(err: io::Error) -> Self {
+        PuzzleError::IOError(err)
+    }
+}
@@ -0,0 +1,158 @@
+use super::{parse_puzzle_from_string, Failure, Puzzle, PuzzleError, Report};
+use crate::moo::parse_program_from_string;
+use crate::program::Program;
+use crate::vm::{Channel, Command, Param, VmError};
+use std::collections::HashMap;
+
+const DOUBLER: &str = "
+# Doubles every number.
+name: Doubler
+max_instructions: 3
+max_cycles: 20
+allow: uadd load jump
+input 0: 1 2
+input 0: 3
+output 0: 2 4 6
+";
+
+fn check(puzzle: &str, program: &str) -> Report {
+    let puzzle = parse_puzzle_from_string(puzzle).unwrap();
+    puzzle.check(&parse_program_from_string(program).unwrap())
+}
+
+#[test]
+fn parse_puzzle_test() {
+    let puzzle = parse_puzzle_from_string(DOUBLER).unwrap();
+    assert_eq!(puzzle.name, "Doubler");
+    assert_eq!(puzzle.inputs, vec![vec![1, 2, 3]]);
+    assert_eq!(puzzle.outputs, vec![vec![2, 4, 6]]);
+    assert_eq!(puzzle.max_instructions, Some(3));
+    assert_eq!(puzzle.max_cycles, Some(20));
+    assert!(puzzle.allowed.unwrap().contains("uadd"));
+}
+
+#[test]
+fn parse_stream_formats_test() {
+    let puzzle = parse_puzzle_from_string("output 1 float: 0.5\noutput 0 signed: -1").unwrap();
+    assert_eq!(puzzle.outputs, vec![vec![-1i64 as u64], vec![0.5f64.to_bits()]]);
+    assert_eq!(puzzle.inputs, Vec::<Vec<u64>>::new());
+    assert_eq!(puzzle.allowed, None);
+}
+
+#[test]
+fn parse_puzzle_errors_test() {
+    match parse_puzzle_from_string("input 0 hex: 1") {
+        Err(PuzzleError::InvalidLine(line)) => assert_eq!(line, "input 0 hex: 1"),
+        other => panic!("expected an invalid line but got {:?}", other),
+    }
+    match parse_puzzle_from_string("input 0: 1 two") {
+        Err(PuzzleError::InvalidNumber(number)) => assert_eq!(number, "two"),
+        other => panic!("expected an invalid number but got {:?}", other),
+    }
+    match parse_puzzle_from_string("allow: load push") {
+        Err(PuzzleError::UnknownInstruction(mnemonic)) => assert_eq!(mnemonic, "push"),
+        other => panic!("expected an unknown instruction but got {:?}", other),
+    }
+    match parse_puzzle_from_string("output 1: 1") {
+        Err(PuzzleError::ChannelNotDefined(channel)) => assert_eq!(channel, Channel::Output(0)),
+        other => panic!("expected an undefined channel but got {:?}", other),
+    }
+    for line in &["input 18446744073709551615: 1", "input 4000000000: 1", "output 256: 1"] {
+        match parse_puzzle_from_string(line) {
+            Err(PuzzleError::InvalidLine(invalid)) => assert_eq!(&invalid, line),
+            other => panic!("expected an invalid line but got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn passing_solution_test() {
+    let report = check(DOUBLER, "1: load I0 R0; uadd R0 R0 O0; jump 1b;");
+    assert_eq!(report, Report { failure: None, cycles: 9, size: 3 });
+    assert!(report.passed());
+}
+
+#[test]
+fn wrong_output_test() {
+    // 1 + 1 happens to be right, 2 + 1 isn't.
+    let report = check(DOUBLER, "1: uadd I0 1u O0; jump 1b;");
+    assert!(!report.passed());
+    assert_eq!(
+        report.failure,
+        Some(Failure::Mismatch { channel: 0, index: 1, expected: Some(4), actual: Some(3) }),
+    );
+}
+
+#[test]
+fn missing_and_extra_output_test() {
+    let report = check(DOUBLER, "load I0 R0; uadd R0 R0 O0;");
+    assert_eq!(
+        report.failure,
+        Some(Failure::Mismatch { channel: 0, index: 1, expected: Some(4), actual: None }),
+    );
+    assert_eq!(report.cycles, 2);
+
+    let report = check("input 0: 1 2 3\noutput 0: 2 4 6", "1: load I0 R0; uadd R0 R0 O0; load 0u O0; jump 1b;");
+    assert_eq!(
+        report.failure,
+        Some(Failure::Mismatch { channel: 0, index: 1, expected: Some(4), actual: Some(0) }),
+    );
+}
+
+#[test]
+fn cycle_limit_test() {
+    let report = check("max_cycles: 10\noutput 0: 1", "1: jump 1b; load 1u O0;");
+    assert_eq!(report.failure, Some(Failure::CycleLimitExceeded { limit: 10 }));
+    assert_eq!(report.cycles, 10);
+
+    // Wrong outputs are reported even if the program was also too slow.
+    let report = check("max_cycles: 10\noutput 0: 1", "load 2u O0; 1: jump 1b;");
+    assert_eq!(
+        report.failure,
+        Some(Failure::Mismatch { channel: 0, index: 0, expected: Some(1), actual: Some(2) }),
+    );
+}
+
+#[test]
+fn program_restrictions_test() {
+    let report = check(DOUBLER, "1: uadd I0 0u R0; uadd R0 R0 O0; jump 1b;");
+    assert_eq!(report.failure, None);
+    let report = check(DOUBLER, "1: load I0 R0; uadd R0 R0 O0; uadd 0u 0u R1; jump 1b;");
+    assert_eq!(report, Report { failure: Some(Failure::TooManyInstructions { limit: 3 }), cycles: 0, size: 4 });
+    let report = check(DOUBLER, "1: umul I0 2u O0; jump 1b;");
+    assert_eq!(
+        report.failure,
+        Some(Failure::InstructionNotAllowed { address: 0, mnemonic: "umul" }),
+    );
+    let report = check(DOUBLER, "1: uadd I0 I1 O0; jump 1b;");
+    assert_eq!(
+        report.failure,
+        Some(Failure::ChannelNotInPuzzle { address: 0, channel: Channel::Input(1) }),
+    );
+}
+
+#[test]
+fn broken_solutions_are_rejected_test() {
+    let puzzle = "input 0: 1\noutput 0: 1";
+    assert_eq!(check(puzzle, "fcmp nanf 1f;").failure, Some(Failure::Machine(VmError::LoadedNan)));
+    assert_eq!(
+        check(puzzle, "jump nowhere;").failure,
+        Some(Failure::Machine(VmError::UnknownLabel("nowhere".to_string()))),
+    );
+    assert!(parse_program_from_string("icmp O0 1i;").is_err());
+
+    let puzzle = parse_puzzle_from_string(puzzle).unwrap();
+    let program = Program::new(vec![Command::ICmp(Param::Output(0), Param::IConstant(1))], HashMap::new());
+    assert_eq!(puzzle.check(&program).failure, Some(Failure::Machine(VmError::ReadOutput(0))));
+}
+
+#[test]
+fn program_without_inputs_test() {
+    let puzzle = Puzzle {
+        outputs: vec![vec![1, 2]],
+        ..Puzzle::default()
+    };
+    let report = puzzle.check(&parse_program_from_string("load 1u O0; load 2u O0;").unwrap());
+    assert!(report.passed());
+    assert_eq!(report.cycles, 2);
+}
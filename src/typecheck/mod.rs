@@ -0,0 +1,292 @@
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::cfg::Cfg;
+use crate::program::Program;
+use crate::vm::{Command, Param};
+
+#[cfg(test)]
+mod typecheck_test;
+
+/// What the bits in a register mean.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Type {
+    Float,
+    Signed,
+    Unsigned,
+    /// Taken from an input, so it could be anything.
+    Untyped,
+    /// Never written, so it reads as 0.
+    Undefined,
+}
+
+const TYPES: [Type; 5] = [Type::Float, Type::Signed, Type::Unsigned, Type::Untyped, Type::Undefined];
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Type::Float => "float",
+            Type::Signed => "signed integer",
+            Type::Unsigned => "unsigned integer",
+            Type::Untyped => "input value",
+            Type::Undefined => "undefined value",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The types a register can have at a point in the program, one for each way of
+/// getting there.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Types(u8);
+
+impl Types {
+    pub const UNDEFINED: Types = Types(1 << Type::Undefined as u8);
+
+    pub fn of(ty: Type) -> Self {
+        Types(1 << ty as u8)
+    }
+
+    pub fn contains(self, ty: Type) -> bool {
+        self.0 & Types::of(ty).0 != 0
+    }
+
+    pub fn union(self, other: Types) -> Self {
+        Types(self.0 | other.0)
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Type> {
+        TYPES.iter().copied().filter(move |&ty| self.contains(ty))
+    }
+}
+
+/// Something suspicious about how a program uses its registers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeWarning {
+    /// The instruction at `address` reads `register` as `expected`, but on some
+    /// way of getting there it holds a `found` instead. The bits aren't converted.
+    Mismatch {
+        address: u64,
+        register: u64,
+        expected: Type,
+        found: Type,
+    },
+    /// The instruction at `address` reads `register`, which isn't written on some
+    /// way of getting there.
+    UseBeforeDefinition { address: u64, register: u64 },
+}
+
+impl TypeWarning {
+    pub fn address(&self) -> u64 {
+        match *self {
+            TypeWarning::Mismatch { address, .. } | TypeWarning::UseBeforeDefinition { address, .. } => address,
+        }
+    }
+
+    /// What is wrong, without the address.
+    pub fn message(&self) -> String {
+        match self {
+            TypeWarning::Mismatch {
+                register,
+                expected,
+                found,
+                ..
+            } => format!("R{} is read as a {} but may hold a {}", register, expected, found),
+            TypeWarning::UseBeforeDefinition { register, .. } => {
+                format!("R{} may be read before it is written", register)
+            }
+        }
+    }
+}
+
+impl fmt::Display for TypeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.address(), self.message())
+    }
+}
+
+/// The types of the registers of a program at every instruction, inferred by
+/// following how values flow through it.
+///
+/// Arithmetic and compares read their operands in their own domain and
+/// arithmetic stores results in it. Loading a constant gives the register the
+/// type of the constant, loading an input leaves it untyped and loading another
+/// register copies its type. Only instructions that can be reached from address 0
+/// are checked.
+#[derive(Clone, Debug)]
+pub struct TypeCheck {
+    // The types of the written registers before each instruction, `None` if the
+    // instruction can't be reached. Registers that aren't in the map are undefined.
+    before: Vec<Option<Registers>>,
+    warnings: Vec<TypeWarning>,
+}
+
+type Registers = BTreeMap<u64, Types>;
+
+impl TypeCheck {
+    pub fn new(program: &Program) -> Self {
+        let cfg = Cfg::new(program);
+        let order = cfg.reverse_postorder();
+        let mut entries: Vec<Option<Registers>> = vec![None; cfg.blocks().len()];
+        // Only what happens from the start of the program is followed.
+        if !entries.is_empty() {
+            entries[0] = Some(Registers::new());
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &id in &order {
+                let mut registers = match &entries[id] {
+                    Some(registers) => registers.clone(),
+                    None => continue,
+                };
+                let block = &cfg.blocks()[id];
+                for address in block.start..block.end {
+                    step(address, &program[address as usize], &mut registers, &mut Vec::new());
+                }
+                for &successor in &block.successors {
+                    changed |= match &mut entries[successor] {
+                        Some(entry) => join(entry, &registers),
+                        entry @ None => {
+                            *entry = Some(registers.clone());
+                            true
+                        }
+                    };
+                }
+            }
+        }
+
+        let mut before = vec![None; program.len()];
+        let mut warnings = Vec::new();
+        for (id, block) in cfg.blocks().iter().enumerate() {
+            let mut registers = match entries[id].take() {
+                Some(registers) => registers,
+                None => continue,
+            };
+            for address in block.start..block.end {
+                before[address as usize] = Some(registers.clone());
+                step(address, &program[address as usize], &mut registers, &mut warnings);
+            }
+        }
+        TypeCheck { before, warnings }
+    }
+
+    /// The warnings in order of address.
+    pub fn warnings(&self) -> &[TypeWarning] {
+        &self.warnings
+    }
+
+    /// The types `register` can have right before the instruction at `address` is
+    /// executed, or `None` if the instruction can't be reached.
+    pub fn types_at(&self, address: u64, register: u64) -> Option<Types> {
+        self.before[address as usize]
+            .as_ref()
+            .map(|registers| registers.get(&register).copied().unwrap_or(Types::UNDEFINED))
+    }
+}
+
+/// Adds the types in `other` to `registers`. Returns whether anything was added.
+fn join(registers: &mut Registers, other: &Registers) -> bool {
+    let mut changed = false;
+    for (register, types) in registers.iter_mut() {
+        let joined = types.union(other.get(register).copied().unwrap_or(Types::UNDEFINED));
+        changed |= joined != *types;
+        *types = joined;
+    }
+    for (&register, &types) in other {
+        if let Entry::Vacant(entry) = registers.entry(register) {
+            entry.insert(types.union(Types::UNDEFINED));
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Updates `registers` for the execution of `command`, warning about how it reads them.
+fn step(address: u64, command: &Command, registers: &mut Registers, warnings: &mut Vec<TypeWarning>) {
+    use self::Command::*;
+
+    let mut read = |param: Param, expected: Option<Type>| -> Types {
+        let register = match param {
+            Param::Register(register) => register,
+            Param::Input(_) => return Types::of(Type::Untyped),
+            Param::FConstant(_) => return Types::of(Type::Float),
+            Param::IConstant(_) => return Types::of(Type::Signed),
+            Param::UConstant(_) => return Types::of(Type::Unsigned),
+            Param::Output(_) => return Types::default(),
+        };
+        let types = registers.get(&register).copied().unwrap_or(Types::UNDEFINED);
+        for found in types.iter() {
+            let warning = match found {
+                Type::Undefined => TypeWarning::UseBeforeDefinition { address, register },
+                Type::Untyped => continue,
+                found => match expected {
+                    Some(expected) if expected != found => TypeWarning::Mismatch {
+                        address,
+                        register,
+                        expected,
+                        found,
+                    },
+                    _ => continue,
+                },
+            };
+            if !warnings.contains(&warning) {
+                warnings.push(warning);
+            }
+        }
+        types
+    };
+
+    let (written, into) = match *command {
+        IAdd(a, b, into) | ISub(a, b, into) | IMul(a, b, into) | IDiv(a, b, into) => {
+            read(a, Some(Type::Signed));
+            read(b, Some(Type::Signed));
+            (Types::of(Type::Signed), into)
+        }
+        UAdd(a, b, into) | USub(a, b, into) | UMul(a, b, into) | UDiv(a, b, into) => {
+            read(a, Some(Type::Unsigned));
+            read(b, Some(Type::Unsigned));
+            (Types::of(Type::Unsigned), into)
+        }
+        FAdd(a, b, into) | FSub(a, b, into) | FMul(a, b, into) | FDiv(a, b, into) => {
+            read(a, Some(Type::Float));
+            read(b, Some(Type::Float));
+            (Types::of(Type::Float), into)
+        }
+        ICmp(a, b) | UCmp(a, b) | FCmp(a, b) => {
+            let expected = match command {
+                ICmp(..) => Type::Signed,
+                UCmp(..) => Type::Unsigned,
+                _ => Type::Float,
+            };
+            read(a, Some(expected));
+            read(b, Some(expected));
+            return;
+        }
+        JINeg(number, _) => {
+            read(number, Some(Type::Signed));
+            return;
+        }
+        JFNeg(number, _) => {
+            read(number, Some(Type::Float));
+            return;
+        }
+        Load(what, into) => {
+            let types = read(what, None);
+            // A copy of a register that may be undefined holds 0 on that path, which
+            // is fine as any type.
+            let types = if types.contains(Type::Undefined) {
+                Types(types.0 & !Types::UNDEFINED.0).union(Types::of(Type::Untyped))
+            } else {
+                types
+            };
+            (types, into)
+        }
+        Jump(_) | JGre(_) | JLess(_) | JEq(_) | JNeq(_) => return,
+    };
+    if let Param::Register(register) = into {
+        registers.insert(register, written);
+    }
+}
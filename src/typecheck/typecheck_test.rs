@@ -0,0 +1,109 @@
+use super::{Type, TypeCheck, TypeWarning, Types};
+use crate::moo::parse_program_from_string;
+
+fn check(source: &str) -> TypeCheck {
+    TypeCheck::new(&parse_program_from_string(source).unwrap())
+}
+
+#[test]
+fn mismatch_test() {
+    let check = check("load 3i R0; fadd R0 1f R1;");
+    assert_eq!(
+        check.warnings(),
+        &[TypeWarning::Mismatch {
+            address: 1,
+            register: 0,
+            expected: Type::Float,
+            found: Type::Signed,
+        }]
+    );
+    assert_eq!(check.types_at(1, 0), Some(Types::of(Type::Signed)));
+    assert_eq!(
+        check.warnings()[0].to_string(),
+        "1: R0 is read as a float but may hold a signed integer"
+    );
+}
+
+#[test]
+fn consistent_program_test() {
+    let check = check("load 0u R0; 1: uadd R0 1u R0; ucmp R0 10u; jless 1b; load R0 O0; fadd I0 0.5f R1; fcmp R1 0f;");
+    assert!(check.warnings().is_empty());
+    assert_eq!(check.types_at(5, 1), Some(Types::UNDEFINED));
+    assert_eq!(check.types_at(6, 1), Some(Types::of(Type::Float)));
+}
+
+#[test]
+fn constants_are_converted_test() {
+    // Constants are converted into the domain of the instruction.
+    let check = check("fadd 3i 2u R0; icmp 1.5f R0;");
+    assert_eq!(
+        check.warnings(),
+        &[TypeWarning::Mismatch {
+            address: 1,
+            register: 0,
+            expected: Type::Signed,
+            found: Type::Float,
+        }]
+    );
+}
+
+#[test]
+fn use_before_definition_test() {
+    let check = check("uadd R0 1u R1; load R1 O0;");
+    assert_eq!(
+        check.warnings(),
+        &[TypeWarning::UseBeforeDefinition { address: 0, register: 0 }]
+    );
+    assert_eq!(check.warnings()[0].to_string(), "0: R0 may be read before it is written");
+}
+
+#[test]
+fn defined_on_one_path_test() {
+    let check = check("icmp I0 0i; jless 1f; load 1.5f R0; 1: fmul R0 2f O0;");
+    assert_eq!(
+        check.warnings(),
+        &[TypeWarning::UseBeforeDefinition { address: 3, register: 0 }]
+    );
+    let types = check.types_at(3, 0).unwrap();
+    assert!(types.contains(Type::Float));
+    assert!(types.contains(Type::Undefined));
+    assert!(!types.contains(Type::Signed));
+}
+
+#[test]
+fn types_meet_at_loop_test() {
+    // The second time around the loop R0 holds a float.
+    let check = check("load 0u R0; 1: ucmp R0 I0; fadd 1f 1f R0; jneq 1b;");
+    assert_eq!(
+        check.warnings(),
+        &[TypeWarning::Mismatch {
+            address: 1,
+            register: 0,
+            expected: Type::Unsigned,
+            found: Type::Float,
+        }]
+    );
+    assert_eq!(
+        check.types_at(1, 0).unwrap().iter().collect::<Vec<_>>(),
+        vec![Type::Float, Type::Unsigned]
+    );
+}
+
+#[test]
+fn load_copies_type_test() {
+    let copied = check("load -1i R0; load R0 R1; jineg R1 1f; load I0 R2; jfneg R2 1f; 1: load R1 O0;");
+    assert!(copied.warnings().is_empty());
+    assert_eq!(copied.types_at(2, 1), Some(Types::of(Type::Signed)));
+    assert_eq!(copied.types_at(4, 2), Some(Types::of(Type::Untyped)));
+
+    let misused = check("load -1i R0; load R0 R1; fsub R1 1f R1;");
+    assert_eq!(misused.warnings().len(), 1);
+    assert_eq!(misused.warnings()[0].address(), 2);
+}
+
+#[test]
+fn unreachable_not_checked_test() {
+    let check = check("jump 1f; fadd R0 R1 R2; 1: load 1u R0;");
+    assert!(check.warnings().is_empty());
+    assert_eq!(check.types_at(1, 0), None);
+}
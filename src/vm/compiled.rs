@@ -0,0 +1,468 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::task::Poll;
+
+use crate::common::SinkError;
+use crate::program::Program;
+
+use super::{
+    transmute_from_signed, transmute_to_float, transmute_to_signed, BoxedSink, BoxedSource, Channel, Command,
+    Interrupt, Param, Status, VmError,
+};
+
+/// An instruction lowered into a closure that executes it.
+type Handler = Box<dyn Fn(&mut Core) -> Result<(), Interrupt> + Send + Sync>;
+
+/// A `Program` lowered once into handlers for a `CompiledMachine`. Labels are
+/// resolved to addresses, constants are converted into the domain they are used
+/// in and registers are numbered densely.
+pub struct CompiledProgram {
+    handlers: Vec<Handler>,
+    // The dense index of every register the program uses.
+    registers: HashMap<u64, usize>,
+}
+
+impl CompiledProgram {
+    pub fn new(program: &Program) -> Result<Self, CompileError> {
+        let mut compiler = Compiler {
+            program,
+            registers: HashMap::new(),
+        };
+        let handlers = program
+            .commands()
+            .iter()
+            .map(|command| compiler.compile(command))
+            .collect::<Result<_, _>>()?;
+        Ok(CompiledProgram {
+            handlers,
+            registers: compiler.registers,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.handlers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompileError {
+    /// A jump goes to a label the program doesn't define.
+    UnknownLabel(String),
+    /// An instruction reads from an output.
+    InvalidOperand(Param),
+    /// An instruction stores into an input or a constant.
+    InvalidDestination(Param),
+    /// An fcmp compares a NaN constant, which has no order.
+    NanCompare,
+}
+
+/// Runs a `CompiledProgram` the way a `MooMachine` runs the program it was compiled
+/// from, but without looking up registers and labels or decoding instructions on
+/// every tick. It can't be traced or observed.
+pub struct CompiledMachine {
+    program: CompiledProgram,
+    core: Core,
+}
+
+/// Everything a handler can change.
+struct Core {
+    registers: Vec<u64>,
+    program_counter: usize,
+    compare: Option<Ordering>,
+    input: Vec<BoxedSource>,
+    output: Vec<BoxedSink>,
+    // Inputs taken by an instruction that could not finish, as in `MooMachine`.
+    taken_inputs: Vec<u64>,
+    reused_inputs: usize,
+}
+
+impl CompiledMachine {
+    pub fn new(program: CompiledProgram, input: Vec<BoxedSource>, output: Vec<BoxedSink>) -> Self {
+        let core = Core {
+            registers: vec![0; program.registers.len()],
+            program_counter: 0,
+            compare: None,
+            input,
+            output,
+            taken_inputs: Vec::new(),
+            reused_inputs: 0,
+        };
+        CompiledMachine { program, core }
+    }
+
+    /// Executes the instruction at the program counter, like `MooMachine::tick`.
+    pub fn tick(&mut self) -> Result<Status, VmError> {
+        let pc = self.core.program_counter;
+        let handler = match self.program.handlers.get(pc) {
+            Some(handler) => handler,
+            None => return Ok(Status::Halted),
+        };
+        self.core.program_counter += 1;
+        self.core.reused_inputs = 0;
+        match handler(&mut self.core) {
+            Ok(()) => {
+                self.core.taken_inputs.clear();
+                Ok(Status::Running)
+            }
+            Err(Interrupt::Blocked(channel)) => {
+                self.core.program_counter = pc;
+                Ok(Status::Blocked { channel })
+            }
+            Err(Interrupt::Error(err)) => {
+                self.core.program_counter = pc;
+                self.core.taken_inputs.clear();
+                Err(err)
+            }
+        }
+    }
+
+    /// Ticks the machine until it halts, blocks or fails, or `max_ticks` ticks have
+    /// been run. Returns the result of the last tick.
+    pub fn run(&mut self, max_ticks: u64) -> Result<Status, VmError> {
+        let mut status = Status::Running;
+        for _ in 0..max_ticks {
+            status = self.tick()?;
+            if status != Status::Running {
+                break;
+            }
+        }
+        Ok(status)
+    }
+
+    /// The address of the next instruction to execute.
+    pub fn program_counter(&self) -> u64 {
+        self.core.program_counter as u64
+    }
+
+    /// The bits in `register`. Registers that were never written hold 0.
+    pub fn register(&self, register: u64) -> u64 {
+        self.program
+            .registers
+            .get(&register)
+            .map_or(0, |&index| self.core.registers[index])
+    }
+
+    /// The result of the latest compare, if there was one.
+    pub fn compare(&self) -> Option<Ordering> {
+        self.core.compare
+    }
+
+    /// Closes every output of the machine.
+    pub fn close_outputs(&mut self) {
+        for output in &mut self.core.output {
+            output.close();
+        }
+    }
+}
+
+impl Core {
+    fn take_input(&mut self, channel: usize) -> Result<u64, Interrupt> {
+        if let Some(&taken) = self.taken_inputs.get(self.reused_inputs) {
+            self.reused_inputs += 1;
+            return Ok(taken);
+        }
+        let input = self.input.get_mut(channel).ok_or(VmError::NoInput(channel as u64))?;
+        match input.poll() {
+            Poll::Ready(Some(taken)) => {
+                self.taken_inputs.push(taken);
+                self.reused_inputs += 1;
+                Ok(taken)
+            }
+            Poll::Ready(None) => Err(Interrupt::Error(VmError::InputClosed(channel as u64))),
+            Poll::Pending => Err(Interrupt::Blocked(Channel::Input(channel as u64))),
+        }
+    }
+
+    fn put_output(&mut self, channel: usize, value: u64) -> Result<(), Interrupt> {
+        let output = self.output.get_mut(channel).ok_or(VmError::NoOutput(channel as u64))?;
+        match output.put(value) {
+            Ok(()) => Ok(()),
+            Err(SinkError::Full) => Err(Interrupt::Blocked(Channel::Output(channel as u64))),
+            Err(SinkError::Closed) => Err(Interrupt::Error(VmError::OutputClosed(channel as u64))),
+        }
+    }
+}
+
+/// A number in one of the domains instructions compute in.
+trait Number: Copy + Send + Sync + 'static {
+    /// Reads the bits of a register or input the way `MooMachine` does.
+    fn from_bits(bits: u64) -> Result<Self, VmError>;
+
+    fn to_bits(self) -> u64;
+
+    /// Converts a constant operand into the domain the way `MooMachine` does.
+    fn from_constant(param: Param) -> Option<Self>;
+}
+
+impl Number for f64 {
+    fn from_bits(bits: u64) -> Result<Self, VmError> {
+        transmute_to_float(bits)
+    }
+
+    fn to_bits(self) -> u64 {
+        f64::to_bits(self)
+    }
+
+    fn from_constant(param: Param) -> Option<Self> {
+        match param {
+            Param::FConstant(float) => Some(float),
+            Param::IConstant(integer) => Some(integer as f64),
+            Param::UConstant(integer) => Some(integer as f64),
+            _ => None,
+        }
+    }
+}
+
+impl Number for i64 {
+    fn from_bits(bits: u64) -> Result<Self, VmError> {
+        Ok(transmute_to_signed(bits))
+    }
+
+    fn to_bits(self) -> u64 {
+        transmute_from_signed(self)
+    }
+
+    fn from_constant(param: Param) -> Option<Self> {
+        match param {
+            Param::FConstant(float) => Some(float as i64),
+            Param::IConstant(integer) => Some(integer),
+            Param::UConstant(integer) => Some(integer as i64),
+            _ => None,
+        }
+    }
+}
+
+impl Number for u64 {
+    fn from_bits(bits: u64) -> Result<Self, VmError> {
+        Ok(bits)
+    }
+
+    fn to_bits(self) -> u64 {
+        self
+    }
+
+    fn from_constant(param: Param) -> Option<Self> {
+        match param {
+            Param::FConstant(float) => Some(float as u64),
+            Param::IConstant(integer) => Some(integer as u64),
+            Param::UConstant(integer) => Some(integer),
+            _ => None,
+        }
+    }
+}
+
+/// A decoded parameter that is read from.
+#[derive(Clone, Copy)]
+enum Operand<T> {
+    Register(usize),
+    Input(usize),
+    Constant(T),
+}
+
+impl<T: Number> Operand<T> {
+    #[inline]
+    fn read(self, core: &mut Core) -> Result<T, Interrupt> {
+        Ok(match self {
+            Operand::Register(register) => T::from_bits(core.registers[register])?,
+            Operand::Input(channel) => T::from_bits(core.take_input(channel)?)?,
+            Operand::Constant(value) => value,
+        })
+    }
+}
+
+/// A decoded parameter that is written to.
+#[derive(Clone, Copy)]
+enum Destination {
+    Register(usize),
+    Output(usize),
+}
+
+impl Destination {
+    #[inline]
+    fn write(self, core: &mut Core, bits: u64) -> Result<(), Interrupt> {
+        match self {
+            Destination::Register(register) => {
+                core.registers[register] = bits;
+                Ok(())
+            }
+            Destination::Output(channel) => core.put_output(channel, bits),
+        }
+    }
+}
+
+struct Compiler<'a> {
+    program: &'a Program,
+    registers: HashMap<u64, usize>,
+}
+
+impl Compiler<'_> {
+    fn register(&mut self, register: u64) -> usize {
+        let next = self.registers.len();
+        *self.registers.entry(register).or_insert(next)
+    }
+
+    fn operand<T: Number>(&mut self, param: Param) -> Result<Operand<T>, CompileError> {
+        if let Some(constant) = T::from_constant(param) {
+            return Ok(Operand::Constant(constant));
+        }
+        match param {
+            Param::Register(register) => Ok(Operand::Register(self.register(register))),
+            Param::Input(channel) => Ok(Operand::Input(channel as usize)),
+            _ => Err(CompileError::InvalidOperand(param)),
+        }
+    }
+
+    fn destination(&mut self, param: Param) -> Result<Destination, CompileError> {
+        match param {
+            Param::Register(register) => Ok(Destination::Register(self.register(register))),
+            Param::Output(channel) => Ok(Destination::Output(channel as usize)),
+            _ => Err(CompileError::InvalidDestination(param)),
+        }
+    }
+
+    fn target(&self, label: &str) -> Result<usize, CompileError> {
+        self.program
+            .labels()
+            .get(label)
+            .map(|&address| address as usize)
+            .ok_or_else(|| CompileError::UnknownLabel(label.to_string()))
+    }
+
+    fn compile(&mut self, command: &Command) -> Result<Handler, CompileError> {
+        use self::Command::*;
+
+        Ok(match *command {
+            FAdd(a, b, into) => self.arithmetic(a, b, into, |a: f64, b| Ok(a + b))?,
+            FSub(a, b, into) => self.arithmetic(a, b, into, |a: f64, b| Ok(a - b))?,
+            FMul(a, b, into) => self.arithmetic(a, b, into, |a: f64, b| Ok(a * b))?,
+            FDiv(a, b, into) => self.arithmetic(a, b, into, |a: f64, b| {
+                if b == 0. {
+                    return Err(VmError::DivisionByZero);
+                }
+                Ok(a / b)
+            })?,
+            UAdd(a, b, into) => self.arithmetic(a, b, into, |a: u64, b| Ok(a.wrapping_add(b)))?,
+            USub(a, b, into) => self.arithmetic(a, b, into, |a: u64, b| Ok(a.wrapping_sub(b)))?,
+            UMul(a, b, into) => self.arithmetic(a, b, into, |a: u64, b| Ok(a.wrapping_mul(b)))?,
+            UDiv(a, b, into) => self.arithmetic(a, b, into, |a: u64, b| {
+                if b == 0 {
+                    return Err(VmError::DivisionByZero);
+                }
+                Ok(a.wrapping_div(b))
+            })?,
+            IAdd(a, b, into) => self.arithmetic(a, b, into, |a: i64, b| Ok(a.wrapping_add(b)))?,
+            ISub(a, b, into) => self.arithmetic(a, b, into, |a: i64, b| Ok(a.wrapping_sub(b)))?,
+            IMul(a, b, into) => self.arithmetic(a, b, into, |a: i64, b| Ok(a.wrapping_mul(b)))?,
+            IDiv(a, b, into) => self.arithmetic(a, b, into, |a: i64, b| {
+                if b == 0 {
+                    return Err(VmError::DivisionByZero);
+                }
+                Ok(a.wrapping_div(b))
+            })?,
+            ICmp(a, b) => self.compare(a, b, |a: i64, b| Ok(a.cmp(&b)))?,
+            UCmp(a, b) => self.compare(a, b, |a: u64, b| Ok(a.cmp(&b)))?,
+            FCmp(a, b) => {
+                let is_nan = |param| matches!(param, Param::FConstant(float) if float.is_nan());
+                if is_nan(a) || is_nan(b) {
+                    return Err(CompileError::NanCompare);
+                }
+                // Registers and inputs are never read as NaN, so this can't fail either.
+                self.compare(a, b, |a: f64, b| a.partial_cmp(&b).ok_or(VmError::LoadedNan))?
+            }
+            Load(what, into) => {
+                // Loading a constant stores it as it is written, anything else is copied.
+                let what = match what {
+                    Param::FConstant(float) => Operand::Constant(float.to_bits()),
+                    Param::IConstant(integer) => Operand::Constant(transmute_from_signed(integer)),
+                    what => self.operand::<u64>(what)?,
+                };
+                let into = self.destination(into)?;
+                Box::new(move |core| {
+                    let value = what.read(core)?;
+                    into.write(core, value)
+                })
+            }
+            Jump(ref label) => {
+                let target = self.target(label)?;
+                Box::new(move |core| {
+                    core.program_counter = target;
+                    Ok(())
+                })
+            }
+            JINeg(number, ref label) => {
+                let target = self.target(label)?;
+                let number = self.operand::<i64>(number)?;
+                Box::new(move |core| {
+                    if number.read(core)? < 0 {
+                        core.program_counter = target;
+                    }
+                    Ok(())
+                })
+            }
+            JFNeg(number, ref label) => {
+                let target = self.target(label)?;
+                let number = self.operand::<f64>(number)?;
+                Box::new(move |core| {
+                    if number.read(core)? < 0. {
+                        core.program_counter = target;
+                    }
+                    Ok(())
+                })
+            }
+            JGre(ref label) => self.jump_if(label, |ordering| ordering == Ordering::Greater)?,
+            JLess(ref label) => self.jump_if(label, |ordering| ordering == Ordering::Less)?,
+            JEq(ref label) => self.jump_if(label, |ordering| ordering == Ordering::Equal)?,
+            JNeq(ref label) => self.jump_if(label, |ordering| ordering != Ordering::Equal)?,
+        })
+    }
+
+    fn arithmetic<T: Number>(
+        &mut self,
+        a: Param,
+        b: Param,
+        into: Param,
+        op: fn(T, T) -> Result<T, VmError>,
+    ) -> Result<Handler, CompileError> {
+        let a = self.operand::<T>(a)?;
+        let b = self.operand::<T>(b)?;
+        let into = self.destination(into)?;
+        Ok(Box::new(move |core| {
+            let a = a.read(core)?;
+            let b = b.read(core)?;
+            into.write(core, op(a, b)?.to_bits())
+        }))
+    }
+
+    fn compare<T: Number>(
+        &mut self,
+        a: Param,
+        b: Param,
+        compare: fn(T, T) -> Result<Ordering, VmError>,
+    ) -> Result<Handler, CompileError> {
+        let a = self.operand::<T>(a)?;
+        let b = self.operand::<T>(b)?;
+        Ok(Box::new(move |core| {
+            let a = a.read(core)?;
+            let b = b.read(core)?;
+            core.compare = Some(compare(a, b)?);
+            Ok(())
+        }))
+    }
+
+    fn jump_if(&self, label: &str, jumps: fn(Ordering) -> bool) -> Result<Handler, CompileError> {
+        let target = self.target(label)?;
+        Ok(Box::new(move |core| match core.compare {
+            Some(ordering) => {
+                if jumps(ordering) {
+                    core.program_counter = target;
+                }
+                Ok(())
+            }
+            None => Err(Interrupt::Error(VmError::JumpWithoutCompare)),
+        }))
+    }
+}
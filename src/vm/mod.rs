@@ -1,60 +1,216 @@
 use std::{
     cmp::Ordering,
-    collections::HashMap,
-    ops::{Add, Mul, Sub},
+    collections::{HashMap, VecDeque},
+    fmt,
+    task::Poll,
 };
 
-use crate::common::{Sink, Source};
+use crate::common::{Sink, SinkError, Source};
 
 use crate::program::Program;
 
+use self::registers::RegisterFile;
+
+mod compiled;
+mod observer;
+mod registers;
+mod state;
+mod trace;
+
+pub use self::compiled::{CompileError, CompiledMachine, CompiledProgram};
+pub use self::observer::{MachineObserver, ObserverId};
+pub use self::registers::{RegisterCountError, MAX_REGISTERS};
+pub use self::state::{MachineState, Undo};
+pub use self::trace::{Divergence, Trace, TraceEntry};
+
 #[cfg(test)]
 mod vm_test;
 
+/// The inputs and outputs of a machine have to be `Send` so that machines can be
+/// run on other threads.
+pub type BoxedSource = Box<dyn Source<u64> + Send>;
+pub type BoxedSink = Box<dyn Sink<u64> + Send>;
+
 pub struct MooMachine {
     compare: Option<Ordering>,
     program: Program,
-    registers: HashMap<u64, u64>,
+    registers: RegisterFile,
     program_counter: u64,
-    input: Vec<Box<Source<u64>>>,
-    output: Vec<Box<Sink<u64>>>,
+    input: Vec<BoxedSource>,
+    output: Vec<BoxedSink>,
+    // Inputs taken by an instruction that could not finish. They are handed out again
+    // in the same order when the instruction is retried so nothing is taken twice.
+    taken_inputs: Vec<u64>,
+    reused_inputs: usize,
+    // Inputs handed back by `undo`, taken before anything new is taken from a source.
+    redelivered: Vec<VecDeque<u64>>,
+    trace: Option<Trace>,
+    observers: Vec<(ObserverId, Box<dyn MachineObserver>)>,
+    next_observer: u64,
 }
 impl MooMachine {
     pub fn new(
         program: Program,
-        input: Vec<Box<Source<u64>>>,
-        output: Vec<Box<Sink<u64>>>,
+        input: Vec<BoxedSource>,
+        output: Vec<BoxedSink>,
     ) -> Self {
         MooMachine {
             compare: None,
-            program: program,
-            registers: HashMap::new(),
+            program,
+            registers: RegisterFile::Sparse(HashMap::new()),
             program_counter: 0,
+            redelivered: vec![VecDeque::new(); input.len()],
             input,
             output,
+            taken_inputs: Vec::new(),
+            reused_inputs: 0,
+            trace: None,
+            observers: Vec::new(),
+            next_observer: 0,
         }
     }
 
-    pub fn tick(&mut self) {
-        use self::Command::*;
+    /// Gives the machine a fixed number of registers, `R0` up to `R{count - 1}`,
+    /// stored in an array instead of a map. Anything written to the registers so far
+    /// is forgotten.
+    ///
+    /// Fails if `count` is more than `MAX_REGISTERS` or the program uses a register
+    /// past the end. Programs for such machines can be parsed with
+    /// `MooParser::with_register_count` or linked with `Linker::set_register_count`
+    /// to find the statement or unit using it.
+    pub fn with_register_count(mut self, count: u64) -> Result<Self, RegisterCountError> {
+        if let Some(register) = self.program.highest_register().filter(|&register| register >= count) {
+            return Err(RegisterCountError::OutOfRange { register, count });
+        }
+        self.registers = RegisterFile::dense(count)?;
+        Ok(self)
+    }
 
-        let pc = self.program_counter as usize;
+    /// Executes the instruction at the program counter.
+    /// If the instruction can't finish because an input has nothing to take or an
+    /// output is full the machine is `Blocked`. The program counter stays where it
+    /// is and the same instruction is retried on the next tick.
+    /// Once the program counter runs past the end of the program the machine is `Halted`.
+    pub fn tick(&mut self) -> Result<Status, VmError> {
+        let pc = self.program_counter;
+        if pc as usize >= self.program.len() {
+            return Ok(Status::Halted);
+        }
         self.program_counter += 1;
+        self.reused_inputs = 0;
+        let command = self.program[pc as usize].clone();
+        self.notify(|observer| observer.before_instruction(pc, &command));
 
-        if pc >= self.program.len() {
-            panic!("program counter overflowed");
+        let result = match self.execute(command) {
+            Ok(()) => {
+                self.taken_inputs.clear();
+                Ok(Status::Running)
+            }
+            Err(Interrupt::Blocked(channel)) => {
+                self.program_counter = pc;
+                Ok(Status::Blocked { channel })
+            }
+            Err(Interrupt::Error(err)) => {
+                self.program_counter = pc;
+                self.taken_inputs.clear();
+                Err(err)
+            }
+        };
+        match &result {
+            Ok(status) => self.notify(|observer| observer.after_instruction(pc, *status)),
+            Err(error) => self.notify(|observer| observer.error(pc, error)),
         }
+        result
+    }
+
+    /// Adds an observer that is told about everything the machine does from now on.
+    pub fn add_observer(&mut self, observer: Box<dyn MachineObserver>) -> ObserverId {
+        let id = ObserverId(self.next_observer);
+        self.next_observer += 1;
+        self.observers.push((id, observer));
+        id
+    }
 
-        match self.program[pc].clone() {
-            FAdd(a, b, into) => self.float_op(f64::add, "addition", a, b, into),
-            FSub(a, b, into) => self.float_op(f64::sub, "substraction", a, b, into),
-            FMul(a, b, into) => self.float_op(f64::mul, "multiplication", a, b, into),
+    /// Removes and returns the observer added as `id`, if it is still there.
+    pub fn remove_observer(&mut self, id: ObserverId) -> Option<Box<dyn MachineObserver>> {
+        let index = self.observers.iter().position(|&(added, _)| added == id)?;
+        Some(self.observers.remove(index).1)
+    }
+
+    /// Removes and returns every observer.
+    pub fn take_observers(&mut self) -> Vec<Box<dyn MachineObserver>> {
+        std::mem::take(&mut self.observers)
+            .into_iter()
+            .map(|(_, observer)| observer)
+            .collect()
+    }
+
+    fn notify<F: FnMut(&mut dyn MachineObserver)>(&mut self, mut f: F) {
+        if let Some(trace) = &mut self.trace {
+            f(trace);
+        }
+        for (_, observer) in &mut self.observers {
+            f(observer.as_mut());
+        }
+    }
+
+    fn set_compare(&mut self, compare: Ordering) {
+        if self.compare != Some(compare) {
+            self.compare = Some(compare);
+            self.notify(|observer| observer.compare_changed(compare));
+        }
+    }
+
+    /// Starts recording every tick into a new `Trace`, dropping any trace that was
+    /// being recorded.
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Trace::new(self.input.len(), self.output.len()));
+    }
+
+    /// Stops recording and returns what was recorded since `start_trace`.
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// The address of the next instruction to execute.
+    pub fn program_counter(&self) -> u64 {
+        self.program_counter
+    }
+
+    /// The bits in `register`. Registers that were never written hold 0.
+    pub fn register(&self, register: u64) -> u64 {
+        *self.registers.get(&register).unwrap_or(&0)
+    }
+
+    /// The result of the latest compare, if there was one.
+    pub fn compare(&self) -> Option<Ordering> {
+        self.compare
+    }
+
+    /// Closes every output of the machine.
+    pub fn close_outputs(&mut self) {
+        for output in &mut self.output {
+            output.close();
+        }
+    }
+
+    fn execute(&mut self, command: Command) -> Result<(), Interrupt> {
+        use self::Command::*;
+
+        match command {
+            FAdd(a, b, into) => self.float_op(|a, b| Ok(a + b), "addition", a, b, into),
+            FSub(a, b, into) => self.float_op(|a, b| Ok(a - b), "substraction", a, b, into),
+            FMul(a, b, into) => self.float_op(|a, b| Ok(a * b), "multiplication", a, b, into),
             FDiv(a, b, into) => self.float_op(
                 |a, b| {
                     if b == 0. {
-                        panic!("Float division by a zero");
+                        Err(VmError::DivisionByZero)
                     } else {
-                        a / b
+                        Ok(a / b)
                     }
                 },
                 "division",
@@ -62,15 +218,15 @@ impl MooMachine {
                 b,
                 into,
             ),
-            UAdd(a, b, into) => self.unsigned_integer_op(u64::wrapping_add, "addition", a, b, into),
-            USub(a, b, into) => self.unsigned_integer_op(u64::wrapping_sub, "substraction", a, b, into),
-            UMul(a, b, into) => self.unsigned_integer_op(u64::wrapping_mul, "multiplication", a, b, into),
+            UAdd(a, b, into) => self.unsigned_integer_op(|a, b| Ok(a.wrapping_add(b)), "addition", a, b, into),
+            USub(a, b, into) => self.unsigned_integer_op(|a, b| Ok(a.wrapping_sub(b)), "substraction", a, b, into),
+            UMul(a, b, into) => self.unsigned_integer_op(|a, b| Ok(a.wrapping_mul(b)), "multiplication", a, b, into),
             UDiv(a, b, into) => self.unsigned_integer_op(
                 |a, b| {
                     if b == 0 {
-                        panic!("Unsigned integer division by a zero");
+                        Err(VmError::DivisionByZero)
                     } else {
-                        a.wrapping_div(b)
+                        Ok(a.wrapping_div(b))
                     }
                 },
                 "division",
@@ -78,15 +234,15 @@ impl MooMachine {
                 b,
                 into,
             ),
-            IAdd(a, b, into) => self.signed_integer_op(i64::wrapping_add, "addition", a, b, into),
-            ISub(a, b, into) => self.signed_integer_op(i64::wrapping_sub, "substraction", a, b, into),
-            IMul(a, b, into) => self.signed_integer_op(i64::wrapping_mul, "multiplication", a, b, into),
+            IAdd(a, b, into) => self.signed_integer_op(|a, b| Ok(a.wrapping_add(b)), "addition", a, b, into),
+            ISub(a, b, into) => self.signed_integer_op(|a, b| Ok(a.wrapping_sub(b)), "substraction", a, b, into),
+            IMul(a, b, into) => self.signed_integer_op(|a, b| Ok(a.wrapping_mul(b)), "multiplication", a, b, into),
             IDiv(a, b, into) => self.signed_integer_op(
                 |a, b| {
                     if b == 0 {
-                        panic!("Unsigned integer division by a zero");
+                        Err(VmError::DivisionByZero)
                     } else {
-                        a.wrapping_div(b)
+                        Ok(a.wrapping_div(b))
                     }
                 },
                 "division",
@@ -98,242 +254,312 @@ impl MooMachine {
             ICmp(a, b) => self.signed_integer_compare(a, b),
             UCmp(a, b) => self.unsigned_integer_compare(a, b),
             FCmp(a, b) => self.float_compare(a, b),
-            Jump(ref label) => self.jump(&label),
-            JFNeg(number, ref label) => self.integer_jump_if_negative(number, &label),
-            JINeg(number, ref label) => self.float_jump_if_negative(number, &label),
-            JGre(ref label) => self.jump_if(&label, Ordering::Greater),
-            JLess(ref label) => self.jump_if(&label, Ordering::Less),
-            JEq(ref label) => self.jump_if(&label, Ordering::Equal),
-            JNeq(ref label) => self.jump_if_not_equal(&label),
-            _ => unimplemented!(),
+            Jump(ref label) => self.jump(label),
+            JFNeg(number, ref label) => self.float_jump_if_negative(number, label),
+            JINeg(number, ref label) => self.integer_jump_if_negative(number, label),
+            JGre(ref label) => self.jump_if(label, Ordering::Greater),
+            JLess(ref label) => self.jump_if(label, Ordering::Less),
+            JEq(ref label) => self.jump_if(label, Ordering::Equal),
+            JNeq(ref label) => self.jump_if_not_equal(label),
         }
     }
 
-    fn signed_integer_compare(&mut self, a: Param, b: Param) {
-        let a = self.get_signed_integer(a);
-        let b = self.get_signed_integer(b);
-        self.compare = Some(a.cmp(&b));
+    fn signed_integer_compare(&mut self, a: Param, b: Param) -> Result<(), Interrupt> {
+        let a = self.get_signed_integer(a)?;
+        let b = self.get_signed_integer(b)?;
+        self.set_compare(a.cmp(&b));
+        Ok(())
     }
 
-    fn unsigned_integer_compare(&mut self, a: Param, b: Param) {
-        let a = self.get_unsigned_integer(a);
-        let b = self.get_unsigned_integer(b);
-        self.compare = Some(a.cmp(&b));
+    fn unsigned_integer_compare(&mut self, a: Param, b: Param) -> Result<(), Interrupt> {
+        let a = self.get_unsigned_integer(a)?;
+        let b = self.get_unsigned_integer(b)?;
+        self.set_compare(a.cmp(&b));
+        Ok(())
     }
 
-    fn float_compare(&mut self, a: Param, b: Param) {
-        let a = self.get_float(a);
-        let b = self.get_float(b);
-        //TODO: Figure out whether panicking here is the best option.
-        let ordering = a.partial_cmp(&b).expect("the floats a and b were not comparable.");
-        self.compare = Some(ordering);
+    fn float_compare(&mut self, a: Param, b: Param) -> Result<(), Interrupt> {
+        let a = self.get_float(a)?;
+        let b = self.get_float(b)?;
+        // Floats read from registers and inputs are never NaN, but constants can be.
+        let ordering = a.partial_cmp(&b).ok_or(VmError::LoadedNan)?;
+        self.set_compare(ordering);
+        Ok(())
     }
 
-    fn jump(&mut self, label: &str) {
-        self.program_counter = self.program.get_address(label);
+    fn jump(&mut self, label: &str) -> Result<(), Interrupt> {
+        // The program counter already points past the jump.
+        let from = self.program_counter - 1;
+        let to = match self.program.labels().get(label) {
+            Some(&to) => to,
+            None => return Err(Interrupt::Error(VmError::UnknownLabel(label.to_string()))),
+        };
+        self.program_counter = to;
+        self.notify(|observer| observer.jump_taken(from, to));
+        Ok(())
     }
 
-    fn integer_jump_if_negative(&mut self, number: Param, label: &str) {
-        use self::Param::*;
-        let number = match number {
-            Register(_) | Input(_) => {
-                self.get_signed_integer(number)
-            },
-            IConstant(what) => what,
-            _ => panic!("Invalid parameter"),
-        };
+    fn integer_jump_if_negative(&mut self, number: Param, label: &str) -> Result<(), Interrupt> {
+        let number = self.get_signed_integer(number)?;
         if number < 0 {
-            self.jump(label);
+            self.jump(label)?;
         }
+        Ok(())
     }
 
-    fn float_jump_if_negative(&mut self, number: Param, label: &str) {
-        use self::Param::*;
-        let number = match number {
-            Register(_) | Input(_) => {
-                self.get_float(number)
-            },
-            FConstant(what) => what,
-            _ => panic!(),
-        };
+    fn float_jump_if_negative(&mut self, number: Param, label: &str) -> Result<(), Interrupt> {
+        let number = self.get_float(number)?;
         if number < 0. {
-            self.jump(label);
+            self.jump(label)?;
         }
+        Ok(())
     }
 
-    fn jump_if(&mut self, label: &str, ord: Ordering) {
+    fn jump_if(&mut self, label: &str, ord: Ordering) -> Result<(), Interrupt> {
         match self.compare {
             Some(o) => {
                 if o == ord {
-                    self.jump(label);
+                    self.jump(label)?;
                 }
+                Ok(())
             },
-            None => panic!("Tried to conditional jump without comparing"),
+            None => Err(Interrupt::Error(VmError::JumpWithoutCompare)),
         }
     }
 
-    fn jump_if_not_equal(&mut self, label: &str) {
+    fn jump_if_not_equal(&mut self, label: &str) -> Result<(), Interrupt> {
         match self.compare {
-            Some(Ordering::Equal) => {},
+            Some(Ordering::Equal) => Ok(()),
             Some(_) => self.jump(label),
-            None => panic!("Tried to conditional jump without comparing")
+            None => Err(Interrupt::Error(VmError::JumpWithoutCompare)),
         }
     }
 
-    fn load(&mut self, what: Param, into: Param) {
+    fn load(&mut self, what: Param, into: Param) -> Result<(), Interrupt> {
         use self::Param::*;
         match what {
             FConstant(what) => self.store_float(what, into),
             IConstant(what) => self.store_signed_integer(what, into),
             UConstant(what) => self.store_unsigned_integer(what, into),
             Input(_) | Register(_) => {
-                let what = self.get_unsigned_integer(what);
-                self.store_unsigned_integer(what, into);
+                let what = self.get_unsigned_integer(what)?;
+                self.store_unsigned_integer(what, into)
             },
-            _ => panic!(),
+            Output(channel) => Err(Interrupt::Error(VmError::ReadOutput(channel))),
         }
     }
 
-    fn float_op<F>(&mut self, op: F, op_name: &str, a: Param, b: Param, into: Param)
+    fn float_op<F>(&mut self, op: F, _op_name: &str, a: Param, b: Param, into: Param) -> Result<(), Interrupt>
     where
-        F: Fn(f64, f64) -> f64,
+        F: Fn(f64, f64) -> Result<f64, VmError>,
     {
-        let a = self.get_float(a);
-        let b = self.get_float(b);
-        self.store_float(op(a, b), into);
+        let a = self.get_float(a)?;
+        let b = self.get_float(b)?;
+        self.store_float(op(a, b)?, into)
     }
 
-    fn unsigned_integer_op<F>(&mut self, op: F, op_name: &str, a: Param, b: Param, into: Param)
+    fn unsigned_integer_op<F>(&mut self, op: F, _op_name: &str, a: Param, b: Param, into: Param) -> Result<(), Interrupt>
     where
-        F: Fn(u64, u64) -> u64,
+        F: Fn(u64, u64) -> Result<u64, VmError>,
     {
-        let a = self.get_unsigned_integer(a);
-        let b = self.get_unsigned_integer(b);
-        self.store_unsigned_integer(op(a, b), into);
+        let a = self.get_unsigned_integer(a)?;
+        let b = self.get_unsigned_integer(b)?;
+        self.store_unsigned_integer(op(a, b)?, into)
     }
 
-    fn signed_integer_op<F>(&mut self, op: F, op_name: &str, a: Param, b: Param, into: Param)
+    fn signed_integer_op<F>(&mut self, op: F, _op_name: &str, a: Param, b: Param, into: Param) -> Result<(), Interrupt>
     where
-        F: Fn(i64, i64) -> i64,
+        F: Fn(i64, i64) -> Result<i64, VmError>,
     {
-        let a = self.get_signed_integer(a);
-        let b = self.get_signed_integer(b);
-        self.store_signed_integer(op(a, b), into);
+        let a = self.get_signed_integer(a)?;
+        let b = self.get_signed_integer(b)?;
+        self.store_signed_integer(op(a, b)?, into)
     }
 
-    fn store_unsigned_integer(&mut self, what: u64, into: Param) {
+    fn store_unsigned_integer(&mut self, what: u64, into: Param) -> Result<(), Interrupt> {
         use self::Param::*;
         match into {
             Register(into) => {
-                self.registers.insert(into, what);
+                self.write_register(into, what);
+                Ok(())
             }
-            Output(into) => self.output.get_mut(into as usize).unwrap().put(what),
-            _ => panic!("tried to store an unsigned integer into a {:?}", into),
+            Output(into) => self.put_output(into, what),
+            _ => Err(Interrupt::Error(VmError::InvalidDestination)),
         }
     }
 
-    fn get_unsigned_integer(&mut self, param: Param) -> u64 {
+    fn write_register(&mut self, register: u64, value: u64) {
+        self.registers.insert(register, value);
+        self.notify(|observer| observer.register_written(register, value));
+    }
+
+    fn put_output(&mut self, channel: u64, what: u64) -> Result<(), Interrupt> {
+        let output = self.output.get_mut(channel as usize).ok_or(VmError::NoOutput(channel))?;
+        match output.put(what) {
+            Ok(()) => {
+                self.notify(|observer| observer.output_produced(channel, what));
+                Ok(())
+            }
+            Err(SinkError::Full) => Err(Interrupt::Blocked(Channel::Output(channel))),
+            Err(SinkError::Closed) => Err(Interrupt::Error(VmError::OutputClosed(channel))),
+        }
+    }
+
+    fn take_input(&mut self, channel: u64) -> Result<u64, Interrupt> {
+        if let Some(&taken) = self.taken_inputs.get(self.reused_inputs) {
+            self.reused_inputs += 1;
+            return Ok(taken);
+        }
+        let redelivered = self.redelivered.get_mut(channel as usize).and_then(VecDeque::pop_front);
+        let polled = match redelivered {
+            Some(taken) => Poll::Ready(Some(taken)),
+            None => self.input.get_mut(channel as usize).ok_or(VmError::NoInput(channel))?.poll(),
+        };
+        match polled {
+            Poll::Ready(Some(taken)) => {
+                self.notify(|observer| observer.input_consumed(channel, taken));
+                self.taken_inputs.push(taken);
+                self.reused_inputs += 1;
+                Ok(taken)
+            }
+            Poll::Ready(None) => Err(Interrupt::Error(VmError::InputClosed(channel))),
+            Poll::Pending => Err(Interrupt::Blocked(Channel::Input(channel))),
+        }
+    }
+
+    fn get_unsigned_integer(&mut self, param: Param) -> Result<u64, Interrupt> {
         use self::Param::*;
-        match param {
+        Ok(match param {
             Register(register) => *self.registers.get(&register).unwrap_or(&0),
-            Input(channel) => self
-                .input
-                .get_mut(channel as usize)
-                .unwrap()
-                .take()
-                .unwrap(),
+            Input(channel) => self.take_input(channel)?,
             FConstant(float) => float as u64,
             UConstant(integer) => integer,
             IConstant(integer) => integer as u64,
-            _ => panic!("tried to get an integer from {:?}", param),
-        }
+            Output(channel) => return Err(Interrupt::Error(VmError::ReadOutput(channel))),
+        })
     }
 
-    fn store_signed_integer(&mut self, what: i64, into: Param) {
+    fn store_signed_integer(&mut self, what: i64, into: Param) -> Result<(), Interrupt> {
         use self::Param::*;
         match into {
             Register(into) => {
-                self.registers.insert(into, transmute_from_signed(what));
+                self.write_register(into, transmute_from_signed(what));
+                Ok(())
             }
-            Output(into) => self
-                .output
-                .get_mut(into as usize)
-                .unwrap()
-                .put(transmute_from_signed(what)),
-            _ => panic!("tried to store an signed integer into a {:?}", into),
+            Output(into) => self.put_output(into, transmute_from_signed(what)),
+            _ => Err(Interrupt::Error(VmError::InvalidDestination)),
         }
     }
 
-    fn get_signed_integer(&mut self, param: Param) -> i64 {
+    fn get_signed_integer(&mut self, param: Param) -> Result<i64, Interrupt> {
         use self::Param::*;
-        match param {
+        Ok(match param {
             Register(register) => transmute_to_signed(*self.registers.get(&register).unwrap_or(&0)),
-            Input(channel) => transmute_to_signed(
-                self.input
-                    .get_mut(channel as usize)
-                    .unwrap()
-                    .take()
-                    .unwrap(),
-            ),
+            Input(channel) => transmute_to_signed(self.take_input(channel)?),
             FConstant(float) => float as i64,
             UConstant(integer) => integer as i64,
             IConstant(integer) => integer,
-            _ => panic!("tried to get an integer from {:?}", param),
-        }
+            Output(channel) => return Err(Interrupt::Error(VmError::ReadOutput(channel))),
+        })
     }
 
-    fn store_float(&mut self, what: f64, into: Param) {
+    fn store_float(&mut self, what: f64, into: Param) -> Result<(), Interrupt> {
         use self::Param::*;
         match into {
             Register(into) => {
-                self.registers.insert(into, what.to_bits());
+                self.write_register(into, what.to_bits());
+                Ok(())
             }
-            Output(into) => self
-                .output
-                .get_mut(into as usize)
-                .unwrap()
-                .put(what.to_bits()),
-            _ => panic!("tried to store a float into something that cannot store floats"),
+            Output(into) => self.put_output(into, what.to_bits()),
+            _ => Err(Interrupt::Error(VmError::InvalidDestination)),
         }
     }
 
-    fn get_float(&mut self, param: Param) -> f64 {
+    fn get_float(&mut self, param: Param) -> Result<f64, Interrupt> {
         use self::Param::*;
-        match param {
-            Register(register) => transmute_to_float(*self.registers.get(&register).unwrap_or(&0)),
-            Input(channel) => transmute_to_float(
-                self.input
-                    .get_mut(channel as usize)
-                    .unwrap()
-                    .take()
-                    .unwrap(),
-            ),
+        Ok(match param {
+            Register(register) => transmute_to_float(*self.registers.get(&register).unwrap_or(&0))?,
+            Input(channel) => transmute_to_float(self.take_input(channel)?)?,
             FConstant(float) => float,
             IConstant(integer) => integer as f64,
             UConstant(integer) => integer as f64,
-            _ => unimplemented!(),
-        }
+            Output(channel) => return Err(Interrupt::Error(VmError::ReadOutput(channel))),
+        })
+    }
+}
+
+/// What a machine did on a tick.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Running,
+    /// The instruction at the program counter is waiting on `channel` and will be
+    /// retried on the next tick.
+    Blocked { channel: Channel },
+    /// The program counter is past the end of the program.
+    Halted,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Input(u64),
+    Output(u64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VmError {
+    /// The source behind the input channel has permanently run out.
+    InputClosed(u64),
+    /// The sink behind the output channel was closed.
+    OutputClosed(u64),
+    /// An integer or float division by zero.
+    DivisionByZero,
+    /// A float was read from bits that are NaN.
+    LoadedNan,
+    /// A float was read from bits that are an infinity.
+    LoadedInfinity,
+    /// A conditional jump on the compare result before anything was compared.
+    JumpWithoutCompare,
+    /// An instruction reads from the output channel.
+    ReadOutput(u64),
+    /// An instruction stores into an input or a constant.
+    InvalidDestination,
+    /// A jump goes to a label the program doesn't define.
+    UnknownLabel(String),
+    /// The program uses an input channel the machine wasn't given.
+    NoInput(u64),
+    /// The program uses an output channel the machine wasn't given.
+    NoOutput(u64),
+}
+
+/// Why an instruction could not finish.
+enum Interrupt {
+    Blocked(Channel),
+    Error(VmError),
+}
+
+impl From<VmError> for Interrupt {
+    fn from(error: VmError) -> Self {
+        Interrupt::Error(error)
     }
 }
 
-fn transmute_to_float(val: u64) -> f64 {
+fn transmute_to_float(val: u64) -> Result<f64, VmError> {
     let val = f64::from_bits(val);
     if val.is_nan() {
-        panic!("tried to load nan");
+        Err(VmError::LoadedNan)
     } else if val.is_infinite() {
-        panic!("tried to load infinity");
+        Err(VmError::LoadedInfinity)
+    } else {
+        Ok(val)
     }
-    val
 }
 
 fn transmute_to_signed(val: u64) -> i64 {
-    use std::mem::transmute;
-    unsafe { transmute(val) }
+    val as i64
 }
 
 fn transmute_from_signed(val: i64) -> u64 {
-    use std::mem::transmute;
-    unsafe { transmute(val) }
+    val as u64
 }
 
 ///General order of the parameters is (what, where)
@@ -363,6 +589,130 @@ pub enum Command {
     JEq(String),
     JNeq(String),
 }
+
+impl Command {
+    /// Returns the label this command may jump to, if it is a jump of any kind.
+    pub fn label(&self) -> Option<&str> {
+        use self::Command::*;
+        match self {
+            Jump(label) | JFNeg(_, label) | JINeg(_, label) | JGre(label) | JLess(label)
+            | JEq(label) | JNeq(label) => Some(label),
+            _ => None,
+        }
+    }
+
+    /// Whether the command only jumps when a condition holds.
+    pub fn is_conditional_jump(&self) -> bool {
+        self.label().is_some() && !matches!(self, Command::Jump(_))
+    }
+
+    /// The name the instruction has in moo source.
+    pub fn mnemonic(&self) -> &'static str {
+        use self::Command::*;
+        match self {
+            IAdd(..) => "iadd",
+            ISub(..) => "isub",
+            IMul(..) => "imul",
+            IDiv(..) => "idiv",
+            ICmp(..) => "icmp",
+            UAdd(..) => "uadd",
+            USub(..) => "usub",
+            UMul(..) => "umul",
+            UDiv(..) => "udiv",
+            UCmp(..) => "ucmp",
+            FAdd(..) => "fadd",
+            FSub(..) => "fsub",
+            FMul(..) => "fmul",
+            FDiv(..) => "fdiv",
+            FCmp(..) => "fcmp",
+            Load(..) => "load",
+            Jump(_) => "jump",
+            JFNeg(..) => "jfneg",
+            JINeg(..) => "jineg",
+            JGre(_) => "jgre",
+            JLess(_) => "jless",
+            JEq(_) => "jeq",
+            JNeq(_) => "jneq",
+        }
+    }
+
+    /// The mnemonics of every kind of instruction.
+    pub fn mnemonics() -> Vec<&'static str> {
+        use self::Command::*;
+        let (p, label) = (Param::Register(0), String::new);
+        [
+            IAdd(p, p, p),
+            ISub(p, p, p),
+            IMul(p, p, p),
+            IDiv(p, p, p),
+            ICmp(p, p),
+            UAdd(p, p, p),
+            USub(p, p, p),
+            UMul(p, p, p),
+            UDiv(p, p, p),
+            UCmp(p, p),
+            FAdd(p, p, p),
+            FSub(p, p, p),
+            FMul(p, p, p),
+            FDiv(p, p, p),
+            FCmp(p, p),
+            Load(p, p),
+            Jump(label()),
+            JFNeg(p, label()),
+            JINeg(p, label()),
+            JGre(label()),
+            JLess(label()),
+            JEq(label()),
+            JNeq(label()),
+        ]
+        .iter()
+        .map(Command::mnemonic)
+        .collect()
+    }
+
+    /// Returns the parameter the command stores its result in, if it stores one.
+    pub fn destination(&self) -> Option<Param> {
+        use self::Command::*;
+        match *self {
+            IAdd(_, _, into) | ISub(_, _, into) | IMul(_, _, into) | IDiv(_, _, into) | UAdd(_, _, into)
+            | USub(_, _, into) | UMul(_, _, into) | UDiv(_, _, into) | FAdd(_, _, into) | FSub(_, _, into)
+            | FMul(_, _, into) | FDiv(_, _, into) | Load(_, into) => Some(into),
+            _ => None,
+        }
+    }
+
+    /// Returns the parameters of the command in the order they are written.
+    pub fn params(&self) -> Vec<Param> {
+        use self::Command::*;
+        match *self {
+            IAdd(a, b, c) | ISub(a, b, c) | IMul(a, b, c) | IDiv(a, b, c) | UAdd(a, b, c) | USub(a, b, c)
+            | UMul(a, b, c) | UDiv(a, b, c) | FAdd(a, b, c) | FSub(a, b, c) | FMul(a, b, c) | FDiv(a, b, c) => {
+                vec![a, b, c]
+            }
+            ICmp(a, b) | UCmp(a, b) | FCmp(a, b) | Load(a, b) => vec![a, b],
+            JFNeg(a, _) | JINeg(a, _) => vec![a],
+            Jump(_) | JGre(_) | JLess(_) | JEq(_) | JNeq(_) => Vec::new(),
+        }
+    }
+
+    /// Returns the parameters the command reads, which are all of them but the destination.
+    pub fn operands(&self) -> Vec<Param> {
+        let mut params = self.params();
+        if self.destination().is_some() {
+            params.pop();
+        }
+        params
+    }
+
+    pub fn label_mut(&mut self) -> Option<&mut String> {
+        use self::Command::*;
+        match self {
+            Jump(label) | JFNeg(_, label) | JINeg(_, label) | JGre(label) | JLess(label)
+            | JEq(label) | JNeq(label) => Some(label),
+            _ => None,
+        }
+    }
+}
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Param {
     Register(u64),
@@ -372,3 +722,30 @@ pub enum Param {
     Input(u64),
     Output(u64),
 }
+
+/// Writes the command the way it would be written in moo source.
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic())?;
+        for param in self.params() {
+            write!(f, " {}", param)?;
+        }
+        if let Some(label) = self.label() {
+            write!(f, " {}", label)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Param::Register(register) => write!(f, "R{}", register),
+            Param::Input(channel) => write!(f, "I{}", channel),
+            Param::Output(channel) => write!(f, "O{}", channel),
+            Param::FConstant(float) => write!(f, "{}f", float),
+            Param::IConstant(integer) => write!(f, "{}i", integer),
+            Param::UConstant(integer) => write!(f, "{}u", integer),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::cmp::Ordering;
+use std::sync::{Arc, Mutex};
+
+use super::{Command, Status, VmError};
+
+/// Gets told what a `MooMachine` does while it ticks. Every method does nothing
+/// by default so observers only implement what they need.
+///
+/// Observers are added with `MooMachine::add_observer`. To look at what an
+/// observer has collected while the machine still owns it, add it as an
+/// `Arc<Mutex<_>>` and keep a clone of the `Arc`.
+pub trait MachineObserver: Send {
+    /// The instruction at `address` is about to be executed.
+    fn before_instruction(&mut self, _address: u64, _command: &Command) {}
+
+    /// The instruction at `address` has finished (`Running`) or has to be retried
+    /// (`Blocked`).
+    fn after_instruction(&mut self, _address: u64, _status: Status) {}
+
+    fn register_written(&mut self, _register: u64, _value: u64) {}
+
+    /// A compare left the compare flag different from what it was.
+    fn compare_changed(&mut self, _compare: Ordering) {}
+
+    /// The instruction at `from` jumped to `to`. Conditional jumps that don't jump
+    /// aren't reported.
+    fn jump_taken(&mut self, _from: u64, _to: u64) {}
+
+    /// A value was taken from input `channel`. Values an instruction took before it
+    /// was blocked aren't reported again when it is retried.
+    fn input_consumed(&mut self, _channel: u64, _value: u64) {}
+
+    fn output_produced(&mut self, _channel: u64, _value: u64) {}
+
+    /// The instruction at `address` failed. It isn't followed by `after_instruction`.
+    fn error(&mut self, _address: u64, _error: &VmError) {}
+}
+
+/// Identifies an observer added to a machine, to remove it again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObserverId(pub(super) u64);
+
+impl<O: MachineObserver> MachineObserver for Arc<Mutex<O>> {
+    fn before_instruction(&mut self, address: u64, command: &Command) {
+        self.lock().unwrap().before_instruction(address, command)
+    }
+
+    fn after_instruction(&mut self, address: u64, status: Status) {
+        self.lock().unwrap().after_instruction(address, status)
+    }
+
+    fn register_written(&mut self, register: u64, value: u64) {
+        self.lock().unwrap().register_written(register, value)
+    }
+
+    fn compare_changed(&mut self, compare: Ordering) {
+        self.lock().unwrap().compare_changed(compare)
+    }
+
+    fn jump_taken(&mut self, from: u64, to: u64) {
+        self.lock().unwrap().jump_taken(from, to)
+    }
+
+    fn input_consumed(&mut self, channel: u64, value: u64) {
+        self.lock().unwrap().input_consumed(channel, value)
+    }
+
+    fn output_produced(&mut self, channel: u64, value: u64) {
+        self.lock().unwrap().output_produced(channel, value)
+    }
+
+    fn error(&mut self, address: u64, error: &VmError) {
+        self.lock().unwrap().error(address, error)
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+/// The most registers a machine with a fixed number of registers can have.
+pub const MAX_REGISTERS: u64 = 1 << 16;
+
+/// Why a machine can't be given a fixed number of registers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RegisterCountError {
+    /// More than `MAX_REGISTERS` registers were asked for.
+    TooMany(u64),
+    /// The program uses `register`, which a machine with `count` registers doesn't have.
+    OutOfRange { register: u64, count: u64 },
+}
+
+/// The registers of a `MooMachine`. Registers that were never written read as 0.
+/// Only written registers are recorded by snapshots and undo, where a register of a
+/// fixed size register file counts as written when it isn't 0.
+#[derive(Clone, Debug)]
+pub(super) enum RegisterFile {
+    /// Every register number can be used and only written registers take up memory.
+    Sparse(HashMap<u64, u64>),
+    /// Registers `R0` up to the length of the array.
+    Dense(Vec<u64>),
+}
+
+impl RegisterFile {
+    pub(super) fn dense(count: u64) -> Result<Self, RegisterCountError> {
+        if count > MAX_REGISTERS {
+            return Err(RegisterCountError::TooMany(count));
+        }
+        Ok(RegisterFile::Dense(vec![0; count as usize]))
+    }
+
+    /// Fails if the register file has a fixed size and `register` is past it.
+    pub(super) fn check(&self, register: u64) -> Result<(), RegisterCountError> {
+        match self {
+            RegisterFile::Dense(registers) if register >= registers.len() as u64 => Err(RegisterCountError::OutOfRange {
+                register,
+                count: registers.len() as u64,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// The value in `register` if it was ever written.
+    pub(super) fn get(&self, register: &u64) -> Option<&u64> {
+        match self {
+            RegisterFile::Sparse(registers) => registers.get(register),
+            RegisterFile::Dense(registers) => registers.get(*register as usize).filter(|&&value| value != 0),
+        }
+    }
+
+    pub(super) fn insert(&mut self, register: u64, value: u64) {
+        match self {
+            RegisterFile::Sparse(registers) => {
+                registers.insert(register, value);
+            }
+            RegisterFile::Dense(registers) => *slot(registers, register) = value,
+        }
+    }
+
+    /// Makes `register` unwritten again.
+    pub(super) fn remove(&mut self, register: &u64) {
+        match self {
+            RegisterFile::Sparse(registers) => {
+                registers.remove(register);
+            }
+            RegisterFile::Dense(registers) => *slot(registers, *register) = 0,
+        }
+    }
+
+    /// Forgets every register that was written.
+    pub(super) fn clear(&mut self) {
+        match self {
+            RegisterFile::Sparse(registers) => registers.clear(),
+            RegisterFile::Dense(registers) => registers.iter_mut().for_each(|value| *value = 0),
+        }
+    }
+
+    /// The written registers and their values, in no particular order.
+    pub(super) fn iter(&self) -> Box<dyn Iterator<Item = (u64, u64)> + '_> {
+        match self {
+            RegisterFile::Sparse(registers) => Box::new(registers.iter().map(|(&register, &value)| (register, value))),
+            RegisterFile::Dense(registers) => Box::new(
+                registers
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &value)| value != 0)
+                    .map(|(register, &value)| (register as u64, value)),
+            ),
+        }
+    }
+}
+
+fn slot(registers: &mut [u64], register: u64) -> &mut u64 {
+    let count = registers.len();
+    registers
+        .get_mut(register as usize)
+        .unwrap_or_else(|| panic!("R{} is out of range for a machine with {} registers", register, count))
+}
@@ -0,0 +1,176 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Read, Write};
+
+use super::{Command, MooMachine, Param, RegisterCountError};
+
+/// Bumped whenever the layout written by `MachineState::write` changes.
+const FORMAT_VERSION: u64 = 1;
+
+/// Everything about a running `MooMachine` that isn't its program or its
+/// inputs and outputs.
+///
+/// A state taken with `MooMachine::snapshot` can be restored into any machine
+/// running the same program, including one created after a restart from a state
+/// saved with `write`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MachineState {
+    pub registers: BTreeMap<u64, u64>,
+    pub program_counter: u64,
+    pub compare: Option<Ordering>,
+    /// Inputs already taken by the instruction at the program counter, which was
+    /// blocked before it could finish. They are handed to it again when it is retried.
+    pub pending_inputs: Vec<u64>,
+}
+
+impl MachineState {
+    /// Writes the state as little-endian `u64`s.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let compare = match self.compare {
+            None => 0,
+            Some(Ordering::Less) => 1,
+            Some(Ordering::Equal) => 2,
+            Some(Ordering::Greater) => 3,
+        };
+        let mut words = vec![FORMAT_VERSION, self.program_counter, compare, self.registers.len() as u64];
+        for (&register, &value) in &self.registers {
+            words.push(register);
+            words.push(value);
+        }
+        words.push(self.pending_inputs.len() as u64);
+        words.extend(&self.pending_inputs);
+        for word in words {
+            writer.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Reads a state written by `write`.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let version = read_word(&mut reader)?;
+        if version != FORMAT_VERSION {
+            return Err(invalid_data(format!("unsupported machine state version {}", version)));
+        }
+        let program_counter = read_word(&mut reader)?;
+        let compare = match read_word(&mut reader)? {
+            0 => None,
+            1 => Some(Ordering::Less),
+            2 => Some(Ordering::Equal),
+            3 => Some(Ordering::Greater),
+            other => return Err(invalid_data(format!("{} is not a valid compare flag", other))),
+        };
+        let mut registers = BTreeMap::new();
+        for _ in 0..read_word(&mut reader)? {
+            let register = read_word(&mut reader)?;
+            registers.insert(register, read_word(&mut reader)?);
+        }
+        let mut pending_inputs = Vec::new();
+        for _ in 0..read_word(&mut reader)? {
+            pending_inputs.push(read_word(&mut reader)?);
+        }
+        Ok(MachineState {
+            registers,
+            program_counter,
+            compare,
+            pending_inputs,
+        })
+    }
+}
+
+fn read_word<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl MooMachine {
+    pub fn snapshot(&self) -> MachineState {
+        MachineState {
+            registers: self.registers.iter().collect(),
+            program_counter: self.program_counter,
+            compare: self.compare,
+            pending_inputs: self.taken_inputs.clone(),
+        }
+    }
+
+    /// Replaces the state of the machine. The program, inputs and outputs are
+    /// left as they are, but inputs handed out again by `undo` are forgotten. Fails
+    /// without changing anything if the state has a register a machine with a fixed
+    /// number of registers doesn't have.
+    pub fn restore(&mut self, state: MachineState) -> Result<(), RegisterCountError> {
+        if let Some(&register) = state.registers.keys().max() {
+            self.registers.check(register)?;
+        }
+        self.registers.clear();
+        for (register, value) in state.registers {
+            self.registers.insert(register, value);
+        }
+        self.program_counter = state.program_counter;
+        self.compare = state.compare;
+        self.taken_inputs = state.pending_inputs;
+        self.reused_inputs = 0;
+        self.redelivered.iter_mut().for_each(VecDeque::clear);
+        Ok(())
+    }
+}
+
+/// What the instruction at the program counter can change, recorded by
+/// `MooMachine::undo_point` before it is ticked so the tick can be taken back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Undo {
+    program_counter: u64,
+    compare: Option<Ordering>,
+    pending_inputs: Vec<u64>,
+    // The register the instruction stores into and what it held, if anything.
+    register: Option<(u64, Option<u64>)>,
+}
+
+impl MooMachine {
+    pub fn undo_point(&self) -> Undo {
+        let register = self
+            .program
+            .commands()
+            .get(self.program_counter as usize)
+            .and_then(Command::destination)
+            .and_then(|destination| match destination {
+                Param::Register(register) => Some((register, self.registers.get(&register).copied())),
+                _ => None,
+            });
+        Undo {
+            program_counter: self.program_counter,
+            compare: self.compare,
+            pending_inputs: self.taken_inputs.clone(),
+            register,
+        }
+    }
+
+    /// Takes back the tick that followed `undo_point`. `taken` are the
+    /// `(channel, value)`s the tick took from its inputs, as listed in its
+    /// `TraceEntry`. They are handed out again before anything new is taken from the
+    /// inputs, so the tick can be run again with the same values.
+    ///
+    /// Values put into outputs can't be taken back and are put again if the tick is
+    /// run again.
+    pub fn undo(&mut self, undo: Undo, taken: &[(u64, u64)]) {
+        self.program_counter = undo.program_counter;
+        self.compare = undo.compare;
+        self.taken_inputs = undo.pending_inputs;
+        self.reused_inputs = 0;
+        match undo.register {
+            Some((register, Some(value))) => {
+                self.registers.insert(register, value);
+            }
+            Some((register, None)) => {
+                self.registers.remove(&register);
+            }
+            None => {}
+        }
+        for &(channel, value) in taken.iter().rev() {
+            self.redelivered[channel as usize].push_front(value);
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use std::io::{self, Read, Write};
+
+use super::{BoxedSink, BoxedSource, Channel, Command, MachineObserver, MooMachine, Status, VmError};
+use crate::program::Program;
+
+/// Bumped whenever the layout written by `Trace::write` changes.
+const FORMAT_VERSION: u64 = 1;
+
+/// What a single tick of a `MooMachine` did.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEntry {
+    pub program_counter: u64,
+    pub command: Command,
+    pub result: Result<Status, VmError>,
+    /// `(register, value)` for every register the instruction wrote.
+    pub register_writes: Vec<(u64, u64)>,
+    /// `(channel, value)` for every value taken from an input's source. A blocked
+    /// instruction may take some inputs that the tick that finishes it then reuses,
+    /// in which case they are only listed on the blocked tick.
+    pub inputs: Vec<(u64, u64)>,
+    /// `(channel, value)` for every value put into an output.
+    pub outputs: Vec<(u64, u64)>,
+}
+
+/// A recording of the ticks of a `MooMachine`, started with
+/// `MooMachine::start_trace`. Ticks after the machine has halted aren't recorded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trace {
+    input_channels: usize,
+    output_channels: usize,
+    entries: Vec<TraceEntry>,
+}
+
+impl Trace {
+    /// An empty trace for a machine with these many channels, which can be added to
+    /// a machine as an observer.
+    pub fn new(input_channels: usize, output_channels: usize) -> Self {
+        Trace {
+            input_channels,
+            output_channels,
+            entries: Vec::new(),
+        }
+    }
+
+    fn current(&mut self) -> &mut TraceEntry {
+        self.entries.last_mut().expect("nothing is being traced")
+    }
+
+    pub fn entries(&self) -> &[TraceEntry] {
+        &self.entries
+    }
+
+    /// Removes and returns the latest entry.
+    pub fn pop(&mut self) -> Option<TraceEntry> {
+        self.entries.pop()
+    }
+
+    /// Every value the traced machine took from input `channel`, in order.
+    pub fn inputs(&self, channel: u64) -> Vec<u64> {
+        self.entries
+            .iter()
+            .flat_map(|entry| &entry.inputs)
+            .filter(|&&(input, _)| input == channel)
+            .map(|&(_, value)| value)
+            .collect()
+    }
+
+    /// Runs `program` on a new machine fed with the recorded inputs and checks that
+    /// it does exactly what the traced machine did. Returns the trace of the replay.
+    ///
+    /// The replayed machine never has to wait for its inputs or outputs, so ticks
+    /// on which the traced machine was blocked are left out of the comparison.
+    pub fn replay(&self, program: Program) -> Result<Trace, Divergence> {
+        let inputs = (0..self.input_channels as u64)
+            .map(|channel| Box::new(self.inputs(channel)) as BoxedSource)
+            .collect();
+        let outputs = (0..self.output_channels)
+            .map(|_| Box::new(Vec::<u64>::new()) as BoxedSink)
+            .collect();
+        let mut machine = MooMachine::new(program, inputs, outputs);
+        machine.start_trace();
+
+        let expected = finished_ticks(&self.entries);
+        for _ in 0..expected.len() {
+            if machine.tick() == Ok(Status::Halted) {
+                break;
+            }
+        }
+        let replayed = machine.take_trace().unwrap();
+        let actual = finished_ticks(&replayed.entries);
+        for index in 0..expected.len().max(actual.len()) {
+            if expected.get(index) != actual.get(index) {
+                return Err(Divergence {
+                    tick: index,
+                    expected: expected.get(index).cloned().map(Box::new),
+                    actual: actual.get(index).cloned().map(Box::new),
+                });
+            }
+        }
+        Ok(replayed)
+    }
+
+    /// Writes the trace in a compact binary format. Commands aren't written as they
+    /// can be looked up from the program when the trace is read.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        for &word in &[FORMAT_VERSION, self.input_channels as u64, self.output_channels as u64] {
+            write_varint(&mut bytes, word);
+        }
+        for entry in &self.entries {
+            write_varint(&mut bytes, entry.program_counter);
+            let (result, channel) = match entry.result {
+                Ok(Status::Running) => (0, 0),
+                Ok(Status::Halted) => (1, 0),
+                Ok(Status::Blocked { channel: Channel::Input(channel) }) => (2, channel),
+                Ok(Status::Blocked { channel: Channel::Output(channel) }) => (3, channel),
+                Err(VmError::InputClosed(channel)) => (4, channel),
+                Err(VmError::OutputClosed(channel)) => (5, channel),
+                Err(VmError::DivisionByZero) => (6, 0),
+                Err(VmError::LoadedNan) => (7, 0),
+                Err(VmError::LoadedInfinity) => (8, 0),
+                Err(VmError::JumpWithoutCompare) => (9, 0),
+                Err(VmError::ReadOutput(channel)) => (10, channel),
+                Err(VmError::InvalidDestination) => (11, 0),
+                // The label is found from the command when the trace is read.
+                Err(VmError::UnknownLabel(_)) => (12, 0),
+                Err(VmError::NoInput(channel)) => (13, channel),
+                Err(VmError::NoOutput(channel)) => (14, channel),
+            };
+            write_varint(&mut bytes, result);
+            write_varint(&mut bytes, channel);
+            for pairs in &[&entry.register_writes, &entry.inputs, &entry.outputs] {
+                write_varint(&mut bytes, pairs.len() as u64);
+                for &(key, value) in pairs.iter() {
+                    write_varint(&mut bytes, key);
+                    write_varint(&mut bytes, value);
+                }
+            }
+        }
+        writer.write_all(&bytes)
+    }
+
+    /// Reads a trace written by `write` for a machine that ran `program`.
+    pub fn read<R: Read>(mut reader: R, program: &Program) -> io::Result<Trace> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let mut words = Words { bytes: &bytes, position: 0 };
+
+        let version = words.next()?;
+        if version != FORMAT_VERSION {
+            return Err(invalid_data(format!("unsupported trace version {}", version)));
+        }
+        let mut trace = Trace::new(words.next()? as usize, words.next()? as usize);
+        while !words.is_empty() {
+            let program_counter = words.next()?;
+            let command = match program.commands().get(program_counter as usize) {
+                Some(command) => command.clone(),
+                None => return Err(invalid_data(format!("{} is not an address in the program", program_counter))),
+            };
+            let (result, channel) = (words.next()?, words.next()?);
+            let result = match result {
+                0 => Ok(Status::Running),
+                1 => Ok(Status::Halted),
+                2 => Ok(Status::Blocked { channel: Channel::Input(channel) }),
+                3 => Ok(Status::Blocked { channel: Channel::Output(channel) }),
+                4 => Err(VmError::InputClosed(channel)),
+                5 => Err(VmError::OutputClosed(channel)),
+                6 => Err(VmError::DivisionByZero),
+                7 => Err(VmError::LoadedNan),
+                8 => Err(VmError::LoadedInfinity),
+                9 => Err(VmError::JumpWithoutCompare),
+                10 => Err(VmError::ReadOutput(channel)),
+                11 => Err(VmError::InvalidDestination),
+                12 => match command.label() {
+                    Some(label) => Err(VmError::UnknownLabel(label.to_string())),
+                    None => return Err(invalid_data(format!("{} at {} doesn't jump", command, program_counter))),
+                },
+                13 => Err(VmError::NoInput(channel)),
+                14 => Err(VmError::NoOutput(channel)),
+                other => return Err(invalid_data(format!("{} is not a valid tick result", other))),
+            };
+            trace.entries.push(TraceEntry {
+                program_counter,
+                command,
+                result,
+                register_writes: words.pairs()?,
+                inputs: words.pairs()?,
+                outputs: words.pairs()?,
+            });
+        }
+        Ok(trace)
+    }
+}
+
+/// The machine tells its trace about every tick like it tells its observers.
+impl MachineObserver for Trace {
+    fn before_instruction(&mut self, address: u64, command: &Command) {
+        self.entries.push(TraceEntry {
+            program_counter: address,
+            command: command.clone(),
+            result: Ok(Status::Running),
+            register_writes: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        });
+    }
+
+    fn after_instruction(&mut self, _address: u64, status: Status) {
+        self.current().result = Ok(status);
+    }
+
+    fn register_written(&mut self, register: u64, value: u64) {
+        self.current().register_writes.push((register, value));
+    }
+
+    fn input_consumed(&mut self, channel: u64, value: u64) {
+        self.current().inputs.push((channel, value));
+    }
+
+    fn output_produced(&mut self, channel: u64, value: u64) {
+        self.current().outputs.push((channel, value));
+    }
+
+    fn error(&mut self, _address: u64, error: &VmError) {
+        self.current().result = Err(error.clone());
+    }
+}
+
+/// Where a replay stopped matching its trace.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    /// The index of the tick among the ticks that weren't blocked.
+    pub tick: usize,
+    /// What the trace recorded. `None` if the replay ran longer.
+    pub expected: Option<Box<TraceEntry>>,
+    /// What the replay did. `None` if the replay halted early.
+    pub actual: Option<Box<TraceEntry>>,
+}
+
+/// Leaves out blocked ticks, moving the inputs they took to the tick that
+/// finished the instruction.
+fn finished_ticks(entries: &[TraceEntry]) -> Vec<TraceEntry> {
+    let mut finished = Vec::new();
+    let mut taken = Vec::new();
+    for entry in entries {
+        if let Ok(Status::Blocked { .. }) = entry.result {
+            taken.extend(&entry.inputs);
+            continue;
+        }
+        let mut entry = entry.clone();
+        taken.append(&mut entry.inputs);
+        entry.inputs = std::mem::take(&mut taken);
+        finished.push(entry);
+    }
+    finished
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut word: u64) {
+    while word >= 0x80 {
+        bytes.push(word as u8 | 0x80);
+        word >>= 7;
+    }
+    bytes.push(word as u8);
+}
+
+struct Words<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Words<'a> {
+    fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    fn next(&mut self) -> io::Result<u64> {
+        let mut word = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = match self.bytes.get(self.position) {
+                Some(&byte) => byte,
+                None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the trace ended in the middle of a number")),
+            };
+            self.position += 1;
+            word |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(word);
+            }
+        }
+        Err(invalid_data("a number in the trace is too long".to_string()))
+    }
+
+    fn pairs(&mut self) -> io::Result<Vec<(u64, u64)>> {
+        let count = self.next()?;
+        let mut pairs = Vec::new();
+        for _ in 0..count {
+            let key = self.next()?;
+            pairs.push((key, self.next()?));
+        }
+        Ok(pairs)
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -1,9 +1,14 @@
-use crate::common::{Sink, Source};
+use crate::common::{BroadcastSink, CollectSink, NumberFormat, Sink, TextSource};
 use crate::program::Program;
 use std::collections::HashMap;
 use std::mem::transmute;
+use std::sync::mpsc::{channel, sync_channel};
+use std::sync::{Arc, Mutex};
 
-use super::{Command, MooMachine, Param};
+use super::{
+    BoxedSource, Channel, Command, CompileError, CompiledMachine, CompiledProgram, MachineObserver, MachineState, MooMachine,
+    Param, RegisterCountError, Status, Trace, VmError, MAX_REGISTERS,
+};
 use crate::moo::parse_program_from_string;
 // impl MooMachine {
 //     fn get_program(&self) -> &Program {
@@ -37,7 +42,7 @@ fn fadd_test() {
         HashMap::new(),
     );
     let mut machine = MooMachine::new(program, Vec::new(), Vec::new());
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(
         *(machine.registers.get(&0).unwrap()), 
         3f64.to_bits(),
@@ -52,7 +57,7 @@ fn load_float_test() {
         machine.registers.get(&1), 
         None,    
     );
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(
         *machine.registers.get(&1).unwrap(), 
         3f64.to_bits(),
@@ -66,11 +71,11 @@ fn jump_test() {
     let mut machine = MooMachine::new(program, Vec::new(), Vec::new());
 
     assert_eq!(machine.program_counter, 0);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(machine.program_counter, 2);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(machine.program_counter, 1);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(machine.program_counter, 0);
 }
 
@@ -85,19 +90,20 @@ load 1f R0;"#;
     let mut machine = MooMachine::new(program, Vec::new(), Vec::new());
 
     assert_eq!(machine.registers.get(&0), None);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(f64::from_bits(*machine.registers.get(&0).unwrap()), 1. + 2.);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(f64::from_bits(*machine.registers.get(&0).unwrap()), 1. - 2.);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(f64::from_bits(*machine.registers.get(&0).unwrap()), 1. * 2.);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(f64::from_bits(*machine.registers.get(&0).unwrap()), 1. / 2.);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(f64::from_bits(*machine.registers.get(&0).unwrap()), 1.);
 }
 
 #[test]
+#[allow(clippy::identity_op, clippy::missing_transmute_annotations, unnecessary_transmutes)]
 fn arithmetic_operators_and_load_for_signed_integers_test() {
     let source = r#"iadd 1i 2i R0;
     isub 1i 2i R0
@@ -108,19 +114,20 @@ load 1i R0;"#;
     let mut machine = MooMachine::new(program, Vec::new(), Vec::new());
 
     assert_eq!(machine.registers.get(&0), None);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(unsafe{transmute::<_, i64>(*machine.registers.get(&0).unwrap())}, 1 + 2);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(unsafe{transmute::<_, i64>(*machine.registers.get(&0).unwrap())}, 1 - 2);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(unsafe{transmute::<_, i64>(*machine.registers.get(&0).unwrap())}, 1 * 2);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(unsafe{transmute::<_, i64>(*machine.registers.get(&0).unwrap())}, 1 / 2);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(unsafe{transmute::<_, i64>(*machine.registers.get(&0).unwrap())}, 1);
 }
 
 #[test]
+#[allow(clippy::identity_op)]
 fn arithmetic_operators_and_load_for_unsigned_test() {
     let source = r#"uadd 1u 2u R0;
     usub 1u 2u R0
@@ -131,16 +138,755 @@ load 1u R0;"#;
     let mut machine = MooMachine::new(program, Vec::new(), Vec::new());
 
     assert_eq!(machine.registers.get(&0), None);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(*machine.registers.get(&0).unwrap(), 1 + 2);
-    machine.tick();
+    machine.tick().unwrap();
     //TODO: I need to define what moofloom does when underflow and overflows happen. 
     //Currently it does what rust does but that's inconvenient and inconsistent.
     assert_eq!(*machine.registers.get(&0).unwrap(), 1u64.wrapping_sub(2));
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(*machine.registers.get(&0).unwrap(), 1 * 2);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(*machine.registers.get(&0).unwrap(), 1 / 2);
-    machine.tick();
+    machine.tick().unwrap();
     assert_eq!(*machine.registers.get(&0).unwrap(), 1);
-}
\ No newline at end of file
+}
+
+#[test]
+fn anonymous_label_loop_test() {
+    let source = r#"load 0u R0;
+    1: uadd R0 1u R0;
+    ucmp R0 3u;
+    jless 1b;"#;
+    let program = parse_program_from_string(source).unwrap();
+    let mut machine = MooMachine::new(program, Vec::new(), Vec::new());
+    for _ in 0..10 {
+        machine.tick().unwrap();
+    }
+    assert_eq!(*machine.registers.get(&0).unwrap(), 3);
+    assert_eq!(machine.program_counter, 4);
+}
+
+#[test]
+fn negative_jump_test() {
+    let source = "jfneg -1f 1f; load 1u R0; 1: jineg 1i 2f; load 2u R1; 2: load 3u R2;";
+    let program = parse_program_from_string(source).unwrap();
+    let mut machine = MooMachine::new(program, Vec::new(), Vec::new());
+    machine.tick().unwrap();
+    assert_eq!(machine.program_counter, 2);
+    machine.tick().unwrap();
+    assert_eq!(machine.program_counter, 3);
+}
+
+/// `jineg` used to read its operand as a float and `jfneg` as a signed integer.
+#[test]
+fn negative_jumps_read_their_own_domain_test() {
+    let source = "load -1i R0; load -0.5f R1; jineg R0 1f; load 1u R2; 1: jfneg R1 2f; load 2u R2; 2: load 3u R3;";
+    let program = parse_program_from_string(source).unwrap();
+    let mut machine = MooMachine::new(program, Vec::new(), Vec::new());
+    while machine.tick().unwrap() != Status::Halted {}
+    assert_eq!(machine.registers.get(&2), None);
+    assert_eq!(machine.registers.get(&3), Some(&3));
+}
+
+#[test]
+fn negative_jumps_convert_constants_test() {
+    let source = "jineg -1.5f 1f; load 1u R0; 1: jfneg -1i 2f; load 2u R0; 2: jineg 1u 3f; load 3u R1; 3: load 4u R2;";
+    let program = parse_program_from_string(source).unwrap();
+    let mut machine = MooMachine::new(program, Vec::new(), Vec::new());
+    while machine.tick().unwrap() != Status::Halted {}
+    assert_eq!(machine.registers.get(&0), None);
+    assert_eq!(machine.registers.get(&1), Some(&3));
+    assert_compiled_matches(source, Vec::new());
+}
+
+#[test]
+fn text_input_and_output_test() {
+    let program = parse_program_from_string("iadd I0 I0 R0; isub R0 I1 O0; load I1 O0;").unwrap();
+    let (sender, receiver) = channel();
+    let mut machine = MooMachine::new(
+        program,
+        vec![
+            Box::new(TextSource::new("2 3".as_bytes(), NumberFormat::Signed)),
+            Box::new(TextSource::new("7 -1".as_bytes(), NumberFormat::Signed)),
+        ],
+        vec![Box::new(sender)],
+    );
+    machine.tick().unwrap();
+    machine.tick().unwrap();
+    machine.tick().unwrap();
+    assert_eq!(receiver.try_recv().unwrap() as i64, -2);
+    assert_eq!(receiver.try_recv().unwrap() as i64, -1);
+}
+
+#[test]
+fn full_output_blocks_and_retries_test() {
+    let program = parse_program_from_string("uadd I0 I0 O0; load 9u R0;").unwrap();
+    let (sender, receiver) = sync_channel(1);
+    sender.send(0).unwrap();
+    let mut machine = MooMachine::new(
+        program,
+        vec![Box::new(vec![2u64, 5])],
+        vec![Box::new(sender)],
+    );
+    assert_eq!(
+        machine.tick(),
+        Ok(Status::Blocked { channel: Channel::Output(0) }),
+    );
+    assert_eq!(machine.program_counter, 0);
+    assert_eq!(receiver.recv().unwrap(), 0);
+    assert_eq!(machine.tick(), Ok(Status::Running));
+    assert_eq!(receiver.recv().unwrap(), 7);
+    assert_eq!(machine.program_counter, 1);
+}
+
+#[test]
+fn broadcast_output_test() {
+    let program = parse_program_from_string("load 1u O0; load 2u O0; load 3u O0;").unwrap();
+    let (sender, receiver) = channel();
+    let (full_sender, full_receiver) = sync_channel(1);
+    let output = BroadcastSink::new(vec![Box::new(sender), Box::new(full_sender)]);
+    let mut machine = MooMachine::new(program, Vec::new(), vec![Box::new(output)]);
+    assert_eq!(machine.tick(), Ok(Status::Running));
+    assert_eq!(machine.tick(), Ok(Status::Running));
+    assert_eq!(
+        machine.tick(),
+        Ok(Status::Blocked { channel: Channel::Output(0) }),
+    );
+    assert_eq!(full_receiver.recv().unwrap(), 1);
+    assert_eq!(machine.tick(), Ok(Status::Running));
+    assert_eq!(machine.tick(), Ok(Status::Halted));
+    assert_eq!(full_receiver.recv().unwrap(), 2);
+    machine.close_outputs();
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(full_receiver.try_iter().collect::<Vec<_>>(), vec![3]);
+}
+
+#[test]
+fn closed_output_test() {
+    let program = parse_program_from_string("load 1u O0;").unwrap();
+    let mut output = CollectSink::with_capacity(1);
+    output.close();
+    let mut machine = MooMachine::new(program, Vec::new(), vec![Box::new(output)]);
+    assert_eq!(machine.tick(), Err(VmError::OutputClosed(0)));
+    assert_eq!(machine.program_counter, 0);
+}
+
+#[test]
+fn pending_input_blocks_and_retries_test() {
+    let program = parse_program_from_string("usub I0 I1 R0; load 1u R1;").unwrap();
+    let (first_sender, first_receiver) = channel();
+    let (second_sender, second_receiver) = channel();
+    let mut machine = MooMachine::new(
+        program,
+        vec![Box::new(first_receiver), Box::new(second_receiver)],
+        Vec::new(),
+    );
+    assert_eq!(
+        machine.tick(),
+        Ok(Status::Blocked { channel: Channel::Input(0) }),
+    );
+    first_sender.send(10).unwrap();
+    first_sender.send(20).unwrap();
+    assert_eq!(
+        machine.tick(),
+        Ok(Status::Blocked { channel: Channel::Input(1) }),
+    );
+    assert_eq!(machine.program_counter, 0);
+    second_sender.send(3).unwrap();
+    assert_eq!(machine.tick(), Ok(Status::Running));
+    // The 10 taken before blocking on the second input is used, not the 20.
+    assert_eq!(*machine.registers.get(&0).unwrap(), 7);
+    assert_eq!(machine.program_counter, 1);
+}
+
+#[test]
+fn closed_input_test() {
+    let program = parse_program_from_string("load I0 R0;").unwrap();
+    let (sender, receiver) = channel::<u64>();
+    drop(sender);
+    let mut machine = MooMachine::new(program, vec![Box::new(receiver)], Vec::new());
+    assert_eq!(machine.tick(), Err(VmError::InputClosed(0)));
+
+    let program = parse_program_from_string("load I0 R0;").unwrap();
+    let mut machine = MooMachine::new(program, vec![Box::new(Vec::new())], Vec::new());
+    assert_eq!(machine.tick(), Err(VmError::InputClosed(0)));
+}
+
+#[test]
+fn runtime_errors_test() {
+    let errors = [
+        ("load 0u R0; udiv 1u R0 R1;", VmError::DivisionByZero),
+        ("load 0i R0; idiv 1i R0 R1;", VmError::DivisionByZero),
+        ("fdiv 1.5f 0.f R1;", VmError::DivisionByZero),
+        ("fadd I0 1.f R0;", VmError::LoadedNan),
+        ("fcmp I1 1.f;", VmError::LoadedInfinity),
+        ("jgre 1f; 1: load 1u R0;", VmError::JumpWithoutCompare),
+        ("jneq 1f; 1: load 1u R0;", VmError::JumpWithoutCompare),
+    ];
+    for (source, error) in &errors {
+        let program = parse_program_from_string(source).unwrap();
+        let inputs: Vec<BoxedSource> = vec![Box::new(vec![f64::NAN.to_bits()]), Box::new(vec![f64::INFINITY.to_bits()])];
+        let mut machine = MooMachine::new(program.clone(), inputs, Vec::new());
+        let status = loop {
+            match machine.tick() {
+                Ok(Status::Running) => {}
+                status => break status,
+            }
+        };
+        assert_eq!(status, Err(error.clone()), "{}", source);
+
+        let compiled = CompiledProgram::new(&program).unwrap();
+        let inputs: Vec<BoxedSource> = vec![Box::new(vec![f64::NAN.to_bits()]), Box::new(vec![f64::INFINITY.to_bits()])];
+        let mut machine = CompiledMachine::new(compiled, inputs, Vec::new());
+        assert_eq!(machine.run(10), Err(error.clone()), "{}", source);
+    }
+}
+
+#[test]
+fn invalid_program_errors_test() {
+    let programs = vec![
+        (parse_program_from_string("fcmp nanf 1f;").unwrap(), VmError::LoadedNan),
+        (parse_program_from_string("jump nowhere;").unwrap(), VmError::UnknownLabel("nowhere".to_string())),
+        (parse_program_from_string("load I1 R0;").unwrap(), VmError::NoInput(1)),
+        (parse_program_from_string("load 1u O2;").unwrap(), VmError::NoOutput(2)),
+        (
+            Program::new(vec![Command::Load(Param::Output(3), Param::Register(0))], HashMap::new()),
+            VmError::ReadOutput(3),
+        ),
+        (
+            Program::new(vec![Command::UAdd(Param::Output(4), Param::UConstant(1), Param::Register(0))], HashMap::new()),
+            VmError::ReadOutput(4),
+        ),
+        (
+            Program::new(vec![Command::FAdd(Param::FConstant(1.), Param::FConstant(1.), Param::Input(0))], HashMap::new()),
+            VmError::InvalidDestination,
+        ),
+        (
+            Program::new(vec![Command::Load(Param::UConstant(1), Param::IConstant(1))], HashMap::new()),
+            VmError::InvalidDestination,
+        ),
+    ];
+    for (program, error) in programs {
+        let mut machine = MooMachine::new(program.clone(), vec![Box::new(vec![1])], Vec::new());
+        machine.start_trace();
+        assert_eq!(machine.tick(), Err(error.clone()));
+
+        let trace = machine.take_trace().unwrap();
+        let mut written = Vec::new();
+        trace.write(&mut written).unwrap();
+        // The NaN constant never equals itself, so only the results are compared.
+        let read = Trace::read(written.as_slice(), &program).unwrap();
+        assert_eq!(read.entries()[0].result, Err(error));
+    }
+}
+
+#[test]
+fn halt_test() {
+    let program = parse_program_from_string("load 1u R0;").unwrap();
+    let mut machine = MooMachine::new(program, Vec::new(), Vec::new());
+    assert_eq!(machine.tick(), Ok(Status::Running));
+    assert_eq!(machine.tick(), Ok(Status::Halted));
+    assert_eq!(machine.tick(), Ok(Status::Halted));
+    assert_eq!(machine.program_counter, 1);
+}
+
+#[test]
+fn snapshot_and_restore_test() {
+    let program = "load 0u R0; 1: uadd R0 1u R0; ucmp R0 10u; jless 1b; load R0 O0;";
+    let mut machine = MooMachine::new(parse_program_from_string(program).unwrap(), Vec::new(), Vec::new());
+    for _ in 0..8 {
+        machine.tick().unwrap();
+    }
+    let state = machine.snapshot();
+    assert_eq!(state.program_counter, 2);
+    assert_eq!(state.registers.get(&0), Some(&3));
+    assert_eq!(state.compare, Some(std::cmp::Ordering::Less));
+
+    // Fork the computation into a machine that sees it through.
+    let (sender, receiver) = channel();
+    let mut fork = MooMachine::new(parse_program_from_string(program).unwrap(), Vec::new(), vec![Box::new(sender)]);
+    fork.restore(state.clone()).unwrap();
+    while fork.tick().unwrap() != Status::Halted {}
+    assert_eq!(receiver.try_recv(), Ok(10));
+
+    // The original machine is unaffected and can be rewound.
+    machine.tick().unwrap();
+    assert_ne!(machine.snapshot(), state);
+    machine.restore(state.clone()).unwrap();
+    assert_eq!(machine.snapshot(), state);
+}
+
+#[test]
+fn dense_registers_test() {
+    let program = parse_program_from_string("load 0u R0; 1: uadd R0 1u R0; ucmp R0 10u; jless 1b; load R0 R15;").unwrap();
+    let mut machine = MooMachine::new(program, Vec::new(), Vec::new()).with_register_count(16).unwrap();
+    assert_eq!(machine.registers.get(&0), None);
+    for _ in 0..5 {
+        machine.tick().unwrap();
+    }
+    let state = machine.snapshot();
+    assert_eq!(state.registers.get(&0), Some(&2));
+    assert_eq!(state.registers.get(&15), None);
+    while machine.tick().unwrap() != Status::Halted {}
+    assert_eq!(machine.register(15), 10);
+    assert_eq!(machine.register(3), 0);
+    assert_eq!(machine.registers.get(&3), None);
+
+    machine.restore(state.clone()).unwrap();
+    assert_eq!(machine.snapshot(), state);
+    assert_eq!(machine.register(15), 0);
+}
+
+#[test]
+fn restore_checks_registers_test() {
+    let program = "load 1u R3;";
+    let mut sparse = MooMachine::new(parse_program_from_string(program).unwrap(), Vec::new(), Vec::new());
+    sparse.registers.insert(20, 5);
+    sparse.tick().unwrap();
+    let state = sparse.snapshot();
+
+    let mut machine = MooMachine::new(parse_program_from_string(program).unwrap(), Vec::new(), Vec::new())
+        .with_register_count(16)
+        .unwrap();
+    machine.tick().unwrap();
+    let before = machine.snapshot();
+    assert_eq!(
+        machine.restore(state),
+        Err(RegisterCountError::OutOfRange { register: 20, count: 16 }),
+    );
+    assert_eq!(machine.snapshot(), before);
+}
+
+#[test]
+fn restore_forgets_redelivered_inputs_test() {
+    let program = "load I0 R0; load I0 R1;";
+    let mut machine = MooMachine::new(parse_program_from_string(program).unwrap(), vec![Box::new(vec![1, 2, 3])], Vec::new());
+    let state = machine.snapshot();
+    let undo = machine.undo_point();
+    machine.tick().unwrap();
+    machine.undo(undo, &[(0, 1)]);
+
+    machine.restore(state).unwrap();
+    machine.tick().unwrap();
+    assert_eq!(machine.register(0), 2);
+}
+
+#[test]
+fn too_few_registers_test() {
+    let program = parse_program_from_string("load 1u R16;").unwrap();
+    assert_eq!(
+        MooMachine::new(program, Vec::new(), Vec::new()).with_register_count(16).err(),
+        Some(RegisterCountError::OutOfRange { register: 16, count: 16 }),
+    );
+}
+
+#[test]
+fn too_many_registers_test() {
+    let program = parse_program_from_string("load 1u R0;").unwrap();
+    assert_eq!(
+        MooMachine::new(program.clone(), Vec::new(), Vec::new()).with_register_count(u64::MAX).err(),
+        Some(RegisterCountError::TooMany(u64::MAX)),
+    );
+    assert!(MooMachine::new(program, Vec::new(), Vec::new()).with_register_count(MAX_REGISTERS).is_ok());
+}
+
+#[test]
+fn snapshot_keeps_pending_inputs_test() {
+    let program = "uadd I0 I1 R0;";
+    let (first_sender, first_receiver) = channel();
+    let (_second_sender, second_receiver) = channel::<u64>();
+    let mut machine = MooMachine::new(
+        parse_program_from_string(program).unwrap(),
+        vec![Box::new(first_receiver), Box::new(second_receiver)],
+        Vec::new(),
+    );
+    first_sender.send(4).unwrap();
+    assert_eq!(machine.tick(), Ok(Status::Blocked { channel: Channel::Input(1) }));
+
+    let mut written = Vec::new();
+    machine.snapshot().write(&mut written).unwrap();
+    let state = MachineState::read(written.as_slice()).unwrap();
+    assert_eq!(state.pending_inputs, vec![4]);
+
+    // After a restart the value taken before blocking is used again.
+    let mut restarted = MooMachine::new(
+        parse_program_from_string(program).unwrap(),
+        vec![Box::new(Vec::new()), Box::new(vec![5])],
+        Vec::new(),
+    );
+    restarted.restore(state).unwrap();
+    assert_eq!(restarted.tick(), Ok(Status::Running));
+    assert_eq!(*restarted.registers.get(&0).unwrap(), 9);
+}
+
+#[test]
+fn read_invalid_state_test() {
+    let mut written = Vec::new();
+    MooMachine::new(Program::new(Vec::new(), HashMap::new()), Vec::new(), Vec::new())
+        .snapshot()
+        .write(&mut written)
+        .unwrap();
+    assert!(MachineState::read(written.as_slice()).is_ok());
+    assert_eq!(
+        MachineState::read(&written[..written.len() - 1]).unwrap_err().kind(),
+        std::io::ErrorKind::UnexpectedEof,
+    );
+    written[0] = 99;
+    assert_eq!(
+        MachineState::read(written.as_slice()).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData,
+    );
+}
+
+#[test]
+fn trace_test() {
+    let program = parse_program_from_string("1: uadd I0 R0 R0; load R0 O0; jump 1b;").unwrap();
+    let (sender, receiver) = channel();
+    let mut machine = MooMachine::new(program, vec![Box::new(vec![2, 3])], vec![Box::new(sender)]);
+    machine.tick().unwrap();
+    machine.start_trace();
+    while machine.tick().is_ok() {}
+    let trace = machine.take_trace().unwrap();
+
+    let entries = trace.entries();
+    assert_eq!(entries.len(), 6);
+    assert_eq!(entries[0].program_counter, 1);
+    assert_eq!(entries[0].command, Command::Load(Param::Register(0), Param::Output(0)));
+    assert_eq!(entries[0].outputs, vec![(0, 2)]);
+    assert_eq!(entries[2].inputs, vec![(0, 3)]);
+    assert_eq!(entries[2].register_writes, vec![(0, 5)]);
+    assert_eq!(entries[5].result, Err(VmError::InputClosed(0)));
+    assert_eq!(trace.inputs(0), vec![3]);
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![2, 5]);
+    // Nothing is recorded once tracing has stopped.
+    machine.tick().unwrap_err();
+    assert_eq!(machine.take_trace(), None);
+}
+
+#[test]
+fn trace_replay_test() {
+    let source = "1: iadd I0 I1 R0; load R0 O0; jump 1b;";
+    let (first_sender, first_receiver) = channel();
+    let (second_sender, second_receiver) = channel();
+    let mut machine = MooMachine::new(
+        parse_program_from_string(source).unwrap(),
+        vec![Box::new(first_receiver), Box::new(second_receiver)],
+        vec![Box::new(Vec::new())],
+    );
+    machine.start_trace();
+    first_sender.send(1).unwrap();
+    assert_eq!(machine.tick(), Ok(Status::Blocked { channel: Channel::Input(1) }));
+    second_sender.send(-5i64 as u64).unwrap();
+    for _ in 0..3 {
+        machine.tick().unwrap();
+    }
+    first_sender.send(7).unwrap();
+    second_sender.send(8).unwrap();
+    for _ in 0..2 {
+        machine.tick().unwrap();
+    }
+    let trace = machine.take_trace().unwrap();
+
+    let replayed = trace.replay(parse_program_from_string(source).unwrap()).unwrap();
+    assert_eq!(replayed.entries().len(), 5);
+    assert_eq!(replayed.entries()[0].inputs, vec![(0, 1), (1, -5i64 as u64)]);
+
+    // A different program is caught.
+    let divergence = trace
+        .replay(parse_program_from_string("1: iadd I0 I1 R0; load R0 O0; load R0 O0; jump 1b;").unwrap())
+        .unwrap_err();
+    assert_eq!(divergence.tick, 2);
+    assert_eq!(divergence.expected.unwrap().command, Command::Jump("1@0".to_string()));
+}
+
+#[test]
+fn trace_round_trip_test() {
+    let source = "1: fadd I0 0.5f R1; load R1 O0; ucmp R1 0u; jgre 1b;";
+    let mut machine = MooMachine::new(
+        parse_program_from_string(source).unwrap(),
+        vec![Box::new(vec![1f64.to_bits(), 2f64.to_bits()])],
+        vec![Box::new(CollectSink::with_capacity(1))],
+    );
+    machine.start_trace();
+    for _ in 0..6 {
+        machine.tick().unwrap();
+    }
+    let trace = machine.take_trace().unwrap();
+    assert_eq!(trace.entries()[5].result, Ok(Status::Blocked { channel: Channel::Output(0) }));
+
+    let program = parse_program_from_string(source).unwrap();
+    let mut written = Vec::new();
+    trace.write(&mut written).unwrap();
+    assert_eq!(Trace::read(written.as_slice(), &program).unwrap(), trace);
+    assert_eq!(
+        Trace::read(&written[..written.len() - 1], &program).unwrap_err().kind(),
+        std::io::ErrorKind::UnexpectedEof,
+    );
+    let short = parse_program_from_string("load 1u R0;").unwrap();
+    assert_eq!(
+        Trace::read(written.as_slice(), &short).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData,
+    );
+}
+
+#[test]
+fn command_display_test() {
+    let source = "1: fadd R0 -0.5f O1; load I2 R3; icmp -4i 7u; jfneg R0 1b;";
+    let program = parse_program_from_string(source).unwrap();
+    let written: Vec<_> = program.commands().iter().map(|command| command.to_string()).collect();
+    assert_eq!(written, vec!["fadd R0 -0.5f O1", "load I2 R3", "icmp -4i 7u", "jfneg R0 1@0"]);
+    assert_eq!(parse_program_from_string(&written[..3].join(";")).unwrap().commands(), &program.commands()[..3]);
+}
+
+#[derive(Default)]
+struct Recorder {
+    events: Vec<String>,
+}
+
+impl MachineObserver for Recorder {
+    fn before_instruction(&mut self, address: u64, command: &Command) {
+        self.events.push(format!("before {} {}", address, command));
+    }
+
+    fn after_instruction(&mut self, address: u64, status: Status) {
+        self.events.push(format!("after {} {:?}", address, status));
+    }
+
+    fn register_written(&mut self, register: u64, value: u64) {
+        self.events.push(format!("R{} = {}", register, value));
+    }
+
+    fn compare_changed(&mut self, compare: std::cmp::Ordering) {
+        self.events.push(format!("compare {:?}", compare));
+    }
+
+    fn jump_taken(&mut self, from: u64, to: u64) {
+        self.events.push(format!("jump {} -> {}", from, to));
+    }
+
+    fn input_consumed(&mut self, channel: u64, value: u64) {
+        self.events.push(format!("I{} -> {}", channel, value));
+    }
+
+    fn output_produced(&mut self, channel: u64, value: u64) {
+        self.events.push(format!("{} -> O{}", value, channel));
+    }
+
+    fn error(&mut self, address: u64, error: &VmError) {
+        self.events.push(format!("error {} {:?}", address, error));
+    }
+}
+
+#[test]
+fn observer_test() {
+    let program = parse_program_from_string("1: load I0 R0; ucmp R0 2u; jless 1b; load R0 O0; ucmp R0 2u;").unwrap();
+    let recorder = Arc::new(Mutex::new(Recorder::default()));
+    let mut machine = MooMachine::new(program, vec![Box::new(vec![1, 5])], vec![Box::new(Vec::new())]);
+    machine.add_observer(Box::new(recorder.clone()));
+    while machine.tick().unwrap() != Status::Halted {}
+    assert_eq!(
+        recorder.lock().unwrap().events,
+        vec![
+            "before 0 load I0 R0",
+            "I0 -> 1",
+            "R0 = 1",
+            "after 0 Running",
+            "before 1 ucmp R0 2u",
+            "compare Less",
+            "after 1 Running",
+            "before 2 jless 1@0",
+            "jump 2 -> 0",
+            "after 2 Running",
+            "before 0 load I0 R0",
+            "I0 -> 5",
+            "R0 = 5",
+            "after 0 Running",
+            "before 1 ucmp R0 2u",
+            "compare Greater",
+            "after 1 Running",
+            "before 2 jless 1@0",
+            "after 2 Running",
+            "before 3 load R0 O0",
+            "5 -> O0",
+            "after 3 Running",
+            // The compare flag didn't change.
+            "before 4 ucmp R0 2u",
+            "after 4 Running",
+        ],
+    );
+
+    assert_eq!(machine.take_observers().len(), 1);
+    assert!(machine.take_observers().is_empty());
+}
+
+#[test]
+fn observer_blocked_and_error_test() {
+    let program = parse_program_from_string("uadd I0 I1 R0;").unwrap();
+    let (sender, receiver) = channel();
+    let recorder = Arc::new(Mutex::new(Recorder::default()));
+    let mut machine = MooMachine::new(program, vec![Box::new(vec![1]), Box::new(receiver)], Vec::new());
+    machine.add_observer(Box::new(recorder.clone()));
+    machine.tick().unwrap();
+    drop(sender);
+    machine.tick().unwrap_err();
+    assert_eq!(
+        recorder.lock().unwrap().events,
+        vec![
+            "before 0 uadd I0 I1 R0",
+            "I0 -> 1",
+            "after 0 Blocked { channel: Input(1) }",
+            "before 0 uadd I0 I1 R0",
+            "error 0 InputClosed(1)",
+        ],
+    );
+}
+
+#[test]
+fn remove_observer_test() {
+    let program = parse_program_from_string("load 1u R0; load 2u R0;").unwrap();
+    let removed = Arc::new(Mutex::new(Recorder::default()));
+    let kept = Arc::new(Mutex::new(Recorder::default()));
+    let mut machine = MooMachine::new(program, Vec::new(), Vec::new());
+    let id = machine.add_observer(Box::new(removed.clone()));
+    machine.add_observer(Box::new(kept.clone()));
+    machine.tick().unwrap();
+    assert!(machine.remove_observer(id).is_some());
+    assert!(machine.remove_observer(id).is_none());
+    machine.tick().unwrap();
+    assert_eq!(removed.lock().unwrap().events.len(), 3);
+    assert_eq!(kept.lock().unwrap().events.len(), 6);
+}
+
+/// Runs `source` on a `MooMachine` and a `CompiledMachine` and checks they write the
+/// same outputs and registers.
+fn assert_compiled_matches(source: &str, input: Vec<u64>) {
+    let program = parse_program_from_string(source).unwrap();
+
+    let (sender, receiver) = channel();
+    let mut machine = MooMachine::new(program.clone(), vec![Box::new(input.clone())], vec![Box::new(sender)]);
+    while machine.tick().unwrap() != Status::Halted {}
+    let expected: Vec<u64> = receiver.try_iter().collect();
+
+    let (sender, receiver) = channel();
+    let mut compiled = CompiledMachine::new(
+        CompiledProgram::new(&program).unwrap(),
+        vec![Box::new(input)],
+        vec![Box::new(sender)],
+    );
+    assert_eq!(compiled.run(u64::MAX), Ok(Status::Halted));
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), expected, "{}", source);
+    assert_eq!(compiled.program_counter(), machine.program_counter());
+    assert_eq!(compiled.compare(), machine.compare());
+    for (register, value) in machine.registers.iter() {
+        assert_eq!(compiled.register(register), value, "R{} in {}", register, source);
+    }
+}
+
+#[test]
+fn compiled_matches_tick_test() {
+    let programs = [
+        "load 0u R0; load 1u R1; load I0 R2;
+         1: uadd R0 R1 R3; load R1 R0; load R3 R1; usub R2 1u R2; ucmp R2 0u; jgre 1b; load R0 O0;",
+        "load I0 R0; iadd R0 -5i R1; imul R1 3i R1; idiv R1 2i O0; jineg R1 1f; load 1u O0; jump 2f; 1: load 2u O0; 2: load R1 O0;",
+        "fadd 1.5f 2i R0; fmul R0 R0 R1; fdiv R1 4u R2; fsub R2 10.f O0; jfneg R2 1f; load 7u O0; 1: fcmp R2 R0; jless 1f; load 8u O0; 1: load R2 O0;",
+        "load I0 R0; 1: icmp R0 10i; jgre 2f; iadd R0 1i R0; load R0 O0; jneq 1b; 2: load -1i O0;",
+        "uadd I0 I0 O0; umul I0 3u R5; udiv R5 2u O0; load 2.5f R6; load R6 O0; load R9 O0;",
+    ];
+    for program in &programs {
+        for &input in &[1u64, 3, 7, 12] {
+            assert_compiled_matches(program, vec![input; 4]);
+        }
+    }
+}
+
+#[test]
+fn compiled_blocks_and_retries_test() {
+    let program = parse_program_from_string("usub I0 I1 R0; load R0 O0;").unwrap();
+    let (first_sender, first_receiver) = channel();
+    let (second_sender, second_receiver) = channel();
+    let (output_sender, output_receiver) = sync_channel(0);
+    let mut machine = CompiledMachine::new(
+        CompiledProgram::new(&program).unwrap(),
+        vec![Box::new(first_receiver), Box::new(second_receiver)],
+        vec![Box::new(output_sender)],
+    );
+    first_sender.send(10).unwrap();
+    first_sender.send(20).unwrap();
+    assert_eq!(machine.tick(), Ok(Status::Blocked { channel: Channel::Input(1) }));
+    assert_eq!(machine.program_counter(), 0);
+    second_sender.send(3).unwrap();
+    assert_eq!(machine.run(10), Ok(Status::Blocked { channel: Channel::Output(0) }));
+    // The 10 taken before blocking on the second input is used, not the 20.
+    assert_eq!(machine.register(0), 7);
+    assert_eq!(machine.program_counter(), 1);
+    drop(output_receiver);
+    assert_eq!(machine.tick(), Err(VmError::OutputClosed(0)));
+    assert_eq!(machine.program_counter(), 1);
+}
+
+#[test]
+fn compiled_closed_input_test() {
+    let program = parse_program_from_string("load 4u R0; load I0 R1;").unwrap();
+    let mut machine = CompiledMachine::new(CompiledProgram::new(&program).unwrap(), vec![Box::new(Vec::new())], Vec::new());
+    assert_eq!(machine.run(10), Err(VmError::InputClosed(0)));
+    assert_eq!(machine.register(0), 4);
+    assert_eq!(machine.program_counter(), 1);
+}
+
+#[test]
+fn compile_unknown_label_test() {
+    let program = Program::new(vec![Command::Jump("nowhere".to_string())], HashMap::new());
+    assert_eq!(
+        CompiledProgram::new(&program).err(),
+        Some(CompileError::UnknownLabel("nowhere".to_string())),
+    );
+}
+
+#[test]
+fn compile_invalid_params_test() {
+    let errors = [
+        (Command::Load(Param::Output(3), Param::Register(0)), CompileError::InvalidOperand(Param::Output(3))),
+        (
+            Command::ISub(Param::IConstant(1), Param::Output(1), Param::Register(0)),
+            CompileError::InvalidOperand(Param::Output(1)),
+        ),
+        (Command::JINeg(Param::Output(0), "1".to_string()), CompileError::InvalidOperand(Param::Output(0))),
+        (
+            Command::UAdd(Param::UConstant(1), Param::UConstant(1), Param::Input(0)),
+            CompileError::InvalidDestination(Param::Input(0)),
+        ),
+        (
+            Command::Load(Param::UConstant(1), Param::FConstant(2.)),
+            CompileError::InvalidDestination(Param::FConstant(2.)),
+        ),
+        (Command::FCmp(Param::Register(0), Param::FConstant(f64::NAN)), CompileError::NanCompare),
+    ];
+    for (command, error) in errors.iter().cloned() {
+        let labels = vec![("1".to_string(), 0)].into_iter().collect();
+        let program = Program::new(vec![command], labels);
+        assert_eq!(CompiledProgram::new(&program).err(), Some(error));
+    }
+
+    // A compare of an infinity is ordered, as in `MooMachine`.
+    let program = Program::new(vec![Command::FCmp(Param::FConstant(f64::INFINITY), Param::FConstant(1.))], HashMap::new());
+    assert!(CompiledProgram::new(&program).is_ok());
+}
+
+#[test]
+fn compiled_missing_channels_test() {
+    let program = parse_program_from_string("load I1 R0;").unwrap();
+    let mut machine = CompiledMachine::new(CompiledProgram::new(&program).unwrap(), vec![Box::new(vec![1])], Vec::new());
+    assert_eq!(machine.tick(), Err(VmError::NoInput(1)));
+
+    let program = parse_program_from_string("load 1u O0;").unwrap();
+    let mut machine = CompiledMachine::new(CompiledProgram::new(&program).unwrap(), Vec::new(), Vec::new());
+    assert_eq!(machine.tick(), Err(VmError::NoOutput(0)));
+}
+
+#[test]
+fn compiled_jump_without_compare_test() {
+    let program = parse_program_from_string("1: jeq 1b;").unwrap();
+    let mut machine = CompiledMachine::new(CompiledProgram::new(&program).unwrap(), Vec::new(), Vec::new());
+    assert_eq!(machine.tick(), Err(VmError::JumpWithoutCompare));
+}